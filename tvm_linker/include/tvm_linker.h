@@ -0,0 +1,44 @@
+/* Generated by cbindgen from src/ffi.rs, do not edit manually. Run `make header` to update. */
+
+#ifndef TVM_LINKER_H
+#define TVM_LINKER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Returns the error of the last failed call in the current thread or null.
+ * The string is owned by the library and is valid until the next failed call.
+ */
+const char *tvm_linker_last_error(void);
+
+/**
+ * Releases a string returned by the library.
+ */
+void tvm_linker_string_free(char *s);
+
+/**
+ * Disassembles code in the root cell of a bag of cells.
+ */
+char *tvm_linker_disassemble(const uint8_t *boc, size_t len);
+
+/**
+ * Computes the address of a contract from its tvc file contents.
+ * Returns the address in raw form `wc:hash`.
+ */
+char *tvm_linker_tvc_address(const uint8_t *tvc, size_t len, int8_t wc);
+
+/**
+ * Encodes a call of the ABI function into a message body and returns it as base64
+ * encoded bag of cells. `header` and `keypair` may be null. `keypair` is 64 bytes of
+ * the secret key followed by the public key.
+ */
+char *tvm_linker_encode_call(const char *abi_json,
+                             const char *method,
+                             const char *params,
+                             const char *header,
+                             const uint8_t *keypair,
+                             bool internal);
+
+#endif /* TVM_LINKER_H */
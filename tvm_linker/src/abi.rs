@@ -11,9 +11,13 @@
  * limitations under the License.
  */
 use abi_json::json_abi::{encode_function_call, decode_function_response};
-use abi_json::Contract;
+use abi_json::{Contract, Function, Param, ParamType};
+use clap::ArgMatches;
 use ed25519_dalek::Keypair;
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
 use ton_types::{BuilderData, SliceData};
 
 pub fn build_abi_body(
@@ -34,14 +38,36 @@ pub fn build_abi_body(
     ).map_err(|e| format!("cannot encode abi body: {:?}", e))
 }
 
+lazy_static! {
+    // ABI files are read and parsed once per process: scenarios and fuzzing use
+    // the same ABI for every call.
+    static ref ABI_FILES: Mutex<HashMap<String, (Option<SystemTime>, String)>> = Mutex::new(HashMap::new());
+    static ref ABI_CONTRACTS: Mutex<HashMap<Vec<u8>, Contract>> = Mutex::new(HashMap::new());
+}
+
+/// Reads ABI file contents. Contents are cached until modification time of the file changes.
 pub fn load_abi_json_string(abi_file: &str) -> Result<String, String> {
+    let modified = std::fs::metadata(abi_file).and_then(|m| m.modified()).ok();
+    if let Some((time, abi_json)) = ABI_FILES.lock().unwrap().get(abi_file) {
+        if modified.is_some() && *time == modified {
+            return Ok(abi_json.clone())
+        }
+    }
     let abi_json = std::fs::read_to_string(abi_file)
         .map_err(|e| format!("unable to read ABI file {}: {}", abi_file, e))?;
+    ABI_FILES.lock().unwrap().insert(abi_file.to_owned(), (modified, abi_json.clone()));
     Ok(abi_json)
 }
 
+/// Parses ABI json. Parsed contracts are cached by hash of the json.
 pub fn load_abi_contract(abi_json: &String) -> Result<Contract, String> {
-    Ok(Contract::load(abi_json.as_bytes()).map_err(|e| format!("cannot parse contract abi: {:?}", e))?)
+    let hash = Sha256::digest(abi_json.as_bytes()).to_vec();
+    if let Some(contract) = ABI_CONTRACTS.lock().unwrap().get(&hash) {
+        return Ok(contract.clone())
+    }
+    let contract = Contract::load(abi_json.as_bytes()).map_err(|e| format!("cannot parse contract abi: {:?}", e))?;
+    ABI_CONTRACTS.lock().unwrap().insert(hash, contract.clone());
+    Ok(contract)
 }
 
 pub fn decode_body(
@@ -59,6 +85,124 @@ pub fn decode_body(
     ).map_err(|e| format!("cannot decode abi body: {:?}", e))
 }
 
+/// Finds the method changing the public key of a contract: the given one or the only method
+/// with a single `uint256` argument and a name mentioning a key or an owner.
+/// Returns names of the method and of its argument.
+pub fn find_key_method(abi_file: &str, method: Option<&str>) -> Result<(String, String), String> {
+    let contract = load_abi_contract(&load_abi_json_string(abi_file)?)?;
+    let takes_key = |function: &Function| {
+        function.inputs.len() == 1 && function.inputs[0].kind == ParamType::Uint(256)
+    };
+    let mut candidates: Vec<&Function> = match method {
+        Some(name) => vec![contract.functions().get(name)
+            .ok_or(format!("method {} is not found in the ABI", name))?],
+        None => contract.functions().values()
+            .filter(|function| {
+                let name = function.name.to_lowercase();
+                (name.contains("key") || name.contains("owner")) && takes_key(function)
+            })
+            .collect(),
+    };
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+    match candidates.as_slice() {
+        [function] if takes_key(function) => Ok((function.name.clone(), function.inputs[0].name.clone())),
+        [function] => Err(format!("method {} must take a single uint256 argument", function.name)),
+        [] => Err("no method changing the key is found in the ABI, specify it with --abi-method".to_string()),
+        _ => Err(format!(
+            "several methods can change the key: {}, specify one with --abi-method",
+            candidates.iter().map(|function| function.name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+/// Change between two versions of a contract ABI.
+pub struct AbiChange {
+    pub message: String,
+    /// callers or decoders built for the old ABI don't work with the new one
+    pub breaking: bool,
+}
+
+fn params_signature(params: &[Param]) -> String {
+    params.iter()
+        .map(|param| format!("{}: {}", param.name, param.kind.type_signature()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Compares header, functions and events of two ABI versions. Removed and changed functions
+/// and events are breaking changes, added ones are not.
+pub fn diff_abi(old: &Contract, new: &Contract) -> Vec<AbiChange> {
+    let mut changes = vec![];
+    let mut change = |message: String, breaking: bool| changes.push(AbiChange { message, breaking });
+
+    if params_signature(old.header()) != params_signature(new.header()) {
+        change(format!("header changed: ({}) -> ({})",
+            params_signature(old.header()), params_signature(new.header())), true);
+    }
+
+    let mut names: Vec<&String> = old.functions().keys().chain(new.functions().keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        match (old.functions().get(name), new.functions().get(name)) {
+            (Some(_), None) => change(format!("function {} removed", name), true),
+            (None, Some(function)) => change(format!("function {} added ({})",
+                name, params_signature(&function.inputs)), false),
+            (Some(old), Some(new)) => {
+                let (old_inputs, new_inputs) = (params_signature(&old.inputs), params_signature(&new.inputs));
+                if old_inputs != new_inputs {
+                    change(format!("function {} inputs changed: ({}) -> ({})", name, old_inputs, new_inputs), true);
+                }
+                let (old_outputs, new_outputs) = (params_signature(&old.outputs), params_signature(&new.outputs));
+                if old_outputs != new_outputs {
+                    change(format!("function {} outputs changed: ({}) -> ({})", name, old_outputs, new_outputs), true);
+                }
+                if old.get_input_id() != new.get_input_id() {
+                    change(format!("function {} id changed: 0x{:08x} -> 0x{:08x}",
+                        name, old.get_input_id(), new.get_input_id()), true);
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    let mut names: Vec<&String> = old.events().keys().chain(new.events().keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        match (old.events().get(name), new.events().get(name)) {
+            (Some(_), None) => change(format!("event {} removed", name), true),
+            (None, Some(event)) => change(format!("event {} added ({})",
+                name, params_signature(&event.inputs)), false),
+            (Some(old), Some(new)) => {
+                let (old_inputs, new_inputs) = (params_signature(&old.inputs), params_signature(&new.inputs));
+                if old_inputs != new_inputs {
+                    change(format!("event {} changed: ({}) -> ({})", name, old_inputs, new_inputs), true);
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    changes
+}
+
+/// Prints changes between two versions of a contract ABI, breaking ones are marked.
+pub fn abi_diff_command(m: &ArgMatches) -> Result<(), String> {
+    let old = load_abi_contract(&load_abi_json_string(m.value_of("OLD").unwrap())?)?;
+    let new = load_abi_contract(&load_abi_json_string(m.value_of("NEW").unwrap())?)?;
+    let changes = diff_abi(&old, &new);
+    if changes.is_empty() {
+        println!("no changes");
+        return Ok(())
+    }
+    for change in &changes {
+        println!("{}: {}", if change.breaking { "breaking" } else { "compatible" }, change.message);
+    }
+    let breaking = changes.iter().filter(|change| change.breaking).count();
+    println!("{} changes, {} breaking", changes.len(), breaking);
+    Ok(())
+}
+
 pub fn gen_abi_id(mut abi: Option<Contract>, func_name: &str) -> u32 {
     if let Some(ref mut contract) = abi {
         let functions = contract.functions();
@@ -78,3 +222,28 @@ fn calc_func_id(func_interface: &str) -> u32 {
     id_bytes.copy_from_slice(&hasher.result()[..4]);
     u32::from_be_bytes(id_bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_abi() {
+        let old = Contract::load(r#"{"ABI version": 2, "header": ["time"], "functions": [
+            {"name": "transfer", "inputs": [{"name": "value", "type": "uint64"}], "outputs": []},
+            {"name": "owner", "inputs": [], "outputs": [{"name": "key", "type": "uint256"}]}
+        ], "events": [], "data": []}"#.as_bytes()).unwrap();
+        let new = Contract::load(r#"{"ABI version": 2, "header": ["time"], "functions": [
+            {"name": "transfer", "inputs": [{"name": "value", "type": "uint128"}], "outputs": []},
+            {"name": "balance", "inputs": [], "outputs": [{"name": "value", "type": "uint128"}]}
+        ], "events": [], "data": []}"#.as_bytes()).unwrap();
+        let changes: Vec<(String, bool)> = diff_abi(&old, &new).into_iter()
+            .map(|change| (change.message, change.breaking))
+            .collect();
+        assert_eq!(changes[0], ("function balance added ()".to_string(), false));
+        assert_eq!(changes[1], ("function owner removed".to_string(), true));
+        assert_eq!(changes[2], ("function transfer inputs changed: (value: uint64) -> (value: uint128)".to_string(), true));
+        assert!(changes[3].0.starts_with("function transfer id changed"));
+        assert_eq!(changes.len(), 4);
+    }
+}
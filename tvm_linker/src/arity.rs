@@ -0,0 +1,100 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use ton_labs_assembler::Lines;
+
+/// Number of arguments and, if known, results of a function declared by `.args`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Signature {
+    pub args: usize,
+    pub returns: Option<usize>,
+}
+
+/// Stack effect of simple instructions: number of values required and pushed.
+/// Control flow and unknown instructions return none.
+fn stack_effect(insn: &str, arg: Option<&str>) -> Option<(usize, usize)> {
+    let effect = match insn {
+        "PUSHINT" | "PUSHSLICE" | "PUSHREF" | "PUSHREFSLICE" | "NULL" | "PUSHNULL" | "TRUE" | "FALSE"
+            | "ZERO" | "ONE" | "TWO" | "TEN" | "NEWC" | "NOW" | "MYADDR" | "BALANCE" | "PUSHROOT" => (0, 1),
+        "PUSH" => {
+            // PUSH sN copies the value at depth N
+            let depth = arg?.trim().trim_start_matches('s').parse::<usize>().ok()?;
+            (depth + 1, depth + 2)
+        }
+        "DUP" => (1, 2),
+        "OVER" => (2, 3),
+        "DROP" => (1, 0),
+        "NIP" => (2, 1),
+        "SWAP" => (2, 2),
+        "INC" | "DEC" | "NEGATE" | "NOT" | "ADDCONST" | "MULCONST" | "CTOS" | "ENDC" | "ISNULL" => (1, 1),
+        "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "AND" | "OR" | "XOR" | "EQUAL" | "NEQ" | "LESS"
+            | "LEQ" | "GREATER" | "GEQ" | "MIN" | "MAX" => (2, 1),
+        _ => return None,
+    };
+    Some(effect)
+}
+
+/// Returns the name of the function called by `CALL $name$`.
+fn called_name(insn: &str, arg: Option<&str>) -> Option<String> {
+    if insn != "CALL" && insn != "CALLDICT" {
+        return None
+    }
+    let arg = arg?.trim();
+    if arg.len() > 2 && arg.starts_with('$') && arg.ends_with('$') {
+        Some(arg[1..arg.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+/// Follows the stack depth of the function body from its start, where the declared
+/// arguments are on the stack, and reports call sites of declared functions with fewer
+/// values on the stack than the callee takes. The check stops at the first instruction
+/// whose effect is unknown, so it only covers straight-line code.
+pub fn check_calls(body: &Lines, args: usize, signatures: &HashMap<String, Signature>) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut depth = args;
+    for line in body {
+        let text = line.text.split(';').next().unwrap().trim();
+        if text.is_empty() {
+            continue
+        }
+        let mut words = text.splitn(2, char::is_whitespace);
+        let insn = words.next().unwrap().to_uppercase();
+        let arg = words.next();
+        if let Some(name) = called_name(&insn, arg) {
+            let signature = match signatures.get(&name) {
+                Some(signature) => signature,
+                None => break,
+            };
+            if depth < signature.args {
+                warnings.push(format!(
+                    "{}:{}: {} takes {} arguments, the stack has {}",
+                    line.pos.filename, line.pos.line, name, signature.args, depth
+                ));
+                break
+            }
+            match signature.returns {
+                Some(returns) => depth = depth - signature.args + returns,
+                None => break,
+            }
+            continue
+        }
+        match stack_effect(&insn, arg) {
+            Some((required, pushed)) if required <= depth => depth = depth - required + pushed,
+            _ => break,
+        }
+    }
+    warnings
+}
@@ -0,0 +1,86 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use abi::load_abi_json_string;
+use abi_json::json_abi::encode_function_call;
+use clap::ArgMatches;
+use disasm::disasm::disasm;
+use parser::ParseEngine;
+use program::{load_from_file, Program};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use ton_types::SliceData;
+
+const DEFAULT_ITERATIONS: usize = 10;
+const DEFAULT_MESSAGES: usize = 1000;
+
+fn parse_count(matches: &ArgMatches, name: &str, default: usize) -> Result<usize, String> {
+    matches.value_of(name)
+        .map(|v| usize::from_str(v))
+        .transpose()
+        .map_err(|e| format!("cannot parse {}: {}", name.to_lowercase(), e))
+        .map(|v| v.unwrap_or(default))
+}
+
+fn measure<F>(name: &str, runs: usize, mut run: F) -> Result<(), String>
+    where F: FnMut() -> Result<(), String>
+{
+    let start = Instant::now();
+    for _ in 0..runs {
+        run()?;
+    }
+    report(name, runs, start.elapsed());
+    Ok(())
+}
+
+fn report(name: &str, runs: usize, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+    println!("{}: {} runs in {:.3} s, {:.3} ms/run, {:.1} runs/s",
+        name, runs, secs, secs * 1000.0 / runs as f64, runs as f64 / secs);
+}
+
+/// Times parsing and linking of sources, disassembling of a tvc file and encoding
+/// of ABI messages. Every operation is measured only if its inputs are given.
+pub fn bench_command(matches: &ArgMatches) -> Result<(), String> {
+    let iterations = parse_count(matches, "ITERATIONS", DEFAULT_ITERATIONS)?;
+    let messages = parse_count(matches, "MESSAGES", DEFAULT_MESSAGES)?;
+    let abi_json = matches.value_of("ABI_JSON").map(|file| load_abi_json_string(file)).transpose()?;
+
+    if let Some(source) = matches.value_of("SOURCE") {
+        let mut sources: Vec<&Path> = matches.values_of("LIB").unwrap_or_default().map(|lib| Path::new(lib)).collect();
+        sources.push(Path::new(source));
+        measure("parse+link", iterations, || {
+            let mut prog = Program::new(ParseEngine::new(sources.clone(), abi_json.clone(), false)?);
+            prog.compile_to_state().map(|_| ())
+        })?;
+    }
+
+    if let Some(tvc) = matches.value_of("TVC") {
+        let code = load_from_file(tvc)?.code.ok_or("tvc file has no code")?;
+        measure("disassemble", iterations, || {
+            disasm(&mut SliceData::from(code.clone()));
+            Ok(())
+        })?;
+    }
+
+    if let Some(method) = matches.value_of("METHOD") {
+        let abi_json = abi_json.ok_or("ABI is mandatory to encode messages")?;
+        let params = matches.value_of("PARAMS").unwrap_or("{}");
+        measure("encode", messages, || {
+            encode_function_call(abi_json.clone(), method.to_owned(), None, params.to_owned(), false, None)
+                .map(|_| ())
+                .map_err(|e| format!("cannot encode abi body: {:?}", e))
+        })?;
+    }
+    Ok(())
+}
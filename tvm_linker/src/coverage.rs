@@ -0,0 +1,191 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use clap::ArgMatches;
+use program::load_from_file;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+use testcall::load_debug_info;
+use ton_labs_assembler::DbgInfo;
+use ton_types::{Cell, UInt256};
+use ton_vm::executor::{EngineTraceInfo, EngineTraceInfoType};
+
+/// Hit counters of executed instructions: cell hash -> offset in cell -> count.
+/// Counters are accumulated in a file across several local runs.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Coverage {
+    hits: BTreeMap<String, BTreeMap<usize, u64>>,
+}
+
+impl Coverage {
+    pub fn load(filename: &str) -> Result<Self, String> {
+        if !Path::new(filename).exists() {
+            return Ok(Coverage::default())
+        }
+        let file = File::open(filename)
+            .map_err(|e| format!("Failed to open coverage file {}: {}", filename, e))?;
+        serde_json::from_reader(file)
+            .map_err(|e| format!("Failed to read coverage file {}: {}", filename, e))
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        let file = File::create(filename)
+            .map_err(|e| format!("Failed to create file {}: {}", filename, e))?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|e| format!("Failed to write data to file: {}", e))
+    }
+
+    pub fn record(&mut self, info: &EngineTraceInfo) {
+        if info.info_type != EngineTraceInfoType::Normal {
+            return
+        }
+        let hash = info.cmd_code.cell().repr_hash().to_hex_string();
+        *self.hits.entry(hash).or_default().entry(info.cmd_code.pos()).or_insert(0) += 1;
+    }
+
+    fn hits(&self, hash: &UInt256, offset: usize) -> u64 {
+        self.hits.get(&hash.to_hex_string())
+            .and_then(|offsets| offsets.get(&offset))
+            .cloned()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+struct LineStat {
+    instructions: usize,
+    executed: usize,
+    hits: u64,
+}
+
+fn collect_lines(
+    cell: &Cell,
+    debug_info: &DbgInfo,
+    coverage: &Coverage,
+    visited: &mut HashSet<UInt256>,
+    lines: &mut BTreeMap<String, BTreeMap<usize, LineStat>>,
+) {
+    let hash = cell.repr_hash();
+    if !visited.insert(hash.clone()) {
+        return
+    }
+    if let Some(offsets) = debug_info.get(&hash) {
+        for (offset, pos) in offsets {
+            let hits = coverage.hits(&hash, *offset);
+            let stat = lines.entry(pos.filename.clone()).or_default()
+                .entry(pos.line).or_default();
+            stat.instructions += 1;
+            stat.hits += hits;
+            if hits > 0 {
+                stat.executed += 1;
+            }
+        }
+    }
+    for i in 0..cell.references_count() {
+        collect_lines(&cell.reference(i).unwrap(), debug_info, coverage, visited, lines);
+    }
+}
+
+fn percent(executed: usize, total: usize) -> f64 {
+    if total == 0 { 100.0 } else { executed as f64 * 100.0 / total as f64 }
+}
+
+/// Splits assembler source into functions by their headers.
+/// Returns function names with line numbers of the headers (starting from 1).
+pub fn source_functions(source: &str) -> Vec<(String, usize)> {
+    lazy_static! {
+        static ref FUNC_RE: Regex = Regex::new(r"^\s*\.(globl|internal|macro)\s+(:?[\w\.:]+)").unwrap();
+        static ref SELECTOR_RE: Regex = Regex::new(r"^\s*\.selector").unwrap();
+    }
+    source.lines().enumerate().filter_map(|(i, text)| {
+        FUNC_RE.captures(text).map(|cap| cap.get(2).unwrap().as_str().to_owned())
+            .or_else(|| if SELECTOR_RE.is_match(text) { Some(".selector".to_owned()) } else { None })
+            .map(|name| (name, i + 1))
+    }).collect()
+}
+
+fn print_file_report(
+    filename: &str,
+    stats: &BTreeMap<usize, LineStat>,
+    source_dir: Option<&str>,
+    annotate: bool,
+) {
+    let total = stats.values().map(|s| s.instructions).sum();
+    let executed = stats.values().map(|s| s.executed).sum();
+    println!("{}: {}/{} instructions ({:.1}%)", filename, executed, total, percent(executed, total));
+
+    let path = Path::new(source_dir.unwrap_or(".")).join(filename);
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(_) => {
+            println!("  source file {:?} not found, per-function report is skipped", path);
+            return
+        }
+    };
+
+    // split source into functions by their headers
+    let headers = source_functions(&source);
+    let mut functions: Vec<(String, usize, usize)> = headers.iter()
+        .map(|(name, _)| (name.clone(), 0, 0))
+        .collect();
+    for (line, stat) in stats {
+        if let Some(index) = headers.iter().rposition(|(_, start)| start <= line) {
+            functions[index].1 += stat.instructions;
+            functions[index].2 += stat.executed;
+        }
+    }
+    for (name, total, executed) in functions.iter().filter(|f| f.1 > 0) {
+        println!("  {:40} {:>5}/{:<5} {:>6.1}%", name, executed, total, percent(*executed, *total));
+    }
+
+    if annotate {
+        println!();
+        for (i, text) in source.lines().enumerate() {
+            let count = match stats.get(&(i + 1)) {
+                Some(stat) if stat.executed == 0 => "#####".to_owned(),
+                Some(stat) => stat.hits.to_string(),
+                None => "-".to_owned(),
+            };
+            println!("{:>9}: {:5}: {}", count, i + 1, text);
+        }
+        println!();
+    }
+}
+
+pub fn coverage_command(matches: &ArgMatches) -> Result<(), String> {
+    let tvc = matches.value_of("TVC").unwrap();
+    let coverage_file = matches.value_of("COVERAGE").unwrap();
+    let debug_map = matches.value_of("DEBUG_MAP").unwrap();
+
+    let state = load_from_file(tvc)?;
+    let code = state.code.ok_or(format!("contract {} has no code", tvc))?;
+    let coverage = Coverage::load(coverage_file)?;
+    let debug_info = load_debug_info(debug_map.to_string())
+        .ok_or(format!("Failed to load debug map {}", debug_map))?;
+
+    let mut lines = BTreeMap::new();
+    collect_lines(&code, &debug_info, &coverage, &mut HashSet::new(), &mut lines);
+    if lines.is_empty() {
+        return Err("debug map doesn't describe the contract code".to_string())
+    }
+
+    let total = lines.values().flat_map(|f| f.values()).map(|s| s.instructions).sum();
+    let executed = lines.values().flat_map(|f| f.values()).map(|s| s.executed).sum();
+    println!("Total: {}/{} instructions ({:.1}%)", executed, total, percent(executed, total));
+    for (filename, stats) in &lines {
+        print_file_report(filename, stats, matches.value_of("SOURCE_DIR"), matches.is_present("ANNOTATE"));
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use abi::{load_abi_contract, load_abi_json_string};
+use std::collections::BTreeMap;
+use ton_types::{Cell, HashmapE, HashmapType, SliceData};
+
+const DATA_KEY_BITS: usize = 64;
+
+fn cell_bits(cell: &Cell) -> String {
+    format!("x{}", SliceData::from(cell.clone()).to_hex_string())
+}
+
+/// Compares two trees of cells skipping equal subtrees and collects changed cells
+/// with their paths from the root: `root.0.1` is the second reference of the first reference.
+fn diff_cells(path: &str, before: &Cell, after: &Cell, changes: &mut Vec<String>) {
+    if before.repr_hash() == after.repr_hash() {
+        return
+    }
+    let (bits_before, bits_after) = (cell_bits(before), cell_bits(after));
+    if bits_before != bits_after {
+        changes.push(format!("{}: {} -> {}", path, bits_before, bits_after));
+    }
+    let (refs_before, refs_after) = (before.references_count(), after.references_count());
+    for i in 0..std::cmp::max(refs_before, refs_after) {
+        let child_path = format!("{}.{}", path, i);
+        match (before.reference(i).ok(), after.reference(i).ok()) {
+            (Some(b), Some(a)) => diff_cells(&child_path, &b, &a, changes),
+            (Some(b), None) => changes.push(format!("{}: removed {}", child_path, b.repr_hash().to_hex_string())),
+            (None, Some(a)) => changes.push(format!("{}: added {}", child_path, a.repr_hash().to_hex_string())),
+            (None, None) => {}
+        }
+    }
+}
+
+/// Reads persistent data in the layout used with ABI `data` section:
+/// a dictionary with 64-bit keys stored as HashmapE in the root cell.
+fn read_data_dict(data: &Cell) -> Option<BTreeMap<u64, String>> {
+    let mut slice = SliceData::from(data.clone());
+    if !slice.get_next_bit().ok()? {
+        return Some(BTreeMap::new())
+    }
+    let dict = HashmapE::with_hashmap(DATA_KEY_BITS, Some(slice.checked_drain_reference().ok()?));
+    let mut values = BTreeMap::new();
+    for item in dict.iter() {
+        let (key, value) = item.ok()?;
+        let key = SliceData::from(key.into_cell().ok()?).get_next_u64().ok()?;
+        values.insert(key, format!("x{}", value.to_hex_string()));
+    }
+    Some(values)
+}
+
+fn diff_data_dict(before: &Cell, after: &Cell, abi_file: &str) -> Result<Option<Vec<String>>, String> {
+    let abi = load_abi_contract(&load_abi_json_string(abi_file)?)?;
+    let names: BTreeMap<u64, String> = abi.data().iter()
+        .map(|(name, item)| (item.key, name.clone()))
+        .collect();
+    let (before, after) = match (read_data_dict(before), read_data_dict(after)) {
+        (Some(before), Some(after)) => (before, after),
+        _ => return Ok(None),
+    };
+    let mut keys: Vec<&u64> = before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let mut changes = vec![];
+    for key in keys {
+        let name = names.get(key).cloned().unwrap_or(format!("key {}", key));
+        match (before.get(key), after.get(key)) {
+            (Some(b), Some(a)) if b != a => changes.push(format!("{}: {} -> {}", name, b, a)),
+            (Some(b), None) => changes.push(format!("{}: removed {}", name, b)),
+            (None, Some(a)) => changes.push(format!("{}: added {}", name, a)),
+            _ => {}
+        }
+    }
+    Ok(Some(changes))
+}
+
+/// Prints changes of the contract data after a local call. If ABI is given and data
+/// is a dictionary of ABI `data` items, changed items are printed by their names.
+pub fn print_data_diff(before: &Cell, after: &Cell, abi_file: Option<&str>) -> Result<(), String> {
+    println!("Data changes:");
+    if before.repr_hash() == after.repr_hash() {
+        println!("  no changes");
+        return Ok(())
+    }
+    println!("  hash: {} -> {}", before.repr_hash().to_hex_string(), after.repr_hash().to_hex_string());
+    let changes = match abi_file {
+        Some(abi_file) => diff_data_dict(before, after, abi_file)?,
+        None => None,
+    };
+    let changes = match changes {
+        Some(changes) => changes,
+        None => {
+            let mut changes = vec![];
+            diff_cells("root", before, after, &mut changes);
+            changes
+        }
+    };
+    for change in changes {
+        println!("  {}", change);
+    }
+    Ok(())
+}
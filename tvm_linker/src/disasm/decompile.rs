@@ -0,0 +1,425 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use super::loader::print_param;
+use super::types::{Code, Instruction, InstructionParameter};
+
+const INDENT: &str = "    ";
+
+/// Work left after the current instruction, run in the reverse order of queuing.
+type Task<'a> = Box<dyn FnOnce(&mut Decompiler<'a>) + 'a>;
+
+fn binary_operator(name: &str) -> Option<&'static str> {
+    let operator = match name {
+        "ADD" => "+",
+        "SUB" => "-",
+        "MUL" => "*",
+        "DIV" => "/",
+        "MOD" => "%",
+        "AND" => "&",
+        "OR" => "|",
+        "XOR" => "^",
+        "EQUAL" => "==",
+        "NEQ" => "!=",
+        "LESS" => "<",
+        "LEQ" => "<=",
+        "GREATER" => ">",
+        "GEQ" => ">=",
+        _ => return None,
+    };
+    Some(operator)
+}
+
+/// Instructions shown as calls: number of arguments and results and the name of the call.
+/// Calls without results are statements, calls with several results are assigned to temporaries.
+fn call_signature(name: &str) -> Option<(usize, usize, &'static str)> {
+    let signature = match name {
+        "NEWC" => (0, 1, "newc"),
+        "NOW" => (0, 1, "now"),
+        "MYADDR" => (0, 1, "my_address"),
+        "BALANCE" => (0, 1, "balance"),
+        "CTOS" => (1, 1, "ctos"),
+        "ENDC" => (1, 1, "endc"),
+        "HASHCU" => (1, 1, "cell_hash"),
+        "HASHSU" => (1, 1, "slice_hash"),
+        "SHA256U" => (1, 1, "sha256"),
+        "ISNULL" => (1, 1, "is_null"),
+        "CHKSIGNU" => (3, 1, "check_signature"),
+        "LDU" => (1, 2, "load_uint"),
+        "LDI" => (1, 2, "load_int"),
+        "LDREF" => (1, 2, "load_ref"),
+        "LDMSGADDR" => (1, 2, "load_address"),
+        "STU" => (2, 1, "store_uint"),
+        "STI" => (2, 1, "store_int"),
+        "STREF" => (2, 1, "store_ref"),
+        "STSLICE" => (2, 1, "store_slice"),
+        "DICTGET" | "DICTIGET" | "DICTUGET" | "DICTUGETREF" => (3, 2, "dict_get"),
+        "DICTSET" | "DICTISET" | "DICTUSET" | "DICTUSETREF" => (4, 1, "dict_set"),
+        "ACCEPT" => (0, 0, "accept"),
+        "SENDRAWMSG" => (2, 0, "send_raw_message"),
+        "SETCODE" => (1, 0, "set_code"),
+        _ => return None,
+    };
+    Some(signature)
+}
+
+/// Recovers expressions from the stack manipulations of the code and prints them as pseudo-code.
+/// Values are followed until an instruction with an unknown effect, the stack is printed
+/// as a comment then and the instruction is kept as is. Continuations are decompiled
+/// through the queue of tasks rather than recursively, so deep nesting is fine.
+struct Decompiler<'a> {
+    /// expressions of the tracked values, the top is the last
+    stack: Vec<String>,
+    /// values below the tracked ones are arguments until the first unknown instruction
+    arguments: bool,
+    args: usize,
+    temps: usize,
+    conts: Vec<&'a Code>,
+    out: String,
+    indent: String,
+    tasks: Vec<Task<'a>>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new() -> Self {
+        Decompiler {
+            stack: vec![],
+            arguments: true,
+            args: 0,
+            temps: 0,
+            conts: vec![],
+            out: String::new(),
+            indent: INDENT.to_string(),
+            tasks: vec![],
+        }
+    }
+
+    fn emit(&mut self, line: String) {
+        self.out += &format!("{}{}\n", self.indent, line);
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("v{}", self.temps)
+    }
+
+    /// Names values found below the tracked ones up to the depth.
+    fn reach(&mut self, depth: usize) {
+        while self.stack.len() <= depth {
+            let name = if self.arguments {
+                self.args += 1;
+                format!("arg{}", self.args - 1)
+            } else {
+                self.temp()
+            };
+            self.stack.insert(0, name);
+        }
+    }
+
+    fn pop(&mut self) -> String {
+        self.reach(0);
+        self.stack.pop().unwrap()
+    }
+
+    fn pop_args(&mut self, count: usize) -> Vec<String> {
+        let mut args: Vec<String> = (0..count).map(|_| self.pop()).collect();
+        args.reverse();
+        args
+    }
+
+    fn get(&mut self, depth: usize) -> String {
+        self.reach(depth);
+        self.stack[self.stack.len() - 1 - depth].clone()
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.reach(i.max(j));
+        let len = self.stack.len();
+        self.stack.swap(len - 1 - i, len - 1 - j);
+    }
+
+    fn cont(&mut self, code: &'a Code) -> String {
+        self.conts.push(code);
+        format!("cont{}", self.conts.len())
+    }
+
+    fn cont_code(&self, value: &str) -> Option<&'a Code> {
+        if !value.starts_with("cont") {
+            return None
+        }
+        value[4..].parse::<usize>().ok()
+            .and_then(|index| index.checked_sub(1))
+            .and_then(|index| self.conts.get(index))
+            .cloned()
+    }
+
+    /// Queues the task to run after the work queued later, e.g. after a continuation.
+    fn then(&mut self, task: impl FnOnce(&mut Self) + 'a) {
+        self.tasks.push(Box::new(task));
+    }
+
+    fn run(&mut self) {
+        while let Some(task) = self.tasks.pop() {
+            task(self);
+        }
+    }
+
+    /// Prints the header and queues the body of the continuation, the tasks queued
+    /// before run after the closing brace.
+    fn nested(&mut self, header: String, code: &'a Code) {
+        self.emit(format!("{} {{", header));
+        self.indent += INDENT;
+        self.then(|this| {
+            let len = this.indent.len() - INDENT.len();
+            this.indent.truncate(len);
+            this.emit("}".to_string());
+        });
+        self.code(code);
+    }
+
+    /// Prints the body of the continuation on the current stack, `then` gets the stack after it
+    /// while the current one is restored.
+    fn block(&mut self, header: String, value: &str, then: impl FnOnce(&mut Self, Vec<String>) + 'a) {
+        let saved = self.stack.clone();
+        self.then(move |this| {
+            let after = std::mem::replace(&mut this.stack, saved);
+            then(this, after);
+        });
+        match self.cont_code(value) {
+            Some(code) => self.nested(header, code),
+            None => self.emit(format!("{} {{ {}() }}", header, value)),
+        }
+    }
+
+    /// Continues with the stack left by branches if they agree on it.
+    fn join(&mut self, branches: Vec<Vec<String>>) {
+        if branches.iter().all(|stack| stack == &branches[0]) {
+            self.stack = branches[0].clone();
+        } else {
+            self.emit("// the stack differs between branches".to_string());
+            self.forget();
+        }
+    }
+
+    fn forget(&mut self) {
+        self.stack.clear();
+        self.arguments = false;
+    }
+
+    fn code(&mut self, code: &'a Code) {
+        for insn in code.iter().rev() {
+            self.then(move |this| this.insn(insn));
+        }
+    }
+
+    fn insn(&mut self, insn: &'a Instruction) {
+        let operands: Vec<String> = insn.params().iter().filter_map(|param| match param {
+            InstructionParameter::Code(_) => None,
+            param => Some(print_param(param)),
+        }).collect();
+        let register = insn.params().iter().filter_map(|param| match param {
+            InstructionParameter::StackRegister(i) => Some(*i as usize),
+            _ => None,
+        }).next();
+        let pair = insn.params().iter().filter_map(|param| match param {
+            InstructionParameter::StackRegisterPair(i, j) => Some((*i as usize, *j as usize)),
+            _ => None,
+        }).next();
+        let nested = insn.params().iter().filter_map(|param| match param {
+            InstructionParameter::Code(code) => Some(code),
+            _ => None,
+        }).next();
+        let name = insn.name();
+        if let Some(operator) = binary_operator(name) {
+            let args = self.pop_args(2);
+            self.stack.push(format!("({} {} {})", args[0], operator, args[1]));
+            return
+        }
+        if let Some((count, results, function)) = call_signature(name) {
+            let mut args = self.pop_args(count);
+            args.extend(operands);
+            let call = format!("{}({})", function, args.join(", "));
+            match results {
+                0 => self.emit(format!("{};", call)),
+                1 => self.stack.push(call),
+                _ => {
+                    let temps: Vec<String> = (0..results).map(|_| self.temp()).collect();
+                    self.emit(format!("let ({}) = {};", temps.join(", "), call));
+                    self.stack.extend(temps);
+                }
+            }
+            return
+        }
+        match (name, register, nested) {
+            ("PUSHINT", _, _) | ("PUSHSLICE", _, _) => self.stack.push(operands.join(", ")),
+            ("NULL", _, _) | ("PUSHNULL", _, _) => self.stack.push("null".to_string()),
+            ("TRUE", _, _) => self.stack.push("true".to_string()),
+            ("FALSE", _, _) => self.stack.push("false".to_string()),
+            ("PUSHCONT", _, Some(code)) | ("PUSHREFCONT", _, Some(code)) => {
+                let cont = self.cont(code);
+                self.stack.push(cont);
+            }
+            ("PUSH", Some(i), _) => {
+                let value = self.get(i);
+                self.stack.push(value);
+            }
+            ("POP", Some(i), _) => {
+                let value = self.pop();
+                if i > 0 {
+                    self.reach(i - 1);
+                    let len = self.stack.len();
+                    self.stack[len - i] = value;
+                }
+            }
+            ("XCHG", Some(i), _) => self.swap(0, i),
+            ("XCHG", None, _) if pair.is_some() => {
+                let (i, j) = pair.unwrap();
+                self.swap(i, j);
+            }
+            ("ROT", _, _) => {
+                self.swap(1, 2);
+                self.swap(0, 1);
+            }
+            ("ROTREV", _, _) => {
+                self.swap(0, 1);
+                self.swap(1, 2);
+            }
+            ("INC", _, _) => {
+                let value = self.pop();
+                self.stack.push(format!("({} + 1)", value));
+            }
+            ("DEC", _, _) => {
+                let value = self.pop();
+                self.stack.push(format!("({} - 1)", value));
+            }
+            ("NEGATE", _, _) => {
+                let value = self.pop();
+                self.stack.push(format!("-{}", value));
+            }
+            ("NOT", _, _) => {
+                let value = self.pop();
+                self.stack.push(format!("~{}", value));
+            }
+            ("ADDCONST", _, _) | ("MULCONST", _, _) | ("EQINT", _, _) => {
+                let operator = match name { "ADDCONST" => "+", "MULCONST" => "*", _ => "==" };
+                let value = self.pop();
+                self.stack.push(format!("({} {} {})", value, operator, operands.join("")));
+            }
+            ("PUSHCTR", _, _) => self.stack.push(operands.join("")),
+            ("POPCTR", _, _) => {
+                let value = self.pop();
+                self.emit(format!("{} = {};", operands.join(""), value));
+            }
+            ("GETGLOB", _, _) => self.stack.push(format!("g{}", operands.join(""))),
+            ("SETGLOB", _, _) => {
+                let value = self.pop();
+                self.emit(format!("g{} = {};", operands.join(""), value));
+            }
+            ("THROW", _, _) => self.emit(format!("throw {};", operands.join(""))),
+            ("THROWIF", _, _) => {
+                let condition = self.pop();
+                self.emit(format!("if ({}) throw {};", condition, operands.join("")));
+            }
+            ("THROWIFNOT", _, _) => {
+                let condition = self.pop();
+                self.emit(format!("require({}, {});", condition, operands.join("")));
+            }
+            ("IF", _, _) | ("IFNOT", _, _) | ("IFJMP", _, _) | ("IFNOTJMP", _, _) => {
+                let cont = self.pop();
+                let condition = self.pop();
+                let header = if name.starts_with("IFNOT") { format!("if (!{})", condition) } else { format!("if ({})", condition) };
+                self.block(header, &cont, move |this, after| {
+                    if name.ends_with("JMP") {
+                        // the branch does not return here
+                    } else {
+                        let before = this.stack.clone();
+                        this.join(vec![after, before]);
+                    }
+                });
+            }
+            ("IFELSE", _, _) => {
+                let otherwise = self.pop();
+                let then = self.pop();
+                let condition = self.pop();
+                self.block(format!("if ({})", condition), &then, move |this, first| {
+                    this.block("else".to_string(), &otherwise, move |this, second| this.join(vec![first, second]));
+                });
+            }
+            ("IFRET", _, _) | ("IFNOTRET", _, _) => {
+                let condition = self.pop();
+                let negation = if name == "IFNOTRET" { "!" } else { "" };
+                self.emit(format!("if ({}{}) return;", negation, condition));
+            }
+            ("CALLX", _, _) | ("JMPX", _, _) => {
+                let cont = self.pop();
+                self.block(if name == "CALLX" { "call" } else { "jump" }.to_string(), &cont, |this, after| this.stack = after);
+            }
+            ("REPEAT", _, _) => {
+                let cont = self.pop();
+                let count = self.pop();
+                self.block(format!("repeat ({})", count), &cont, |this, after| {
+                    let before = this.stack.clone();
+                    this.join(vec![after, before]);
+                });
+            }
+            ("RET", _, _) => {
+                let values = self.stack.join(", ");
+                self.emit(if values.is_empty() { "return;".to_string() } else { format!("return {};", values) });
+            }
+            _ => self.unknown(insn, operands, nested),
+        }
+    }
+
+    /// Prints the tracked stack and the instruction as is, values are not followed after it.
+    fn unknown(&mut self, insn: &'a Instruction, operands: Vec<String>, nested: Option<&'a Code>) {
+        let values = std::mem::replace(&mut self.stack, vec![]);
+        self.forget();
+        let conts: Vec<(String, &'a Code)> = values.iter()
+            .filter_map(|value| self.cont_code(value).map(|code| (value.clone(), code)))
+            .collect();
+        self.then(move |this| {
+            if !values.is_empty() {
+                this.emit(format!("// stack: {}", values.join(", ")));
+            }
+            let name = if insn.is_quiet() { format!("{}Q", insn.name()) } else { insn.name().to_string() };
+            let line = if operands.is_empty() { name } else { format!("{} {}", name, operands.join(", ")) };
+            this.then(|this| this.forget());
+            match nested {
+                Some(code) => this.nested(format!("asm(\"{}\")", line), code),
+                None => this.emit(format!("asm(\"{}\");", line)),
+            }
+        });
+        // continuations taken by the instruction are printed before it
+        for (value, code) in conts.into_iter().rev() {
+            self.then(move |this| {
+                this.then(|this| this.forget());
+                this.nested(format!("let {} =", value), code);
+            });
+        }
+    }
+}
+
+/// Decompiles the code of a function into pseudo-code: arithmetic and comparisons become
+/// expressions, conditions and exceptions become `if` and `require`, stores, loads and
+/// dictionary lookups become calls. Values found on the stack before the code are named
+/// `arg0`, `arg1` and so on from the top.
+pub(super) fn decompile(code: &Code) -> String {
+    let mut decompiler = Decompiler::new();
+    decompiler.code(code);
+    decompiler.run();
+    if !decompiler.stack.is_empty() {
+        let stack = decompiler.stack.join(", ");
+        decompiler.emit(format!("// stack: {}", stack));
+    }
+    decompiler.out
+}
@@ -0,0 +1,58 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{HashMap, HashSet};
+use ton_types::{Cell, CellImpl, UInt256};
+
+/// Identical cells stored in a bag of cells more than once.
+pub struct Duplicate {
+    pub hash: UInt256,
+    pub copies: usize,
+    pub bits: usize,
+    pub refs: usize,
+}
+
+/// Address of the cell object: every cell stored in the bag is deserialized once,
+/// references to it share the object, while its identical copies are separate objects.
+fn address(cell: &Cell) -> usize {
+    &**cell as *const dyn CellImpl as *const u8 as usize
+}
+
+/// Finds cells stored in the deserialized bag of cells several times instead of being shared.
+/// Identical cells have the same representation hash, so identical subtrees are found
+/// as duplicates of their every cell. Cells are listed in the order they are found from the roots.
+pub(super) fn find_duplicates(roots: &[Cell]) -> Vec<Duplicate> {
+    let mut visited = HashSet::new();
+    let mut copies: HashMap<UInt256, usize> = HashMap::new();
+    let mut order = vec![];
+    let mut stack: Vec<Cell> = roots.iter().rev().cloned().collect();
+    while let Some(cell) = stack.pop() {
+        if !visited.insert(address(&cell)) {
+            continue
+        }
+        let count = copies.entry(cell.repr_hash()).or_insert(0);
+        *count += 1;
+        if *count == 2 {
+            order.push(cell.clone());
+        }
+        for i in (0..cell.references_count()).rev() {
+            stack.push(cell.reference(i).unwrap());
+        }
+    }
+    order.into_iter().map(|cell| Duplicate {
+        copies: copies[&cell.repr_hash()],
+        hash: cell.repr_hash(),
+        bits: cell.bit_length(),
+        refs: cell.references_count(),
+    }).collect()
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use super::loader::instruction_text;
+
+enum Edit<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Lengths of the longest common subsequences of `a` and every prefix of `b`,
+/// computed row by row in linear space.
+fn lcs_lengths(a: &[&str], b: &[&str]) -> Vec<usize> {
+    let mut row = vec![0; b.len() + 1];
+    for line in a {
+        // the value of row[j] in the previous row
+        let mut diagonal = 0;
+        for j in 0..b.len() {
+            let up = row[j + 1];
+            row[j + 1] = if *line == b[j] { diagonal + 1 } else { up.max(row[j]) };
+            diagonal = up;
+        }
+    }
+    row
+}
+
+/// Hirschberg's algorithm: the first half of `a` is matched with the prefix of `b` giving
+/// the longest common subsequence together with the rest, then both halves are split again.
+fn align<'a>(a: &[&'a str], b: &[&'a str], edits: &mut Vec<Edit<'a>>) {
+    if a.is_empty() {
+        edits.extend(b.iter().map(|line| Edit::Added(*line)));
+        return
+    }
+    if b.is_empty() {
+        edits.extend(a.iter().map(|line| Edit::Removed(*line)));
+        return
+    }
+    if a.len() == 1 {
+        match b.iter().position(|line| *line == a[0]) {
+            Some(k) => {
+                edits.extend(b[..k].iter().map(|line| Edit::Added(*line)));
+                edits.push(Edit::Same(a[0]));
+                edits.extend(b[k + 1..].iter().map(|line| Edit::Added(*line)));
+            }
+            None => {
+                edits.push(Edit::Removed(a[0]));
+                edits.extend(b.iter().map(|line| Edit::Added(*line)));
+            }
+        }
+        return
+    }
+    let mid = a.len() / 2;
+    let head = lcs_lengths(&a[..mid], b);
+    let a_tail: Vec<&str> = a[mid..].iter().rev().cloned().collect();
+    let b_reversed: Vec<&str> = b.iter().rev().cloned().collect();
+    let tail = lcs_lengths(&a_tail, &b_reversed);
+    let split = (0..=b.len()).max_by_key(|j| head[*j] + tail[b.len() - j]).unwrap();
+    align(&a[..mid], &b[..split], edits);
+    align(&a[mid..], &b[split..], edits);
+}
+
+/// Matches lines of both texts by their longest common subsequence, common leading
+/// and trailing lines are matched first. Time is proportional to the product of the numbers
+/// of differing lines, memory to their sum.
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+    let mut aligned = vec![];
+    align(&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix], &mut aligned);
+
+    // removed lines of every changed run go first, so a replaced line is followed by its replacement
+    let mut edits: Vec<Edit> = old[..prefix].iter().map(|line| Edit::Same(*line)).collect();
+    let mut added = vec![];
+    for edit in aligned {
+        match edit {
+            Edit::Added(line) => added.push(Edit::Added(line)),
+            Edit::Removed(line) => edits.push(Edit::Removed(line)),
+            Edit::Same(line) => {
+                edits.append(&mut added);
+                edits.push(Edit::Same(line));
+            }
+        }
+    }
+    edits.append(&mut added);
+    edits.extend(old[old.len() - suffix..].iter().map(|line| Edit::Same(*line)));
+    edits
+}
+
+/// The line without its operands: indentation and the instruction name.
+fn opcode(line: &str) -> (usize, &str) {
+    let indent = line.len() - line.trim_start().len();
+    (indent, instruction_text(line).split(' ').next().unwrap())
+}
+
+/// Prints the line by line difference of two disassembled texts: unchanged lines are prefixed
+/// with two spaces, removed ones with `-`, added ones with `+`. A removed instruction replaced
+/// by the same instruction at the same level with other operands is printed once with `~`
+/// and its old operands. The counts of changes follow the text.
+pub(super) fn diff_text(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let edits = edits(&old, &new);
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    let mut out = String::new();
+    let mut index = 0;
+    while index < edits.len() {
+        // a run of removed lines followed by a run of added ones is aligned pairwise
+        let removed_run = edits[index..].iter().take_while(|edit| match edit { Edit::Removed(_) => true, _ => false }).count();
+        let added_run = edits[index + removed_run..].iter().take_while(|edit| match edit { Edit::Added(_) => true, _ => false }).count();
+        if removed_run == 0 && added_run == 0 {
+            if let Edit::Same(line) = edits[index] {
+                out += &format!("  {}\n", line);
+            }
+            index += 1;
+            continue
+        }
+        let lines = |from: usize, count: usize| -> Vec<&str> {
+            edits[from..from + count].iter().map(|edit| match edit {
+                Edit::Same(line) | Edit::Removed(line) | Edit::Added(line) => *line,
+            }).collect()
+        };
+        let old_lines = lines(index, removed_run);
+        let new_lines = lines(index + removed_run, added_run);
+        let pairs = old_lines.len().min(new_lines.len());
+        let mut pending = vec![];
+        for k in 0..pairs {
+            if opcode(old_lines[k]) == opcode(new_lines[k]) {
+                out += &format!("~ {} ;; was {}\n", new_lines[k], instruction_text(old_lines[k]));
+                changed += 1;
+            } else {
+                out += &format!("- {}\n", old_lines[k]);
+                pending.push(new_lines[k]);
+                removed += 1;
+            }
+        }
+        for line in &old_lines[pairs..] {
+            out += &format!("- {}\n", line);
+            removed += 1;
+        }
+        for line in pending.iter().chain(&new_lines[pairs..]) {
+            out += &format!("+ {}\n", line);
+            added += 1;
+        }
+        index += removed_run + added_run;
+    }
+    out += &format!(";; {} added, {} removed, {} changed\n", added, removed, changed);
+    out
+}
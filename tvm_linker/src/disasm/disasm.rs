@@ -11,16 +11,27 @@
  * limitations under the License.
  */
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
-use ton_block::Serializable;
+use ton_block::{Account, Deserializable, Message, Serializable, StateInit};
 use clap::ArgMatches;
 use ton_types::cells_serialization::deserialize_cells_tree;
-use ton_types::{Cell, HashmapE, HashmapType, SliceData, UInt256};
-use std::io::Cursor;
+use ton_types::{Cell, CellType, HashmapE, HashmapType, SliceData, UInt256};
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use serde_json::{Map, Value};
+
+use abi::{load_abi_contract, load_abi_json_string};
 
 use super::types::Shape;
-use super::loader::{load, print_code};
+use super::dedup::find_duplicates;
+use super::diff::diff_text;
+use super::decompile::decompile;
+use super::xref::{annotate_calls, call_targets, xref_index};
+use super::lint::{lint, security_lint, throws_replay_error};
+use super::stats::{slice_usage, stats_report, FunctionStats};
+use super::loader::{debug_string, instruction_text, load, load_cell, print_code, print_code_annotated, print_param, Annotations};
+use super::types::{walk, Code, InstructionParameter};
 
 pub fn disasm_command(m: &ArgMatches) -> core::result::Result<(), String> {
     if let Some(m) = m.subcommand_matches("dump") {
@@ -29,30 +40,113 @@ pub fn disasm_command(m: &ArgMatches) -> core::result::Result<(), String> {
         return disasm_graphviz_command(m);
     } else if let Some(m) = m.subcommand_matches("text") {
         return disasm_text_command(m);
+    } else if let Some(m) = m.subcommand_matches("lint") {
+        return disasm_lint_command(m);
+    } else if let Some(m) = m.subcommand_matches("dedup") {
+        return disasm_dedup_command(m);
+    } else if let Some(m) = m.subcommand_matches("diff") {
+        return disasm_diff_command(m);
     }
     Err("unknown command".to_owned())
 }
 
+fn read_tvc(m: &ArgMatches) -> core::result::Result<Vec<Cell>, String> {
+    read_boc(m.value_of("TVC").unwrap())
+}
+
+/// Reads roots of the bag of cells from a tvc or boc file, the file may also hold it
+/// as a hex or base64 string, which can be given in place of the file name as well.
+/// A binary file is deserialized directly from the file without loading all its bytes in memory first.
+/// An input which is not a bag of cells and looks like a path is reported as a missing file.
+pub(super) fn read_boc(input: &str) -> core::result::Result<Vec<Cell>, String> {
+    if !Path::new(input).is_file() {
+        let roots = decode_boc(input.as_bytes().to_vec())
+            .and_then(|boc| deserialize_cells_tree(&mut Cursor::new(boc)).map_err(|e| e.to_string()));
+        return match roots {
+            Err(_) if looks_like_path(input) => Err(format!("file {} not found", input)),
+            roots => roots,
+        }
+    }
+    let file = std::fs::File::open(input)
+        .map_err(|e| format!(" failed to read tvc file: {}", e))?;
+    let mut reader = BufReader::new(file);
+    let binary = is_boc(reader.fill_buf().map_err(|e| format!(" failed to read tvc file: {}", e))?);
+    if binary {
+        return deserialize_cells_tree(&mut reader).map_err(|e| e.to_string())
+    }
+    let mut text = vec![];
+    reader.read_to_end(&mut text).map_err(|e| format!(" failed to read tvc file: {}", e))?;
+    deserialize_cells_tree(&mut Cursor::new(decode_boc(text)?)).map_err(|e| e.to_string())
+}
+
+/// Whether the string has a path separator or the extension of a tvc or boc file.
+fn looks_like_path(input: &str) -> bool {
+    input.contains('/') || input.contains(std::path::MAIN_SEPARATOR) || input.ends_with(".tvc") || input.ends_with(".boc")
+}
+
+/// Whether the bytes start with a magic of the bag of cells.
+fn is_boc(bytes: &[u8]) -> bool {
+    const MAGICS: [[u8; 4]; 3] = [[0xb5, 0xee, 0x9c, 0x72], [0x68, 0xff, 0x65, 0xf3], [0xac, 0xc3, 0xa7, 0x28]];
+    bytes.len() >= 4 && MAGICS.iter().any(|magic| bytes[..4] == magic[..])
+}
+
+/// Returns the bag of cells given as raw bytes or as a hex or base64 string.
+pub(super) fn decode_boc(bytes: Vec<u8>) -> core::result::Result<Vec<u8>, String> {
+    if is_boc(&bytes) {
+        return Ok(bytes)
+    }
+    let text: String = String::from_utf8(bytes)
+        .map_err(|_| "input is neither a bag of cells nor a string".to_string())?
+        .split_whitespace().collect();
+    if let Ok(bytes) = hex::decode(&text) {
+        return Ok(bytes)
+    }
+    base64::decode(&text).map_err(|_| "input is neither a bag of cells nor a hex or base64 string".to_string())
+}
+
+/// Reads the first root of the bag of cells and takes the code from it.
+fn read_code(input: &str) -> core::result::Result<Cell, String> {
+    let mut roots = read_boc(input)?;
+    if roots.is_empty() {
+        return Err("no root cells in the input".to_string())
+    }
+    Ok(extract_code(roots.remove(0)))
+}
+
+/// Takes the code from a state init, message or account, the root is returned
+/// as is if it is code with a known selector or nothing else matches.
+fn extract_code(root: Cell) -> Cell {
+    if recognize_selector(&root).is_ok() {
+        return root
+    }
+    let code = if let Ok(msg) = Message::construct_from_cell(root.clone()) {
+        msg.state_init().and_then(|state| state.code.clone())
+    } else if let Ok(account) = Account::construct_from_cell(root.clone()) {
+        account.state_init().and_then(|state| state.code.clone())
+    } else {
+        StateInit::construct_from_cell(root.clone()).ok().and_then(|state| state.code)
+    };
+    code.unwrap_or(root)
+}
+
 fn disasm_graphviz_command(m: &ArgMatches) -> core::result::Result<(), String> {
-    let filename = m.value_of("TVC");
-    let tvc = filename.map(|f| std::fs::read(f))
-        .transpose()
-        .map_err(|e| format!(" failed to read tvc file: {}", e))?
-        .unwrap();
-    let mut csor = Cursor::new(tvc);
-    let mut roots = deserialize_cells_tree(&mut csor).unwrap();
-    let root = roots.remove(0).reference(0).unwrap();
+    let root = read_code(m.value_of("TVC").unwrap())?;
     match m.value_of("METHOD") {
         Some(string) => {
             if string == "int" {
-                graphviz(&root.reference(1).unwrap())
+                graphviz(&root.reference(1).map_err(|e| e.to_string())?)
             } else if string == "ext" {
-                graphviz(&root.reference(2).unwrap())
+                graphviz(&root.reference(2).map_err(|e| e.to_string())?)
             } else if string == "ticktock" {
-                graphviz(&root.reference(3).unwrap())
+                graphviz(&root.reference(3).map_err(|e| e.to_string())?)
             } else {
-                let method_id = u32::from_str(string).map_err(|e| -> String { e.to_string() })?;
-                let dict_cell = root.reference(0).unwrap().reference(0).unwrap();
+                let method_id = match m.value_of("ABI") {
+                    Some(filename) if u32::from_str(string).is_err() => load_function_names(filename)?
+                        .into_iter().find(|(_, name)| name == string).map(|(id, _)| id)
+                        .ok_or(format!("function {} not found in the ABI", string))?,
+                    _ => u32::from_str(string).map_err(|e| -> String { e.to_string() })?,
+                };
+                let dict_cell = root.reference(0).and_then(|cell| cell.reference(0)).map_err(|e| e.to_string())?;
                 let dict = HashmapE::with_hashmap(32, Some(dict_cell));
                 if dict.len().is_err() {
                     return Err("empty internal methods dictionary".to_string())
@@ -85,24 +179,26 @@ fn data_pretty_printed(cell: &Cell) -> String {
     string
 }
 
-fn tree_walk_graphviz(cell: &Cell, visited: &mut HashSet<UInt256>) {
-    visited.insert(cell.repr_hash());
-    let cell_hash = cell.repr_hash().to_hex_string();
-    let cell_id = &cell_hash.as_str()[..8];
-    println!("  \"{}\" [label=<<table border=\"0\"><tr><td align=\"left\"><b>{}</b></td></tr>{}</table>>];",
-        cell_id, cell_id, data_pretty_printed(cell));
-    if cell.references_count() > 0 {
+fn tree_walk_graphviz(root: &Cell, visited: &mut HashSet<UInt256>) {
+    // an explicit stack instead of recursion, long chains of references would overflow
+    // the native one; children are pushed in reverse to be visited in order
+    let mut stack = vec![root.clone()];
+    while let Some(cell) = stack.pop() {
+        if !visited.insert(cell.repr_hash()) {
+            continue
+        }
+        let cell_hash = cell.repr_hash().to_hex_string();
+        let cell_id = &cell_hash.as_str()[..8];
+        println!("  \"{}\" [label=<<table border=\"0\"><tr><td align=\"left\"><b>{}</b></td></tr>{}</table>>];",
+            cell_id, cell_id, data_pretty_printed(&cell));
         for i in 0..cell.references_count() {
             let child = cell.reference(i).unwrap();
             let child_hash = child.repr_hash().to_hex_string();
             let child_id = &child_hash.as_str()[..8];
             println!("  \"{}\" -> \"{}\" [ taillabel=\"{}\"];", cell_id, child_id, i.to_string());
         }
-    }
-    for i in 0..cell.references_count() {
-        let child = cell.reference(i).unwrap();
-        if !visited.contains(&child.repr_hash()) {
-            tree_walk_graphviz(&child, visited);
+        for i in (0..cell.references_count()).rev() {
+            stack.push(cell.reference(i).unwrap());
         }
     }
 }
@@ -116,13 +212,7 @@ fn graphviz(cell: &Cell) {
 }
 
 fn disasm_dump_command(m: &ArgMatches) -> core::result::Result<(), String> {
-    let filename = m.value_of("TVC");
-    let tvc = filename.map(|f| std::fs::read(f))
-        .transpose()
-        .map_err(|e| format!(" failed to read tvc file: {}", e))?
-        .unwrap();
-    let mut csor = Cursor::new(tvc);
-    let roots = deserialize_cells_tree(&mut csor).map_err(|e| e.to_string())?;
+    let roots = read_tvc(m)?;
     if roots.len() == 0 {
         println!("empty");
     } else {
@@ -136,8 +226,49 @@ fn disasm_dump_command(m: &ArgMatches) -> core::result::Result<(), String> {
     Ok(())
 }
 
+fn disasm_dedup_command(m: &ArgMatches) -> core::result::Result<(), String> {
+    let roots = read_tvc(m)?;
+    let duplicates = find_duplicates(&roots);
+    let (mut cells, mut bits) = (0, 0);
+    for dup in &duplicates {
+        println!("cell {} is stored {} times: {} bits, {} refs", dup.hash.to_hex_string(), dup.copies, dup.bits, dup.refs);
+        cells += dup.copies - 1;
+        bits += (dup.copies - 1) * dup.bits;
+    }
+    println!("redundant copies: {} cells, {} bits", cells, bits);
+
+    if let Some(output) = m.value_of("OUTPUT") {
+        if roots.len() != 1 {
+            return Err(format!("expected one root cell, found {}", roots.len()))
+        }
+        // identical cells are serialized once
+        let shared = ton_types::serialize_toc(&roots[0]).map_err(|e| e.to_string())?;
+        std::fs::write(output, &shared)
+            .map_err(|e| format!("failed to write {}: {}", output, e))?;
+        println!("saved to {}: {} bytes", output, shared.len());
+    }
+    Ok(())
+}
+
+fn disasm_diff_command(m: &ArgMatches) -> core::result::Result<(), String> {
+    let mut texts = vec![];
+    for input in &["OLD", "NEW"] {
+        let code = read_code(m.value_of(input).unwrap())?;
+        // code without a known selector is compared as a single continuation
+        let text = match disasm_text(&code, &HashMap::new(), &HashMap::new(), Annotations::default(), false) {
+            Ok(text) => text,
+            Err(_) => disasm_ex(&mut SliceData::from(code))?,
+        };
+        texts.push(text);
+    }
+    print!("{}", diff_text(&texts[0], &texts[1]));
+    Ok(())
+}
+
 pub(super) fn print_tree_of_cells(toc: &Cell) {
-    fn print_tree_of_cells(cell: &Cell, prefix: String, last: bool) {
+    // depth first with an explicit stack, children are pushed in reverse to keep their order
+    let mut stack = vec![(toc.clone(), String::new(), true)];
+    while let Some((cell, prefix, last)) = stack.pop() {
         let indent = if last { "└ " } else { "├ " };
         let mut hex = cell.to_hex_string(true);
         if hex.len() > 0 {
@@ -156,33 +287,462 @@ pub(super) fn print_tree_of_cells(toc: &Cell) {
 
         let prefix_child = if last { "  " } else { "│ " };
         let prefix = prefix + prefix_child;
-        if cell.references_count() > 0 {
-            let last_child = cell.references_count() - 1;
-            for i in 0..cell.references_count() {
-                let child = cell.reference(i).unwrap();
-                print_tree_of_cells(&child, prefix.to_string(), i == last_child);
-            }
+        let count = cell.references_count();
+        for i in (0..count).rev() {
+            stack.push((cell.reference(i).unwrap(), prefix.clone(), i + 1 == count));
+        }
+    }
+}
+
+/// Renders the code cell DAG as an indented tree: each cell with its hash, bits and
+/// references, followed by instructions stored in the cell. A cell shared by several
+/// parents is expanded only once.
+pub(super) fn code_tree(root: &Cell) -> String {
+    let mut out = String::new();
+    let mut visited = HashSet::new();
+    // depth first with an explicit stack, children are pushed in reverse to keep their order
+    let mut stack = vec![(root.clone(), String::new(), true)];
+    while let Some((cell, prefix, last)) = stack.pop() {
+        let hash = cell.repr_hash().to_hex_string();
+        let indent = if last { "└ " } else { "├ " };
+        let indent_next = if last { "  " } else { "│ " };
+        if !visited.insert(cell.repr_hash()) {
+            out += &format!("{}{}{} (see above)\n", prefix, indent, &hash[..8]);
+            continue
+        }
+        out += &format!("{}{}{} bits: {}, refs: {}\n", prefix, indent, &hash[..8], cell.bit_length(), cell.references_count());
+        match load_cell(&mut SliceData::from(&cell)) {
+            Ok(code) => for insn in code {
+                let text = print_code(&vec![insn], "");
+                let mut lines = text.lines();
+                let first = lines.next().unwrap_or_default();
+                let more = if lines.next().is_some() { " … }" } else { "" };
+                out += &format!("{}{}  {}{}\n", prefix, indent_next, first, more);
+            },
+            Err(_) => out += &format!("{}{}  ;; not code: x{}\n", prefix, indent_next, cell.to_hex_string(true)),
+        }
+        let prefix = format!("{}{}", prefix, indent_next);
+        let count = cell.references_count();
+        for i in (0..count).rev() {
+            stack.push((cell.reference(i).unwrap(), prefix.clone(), i + 1 == count));
+        }
+    }
+    out
+}
+
+/// Checks whether the tree has pruned branches, library references or Merkle cells.
+fn has_exotic_cells(root: &Cell) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![root.clone()];
+    while let Some(cell) = stack.pop() {
+        if !visited.insert(cell.repr_hash()) {
+            continue
+        }
+        if cell.cell_type() != CellType::Ordinary {
+            return true
         }
+        stack.extend((0..cell.references_count()).map(|i| cell.reference(i).unwrap()));
     }
-    print_tree_of_cells(&toc, "".to_string(), true);
+    false
 }
 
-fn print_code_dict(cell: &Cell, key_size: usize) {
+/// Labels of transaction entry points which fun-c places in the functions dictionary
+/// under ids 0, -1 and -2.
+fn entry_label(key_size: usize, id: u64) -> Option<&'static str> {
+    let signed = if id >> (key_size - 1) == 1 { id as i64 - (1i64 << key_size) } else { id as i64 };
+    match signed {
+        0 => Some("internal entry"),
+        -1 => Some("external entry"),
+        -2 => Some("ticktock"),
+        _ => None,
+    }
+}
+
+/// Disassembled function of the contract.
+struct Function {
+    /// entry label, ABI name or hex id, used as a file name
+    name: String,
+    /// header of the function in the text
+    label: String,
+    /// id in the dictionary, used by calls
+    id: Option<isize>,
+    code: String,
+    insns: Code,
+    /// cells and bits taken by the function
+    usage: (usize, usize),
+}
+
+impl Function {
+    fn new(
+        name: String,
+        label: String,
+        id: Option<isize>,
+        slice: &SliceData,
+        names: &HashMap<u32, String>,
+        errors: &HashMap<isize, String>,
+        annotations: Annotations,
+    ) -> core::result::Result<Self, String> {
+        let insns = load(&mut slice.clone()).map_err(|e| format!("failed to disassemble {}: {}", label, e))?;
+        let code = print_code_annotated(&insns, "", annotations);
+        let code = annotate_function_ids(annotate_throws(code, errors), names);
+        Ok(Function { name, label, id, code, insns, usage: slice_usage(slice) })
+    }
+}
+
+fn code_dict_functions(
+    cell: &Cell,
+    key_size: usize,
+    entries: bool,
+    names: &HashMap<u32, String>,
+    errors: &HashMap<isize, String>,
+    annotations: Annotations,
+) -> core::result::Result<Option<Vec<Function>>, String> {
     let dict = HashmapE::with_hashmap(key_size, Some(cell.clone()));
-    if dict.len().is_err() {
-        println!("failed to recognize dictionary");
-        return
+    // a dictionary taken from a proof may have pruned branches, functions found before them are kept
+    let exotic = has_exotic_cells(cell);
+    if dict.len().is_err() && !exotic {
+        return Ok(None)
     }
-    for (key, slice) in dict.iter().map(|r| r.unwrap()) {
+    let mut functions = vec![];
+    for item in dict.iter() {
+        let (key, slice) = match item {
+            Ok(item) => item,
+            Err(e) => {
+                functions.push(Function {
+                    name: "unavailable".to_string(),
+                    label: format!("the rest of the dictionary is not available: {}", e),
+                    id: None,
+                    code: String::new(),
+                    insns: vec![],
+                    usage: (0, 0),
+                });
+                break
+            }
+        };
         let cell = key.into_cell().unwrap();
         let id = SliceData::from(cell).get_next_int(key_size).unwrap();
-        println!("");
-        println!(";; function id 0x{:x}", id);
-        print!("{}", disasm(&mut slice.clone()));
+        let name = names.get(&(id as u32)).filter(|_| key_size == 32);
+        let (name, label, id) = match (entry_label(key_size, id).filter(|_| entries), name) {
+            (Some(label), _) => (label.replace(' ', "_"), label.to_string(), None),
+            (None, Some(name)) => (name.clone(), format!("function id 0x{:x} ({})", id, name), Some(id as isize)),
+            (None, None) => (format!("0x{:x}", id), format!("function id 0x{:x}", id), Some(id as isize)),
+        };
+        functions.push(Function::new(name, label, id, &slice, names, errors, annotations)?);
     }
+    Ok(Some(functions))
+}
+
+/// Prints functions of the code dictionary and moves them to the list.
+fn print_code_dict(
+    cell: &Cell,
+    key_size: usize,
+    entries: bool,
+    names: &HashMap<u32, String>,
+    errors: &HashMap<isize, String>,
+    annotations: Annotations,
+    functions: &mut Vec<Function>,
+) -> core::result::Result<String, String> {
+    Ok(match code_dict_functions(cell, key_size, entries, names, errors, annotations)? {
+        Some(mut found) => {
+            let text = found.iter()
+                .map(|function| format!("\n;; {}\n{}", function.label, function.code))
+                .collect();
+            functions.append(&mut found);
+            text
+        }
+        None => "failed to recognize dictionary\n".to_string(),
+    })
+}
+
+/// Solidity throws exception 52 for external messages if their timestamp is not newer
+/// than the last one accepted, the check may be done in a function called by the entry.
+fn has_replay_protection(code: &str) -> bool {
+    code.lines().any(|line| throws_replay_error(instruction_text(line)))
+}
+
+/// Loads names of error codes from the `errors` section of an ABI file
+/// (`[{"name": "NotOwner", "code": 101}]`) or from a json object `{"101": "NotOwner"}`.
+fn load_error_names(filename: &str) -> core::result::Result<HashMap<isize, String>, String> {
+    let text = std::fs::read_to_string(filename)
+        .map_err(|e| format!("failed to read file {}: {}", filename, e))?;
+    let json: serde_json::Value = serde_json::from_str(&text)
+        .map_err(|e| format!("failed to parse file {}: {}", filename, e))?;
+    let mut names = HashMap::new();
+    match json.get("errors") {
+        Some(serde_json::Value::Array(errors)) => for error in errors {
+            if let (Some(code), Some(name)) = (error["code"].as_i64(), error["name"].as_str()) {
+                names.insert(code as isize, name.to_owned());
+            }
+        },
+        _ => for (code, name) in json.as_object().into_iter().flatten() {
+            if let (Ok(code), Some(name)) = (isize::from_str(code), name.as_str()) {
+                names.insert(code, name.to_owned());
+            }
+        },
+    }
+    Ok(names)
+}
+
+/// Maps ids of public functions to their names from the ABI file.
+fn load_function_names(filename: &str) -> core::result::Result<HashMap<u32, String>, String> {
+    let contract = load_abi_contract(&load_abi_json_string(filename)?)?;
+    Ok(contract.functions().values().map(|function| (function.get_input_id(), function.name.clone())).collect())
+}
+
+/// Appends names of public functions to PUSHINT instructions with their ids,
+/// ids of answers have the highest bit set.
+pub(super) fn annotate_function_ids(code: String, names: &HashMap<u32, String>) -> String {
+    if names.is_empty() {
+        return code
+    }
+    code.lines().map(|line| {
+        let mut words = instruction_text(line).split(' ');
+        let name = match (words.next(), words.next(), words.next()) {
+            (Some("PUSHINT"), Some(arg), None) => u32::from_str(arg).ok().and_then(|id| {
+                match names.get(&id) {
+                    Some(name) => Some(name.clone()),
+                    None => names.get(&(id & 0x7fffffff)).filter(|_| id >> 31 == 1)
+                        .map(|name| format!("{} answer", name)),
+                }
+            }),
+            _ => None,
+        };
+        match name {
+            Some(name) => format!("{} ;; {}\n", line, name),
+            None => format!("{}\n", line),
+        }
+    }).collect()
+}
+
+/// Appends names of error codes to THROW instructions with a constant code.
+pub(super) fn annotate_throws(code: String, errors: &HashMap<isize, String>) -> String {
+    if errors.is_empty() {
+        return code
+    }
+    code.lines().map(|line| {
+        // offsets may precede the instruction, gas and debug comments may follow it
+        let mut words = instruction_text(line).split(' ');
+        let name = match (words.next(), words.next(), words.next()) {
+            (Some(insn), Some(arg), None) if insn.starts_with("THROW") =>
+                isize::from_str(arg).ok().and_then(|code| errors.get(&code)),
+            _ => None,
+        };
+        match name {
+            Some(name) => format!("{} ;; {}\n", line, name),
+            None => format!("{}\n", line),
+        }
+    }).collect()
 }
 
 fn disasm_text_command(m: &ArgMatches) -> core::result::Result<(), String> {
+    let errors = match m.value_of("ERRORS") {
+        Some(filename) => load_error_names(filename)?,
+        None => HashMap::new(),
+    };
+    let names = match m.value_of("ABI") {
+        Some(filename) => load_function_names(filename)?,
+        None => HashMap::new(),
+    };
+    let code = read_code(m.value_of("TVC").unwrap())?;
+
+    if m.is_present("TREE") {
+        print!("{}", code_tree(&code));
+        return Ok(())
+    }
+    let annotations = Annotations { gas: m.is_present("GAS"), offsets: m.is_present("OFFSETS") };
+    if let Some(dir) = m.value_of("SPLIT_OUTPUT") {
+        return disasm_split(&code, &names, &errors, annotations, Path::new(dir))
+    }
+    if m.is_present("STATS") {
+        let (_, functions) = disasm_functions(&code, &names, &errors, Annotations::default())?;
+        let stats: Vec<FunctionStats> = functions.iter().map(|function| FunctionStats {
+            label: &function.label,
+            code: &function.insns,
+            cells: function.usage.0,
+            bits: function.usage.1,
+        }).collect();
+        print!("{}", stats_report(&stats));
+        return Ok(())
+    }
+    if m.is_present("DECOMPILE") {
+        let (_, functions) = disasm_functions(&code, &names, &errors, Annotations::default())?;
+        for function in functions {
+            println!(";; {}\nfunction {} {{\n{}}}\n", function.label, function.name, decompile(&function.insns));
+        }
+        return Ok(())
+    }
+    if m.is_present("JSON") {
+        let json = disasm_json(&code, &names)?;
+        println!("{}", serde_json::to_string_pretty(&json).map_err(|e| format!("failed to serialize json: {}", e))?);
+        return Ok(())
+    }
+
+    if m.is_present("DEBUG_STRINGS") {
+        let (_, functions) = disasm_functions(&code, &names, &errors, Annotations::default())?;
+        let strings: Vec<String> = functions.iter()
+            .flat_map(|function| debug_strings(&function.label, &function.insns))
+            .collect();
+        if strings.is_empty() {
+            println!("no debug strings");
+        }
+        for string in strings {
+            println!("{}", string);
+        }
+        return Ok(())
+    }
+    let text = disasm_text(&code, &names, &errors, annotations, m.is_present("XREFS"))?;
+    print!("{}", text);
+    Ok(())
+}
+
+/// Lists strings of LOGSTR, PRINTSTR and DUMPTOSFMT instructions of the function and its
+/// continuations with the bit offsets of the instructions in their cells.
+pub(super) fn debug_strings(label: &str, code: &Code) -> Vec<String> {
+    walk(code).filter_map(|(_, insn)| {
+        let string = debug_string(insn)?;
+        Some(match insn.position() {
+            Some((_, offset)) => format!("{}, offset {}: {:?}", label, offset, string),
+            None => format!("{}: {:?}", label, string),
+        })
+    }).collect()
+}
+
+/// Disassembles every function of the contract: functions of dictionaries and entries.
+/// Returns the kind of the selector and the functions.
+fn disasm_functions(
+    code: &Cell,
+    names: &HashMap<u32, String>,
+    errors: &HashMap<isize, String>,
+    annotations: Annotations,
+) -> core::result::Result<(&'static str, Vec<Function>), String> {
+    let (selector, assigned) = recognize_selector(code)?;
+    let dict = |name: &str, key_size: usize, entries: bool| -> core::result::Result<Vec<Function>, String> {
+        code_dict_functions(&assigned[name], key_size, entries, names, errors, annotations)?
+            .ok_or("failed to recognize dictionary".to_string())
+    };
+    let entry = |part: &str, label: &str| {
+        Function::new(label.replace(' ', "_"), label.to_string(), None, &SliceData::from(&assigned[part]), names, errors, annotations)
+    };
+    match selector {
+        Selector::SolidityDeprecated => {
+            let mut functions = dict("dict-public", 32, false)?;
+            functions.append(&mut dict("dict-c3", 32, false)?);
+            Ok(("solidity deprecated", functions))
+        }
+        Selector::Solidity => {
+            let mut functions = dict("dict-c3", 32, false)?;
+            functions.push(entry("internal", "internal entry")?);
+            functions.push(entry("external", "external entry")?);
+            functions.push(entry("ticktock", "ticktock")?);
+            Ok(("solidity", functions))
+        }
+        Selector::FunC => Ok(("fun-c", dict("dict-c3", 19, true)?)),
+    }
+}
+
+/// Appends instructions of the code as json objects to the list, instructions
+/// of nested continuations follow their instruction with the next level.
+pub(super) fn code_json(code: &Code, level: usize, out: &mut Vec<Value>) {
+    for (depth, insn) in walk(code) {
+        let operands: Vec<String> = insn.params().iter().filter_map(|param| match param {
+            InstructionParameter::Code(_) => None,
+            param => Some(print_param(param)),
+        }).collect();
+        let (cell, offset) = match insn.position() {
+            Some((cell, offset)) => (Value::from(cell.to_hex_string()), Value::from(*offset)),
+            None => (Value::Null, Value::Null),
+        };
+        let name = if insn.is_quiet() { format!("{}Q", insn.name()) } else { insn.name().to_string() };
+        let mut object = Map::new();
+        object.insert("name".to_string(), Value::from(name));
+        object.insert("operands".to_string(), Value::from(operands));
+        object.insert("cell".to_string(), cell);
+        object.insert("offset".to_string(), offset);
+        object.insert("level".to_string(), Value::from(level + depth));
+        object.insert("gas".to_string(), insn.gas().map(Value::from).unwrap_or(Value::Null));
+        out.push(Value::Object(object));
+    }
+}
+
+fn disasm_json(code: &Cell, names: &HashMap<u32, String>) -> core::result::Result<Value, String> {
+    let (selector, functions) = disasm_functions(code, names, &HashMap::new(), Annotations::default())?;
+    let functions: Vec<Value> = functions.iter().map(|function| {
+        let mut insns = vec![];
+        code_json(&function.insns, 0, &mut insns);
+        let mut object = Map::new();
+        object.insert("name".to_string(), Value::from(function.name.as_str()));
+        object.insert("label".to_string(), Value::from(function.label.as_str()));
+        object.insert("instructions".to_string(), Value::Array(insns));
+        Value::Object(object)
+    }).collect();
+    let mut object = Map::new();
+    object.insert("selector".to_string(), Value::from(selector));
+    object.insert("functions".to_string(), Value::Array(functions));
+    Ok(Value::Object(object))
+}
+
+/// Writes every function of the contract into its own file in the directory
+/// and the list of files with function headers into `index.txt`.
+pub(super) fn disasm_split(
+    code: &Cell,
+    names: &HashMap<u32, String>,
+    errors: &HashMap<isize, String>,
+    annotations: Annotations,
+    dir: &Path,
+) -> core::result::Result<(), String> {
+    let (selector, functions) = disasm_functions(code, names, errors, annotations)?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("failed to create directory {}: {}", dir.display(), e))?;
+    let mut index = format!(";; {} selector detected\n", selector);
+    let mut files = HashSet::new();
+    for function in functions {
+        // the same id may be found in both dictionaries of the deprecated selector
+        let mut file = format!("{}.code", function.name);
+        let mut copy = 1;
+        while !files.insert(file.clone()) {
+            copy += 1;
+            file = format!("{}-{}.code", function.name, copy);
+        }
+        std::fs::write(dir.join(&file), format!(";; {}\n{}", function.label, function.code))
+            .map_err(|e| format!("failed to write file {}: {}", file, e))?;
+        index += &format!("{} ;; {}\n", file, function.label);
+    }
+    std::fs::write(dir.join("index.txt"), index)
+        .map_err(|e| format!("failed to write index file: {}", e))?;
+    println!("{} functions written to {}", files.len(), dir.display());
+    Ok(())
+}
+
+fn disasm_lint_command(m: &ArgMatches) -> core::result::Result<(), String> {
+    let code = read_code(m.value_of("TVC").unwrap())?;
+    let text = disasm_text(&code, &HashMap::new(), &HashMap::new(), Annotations::default(), false)?;
+    let security = m.is_present("SECURITY");
+    let findings = if security { security_lint(&text) } else { lint(&text) };
+    if findings.is_empty() {
+        println!("no findings");
+        return Ok(())
+    }
+    let mut total = 0;
+    for finding in &findings {
+        println!("{}", finding);
+        total += finding.savings.unwrap_or(0);
+    }
+    if security {
+        println!("{} findings", findings.len());
+    } else {
+        println!("{} findings, estimated savings: {} gas", findings.len(), total);
+    }
+    Ok(())
+}
+
+enum Selector {
+    SolidityDeprecated,
+    Solidity,
+    FunC,
+}
+
+/// Recognizes the selector of the contract and returns its parts: dictionaries and entries.
+fn recognize_selector(code: &Cell) -> core::result::Result<(Selector, HashMap<&'static str, Cell>), String> {
     let shape_deprecated = Shape::literal("ff00f4a42022c00192f4a0e18aed535830f4a1")
         .branch(Shape::var("dict-public"))
         .branch(Shape::literal("f4a420f4a1")
@@ -206,43 +766,88 @@ fn disasm_text_command(m: &ArgMatches) -> core::result::Result<(), String> {
         .branch(Shape::var("dict-c3")
             .branch(Shape::any())); // just to mark any() as used, can be omitted
 
-    let filename = m.value_of("TVC");
-    let tvc = filename.map(|f| std::fs::read(f))
-        .transpose()
-        .map_err(|e| format!(" failed to read tvc file: {}", e))?
-        .unwrap();
-    let mut csor = Cursor::new(tvc);
-    let mut roots = deserialize_cells_tree(&mut csor).map_err(|e| e.to_string())?;
-    let code = roots.remove(0);
-
-    if let Ok(assigned) = shape_deprecated.captures(&code) {
-        println!(";; solidity deprecated selector detected");
-        println!(";; public methods dictionary");
-        print_code_dict(&assigned["dict-public"], 32);
-        println!(";; internal functions dictionary");
-        print_code_dict(&assigned["dict-c3"], 32);
-    } else if let Ok(assigned) = shape_current.captures(&code)
-            .or_else(|_| shape_current_mycode.captures(&code)) {
-        println!(";; solidity selector detected");
-        println!(";; internal functions dictionary");
-        print_code_dict(&assigned["dict-c3"], 32);
-        println!(";; internal transaction entry point");
-        println!("{}", disasm(&mut SliceData::from(&assigned["internal"])));
-        println!(";; external transaction entry point");
-        println!("{}", disasm(&mut SliceData::from(&assigned["external"])));
-        println!(";; ticktock transaction entry point");
-        println!("{}", disasm(&mut SliceData::from(&assigned["ticktock"])));
-    } else if let Ok(assigned) = shape_fun_c.captures(&code) {
-        println!(";; fun-c selector detected");
-        println!(";; internal functions dictionary");
-        print_code_dict(&assigned["dict-c3"], 19);
+    if let Ok(assigned) = shape_deprecated.captures(code) {
+        Ok((Selector::SolidityDeprecated, assigned))
+    } else if let Ok(assigned) = shape_current.captures(code)
+            .or_else(|_| shape_current_mycode.captures(code)) {
+        Ok((Selector::Solidity, assigned))
+    } else if let Ok(assigned) = shape_fun_c.captures(code) {
+        Ok((Selector::FunC, assigned))
     } else {
-        return Err("failed to recognize selector".to_string())
+        Err("failed to recognize selector".to_string())
     }
+}
 
-    Ok(())
+/// Disassembles code of a contract recognizing its selector, the result is split into labeled sections.
+/// Calls by id are annotated with the called functions, with `xrefs` the index of their callers
+/// and callees is appended.
+pub(super) fn disasm_text(
+    code: &Cell,
+    names: &HashMap<u32, String>,
+    errors: &HashMap<isize, String>,
+    annotations: Annotations,
+    xrefs: bool,
+) -> core::result::Result<String, String> {
+    let (selector, assigned) = recognize_selector(code)?;
+    let entry = |part: &str, label: &str| {
+        Function::new(label.replace(' ', "_"), label.to_string(), None, &SliceData::from(&assigned[part]), names, errors, annotations)
+    };
+    let mut functions = vec![];
+    let mut out = String::new();
+    match selector {
+        Selector::SolidityDeprecated => {
+            out += ";; solidity deprecated selector detected\n";
+            out += ";; public methods dictionary\n";
+            out += &print_code_dict(&assigned["dict-public"], 32, false, names, errors, annotations, &mut functions)?;
+            out += ";; internal functions dictionary\n";
+            out += &print_code_dict(&assigned["dict-c3"], 32, false, names, errors, annotations, &mut functions)?;
+        }
+        Selector::Solidity => {
+            out += ";; solidity selector detected\n";
+            out += ";; selector: SETCP0, c3 := internal functions dictionary, jump to the entry by transaction type\n";
+            out += ";; internal functions dictionary\n";
+            out += &print_code_dict(&assigned["dict-c3"], 32, false, names, errors, annotations, &mut functions)?;
+            let internal = entry("internal", "internal entry")?;
+            out += ";; internal entry\n";
+            out += &format!("{}\n", internal.code);
+            let external = entry("external", "external entry")?;
+            out += ";; external entry\n";
+            if has_replay_protection(&out) || has_replay_protection(&external.code) {
+                out += ";; replay protection detected (exception 52)\n";
+            }
+            out += &format!("{}\n", external.code);
+            let ticktock = entry("ticktock", "ticktock")?;
+            out += ";; ticktock\n";
+            out += &format!("{}\n", ticktock.code);
+            functions.push(internal);
+            functions.push(external);
+            functions.push(ticktock);
+        }
+        Selector::FunC => {
+            out += ";; fun-c selector detected\n";
+            out += ";; selector: SETCP0, jump to the function from c3 dictionary by id, entries have ids 0, -1 and -2\n";
+            out += ";; internal functions dictionary\n";
+            out += &print_code_dict(&assigned["dict-c3"], 19, true, names, errors, annotations, &mut functions)?;
+        }
+    }
+    // calls by id are resolved to functions of the dictionaries
+    let ids: HashMap<isize, String> = functions.iter()
+        .filter_map(|function| function.id.map(|id| (id, function.name.clone())))
+        .collect();
+    out = annotate_calls(out, &ids);
+    if xrefs {
+        let calls: Vec<(&str, Vec<isize>)> = functions.iter()
+            .map(|function| (function.name.as_str(), call_targets(&function.insns)))
+            .collect();
+        out += &xref_index(&calls, &ids);
+    }
+    Ok(out)
 }
 
-pub(super) fn disasm(slice: &mut SliceData) -> String {
+pub fn disasm(slice: &mut SliceData) -> String {
     print_code(&load(slice).unwrap(), "")
 }
+
+pub fn disasm_ex(slice: &mut SliceData) -> core::result::Result<String, String> {
+    load(slice).map(|code| print_code(&code, "")).map_err(|e| e.to_string())
+}
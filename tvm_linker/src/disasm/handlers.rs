@@ -11,12 +11,45 @@
  * limitations under the License.
  */
 
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use ton_types::{Result, SliceData};
 
 use super::types::{Instruction, Signaling, Quiet};
 use super::loader::*;
 
-pub(super) type LoadHandler = fn(&mut SliceData) -> Result<Instruction>;
+pub type LoadHandler = fn(&mut SliceData) -> Result<Instruction>;
+
+/// Additional instructions, e.g. extensions of a particular network, which are
+/// decoded by the disassembler along with the standard code page 0.
+pub trait InstructionSet: Send {
+    fn register(&self, handlers: &mut Handlers) -> std::result::Result<(), String>;
+}
+
+lazy_static! {
+    // code page 0 with all registered instruction sets, replaced as a whole on registration
+    static ref CODE_PAGE_0: Mutex<Arc<Handlers>> = Mutex::new(Arc::new(Handlers::new_code_page_0_base()));
+}
+
+/// Number of registrations so far, a thread takes code page 0 from the registry
+/// only when it changed since the copy the thread keeps.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CACHED_CODE_PAGE_0: RefCell<Option<(usize, Arc<Handlers>)>> = RefCell::new(None);
+}
+
+/// Registers the instruction set for all subsequent disassembling. The set is
+/// rejected if its opcodes conflict with already known instructions.
+pub fn register_instruction_set(set: Box<dyn InstructionSet>) -> std::result::Result<(), String> {
+    let mut code_page_0 = CODE_PAGE_0.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut handlers = Handlers::clone(&code_page_0);
+    set.register(&mut handlers)?;
+    *code_page_0 = Arc::new(handlers);
+    GENERATION.fetch_add(1, Ordering::Release);
+    Ok(())
+}
 
 #[derive(Clone, Copy)]
 enum Handler {
@@ -24,6 +57,7 @@ enum Handler {
     Subset(usize),
 }
 
+#[derive(Clone)]
 pub struct Handlers {
     directs: [Handler; 256],
     subsets: Vec<Handlers>,
@@ -38,7 +72,23 @@ impl Handlers {
         }
     }
 
-    pub(super) fn new_code_page_0() -> Handlers {
+    pub(super) fn new_code_page_0() -> Arc<Handlers> {
+        let generation = GENERATION.load(Ordering::Acquire);
+        CACHED_CODE_PAGE_0.with(|cached| {
+            let mut cached = cached.borrow_mut();
+            if let Some((cached_generation, handlers)) = &*cached {
+                if *cached_generation == generation {
+                    return handlers.clone()
+                }
+            }
+            // the lock is poisoned only by a panicking registration, which never replaces the handlers
+            let handlers = CODE_PAGE_0.lock().unwrap_or_else(PoisonError::into_inner).clone();
+            *cached = Some((generation, handlers.clone()));
+            handlers
+        })
+    }
+
+    fn new_code_page_0_base() -> Handlers {
         let mut handlers = Handlers::new();
         handlers
             .add_code_page_0_part_stack()
@@ -614,6 +664,7 @@ impl Handlers {
                 .set(0x04, load_setcode)
                 .set(0x06, load_setlibcode)
                 .set(0x07, load_changelib)
+                .set(0x0A, load_copyleft)
             )
     }
 
@@ -790,6 +841,10 @@ impl Handlers {
                 .set(0x27, load_balance)
                 .set(0x28, load_my_addr)
                 .set(0x29, load_config_root)
+                .set(0x2A, load_my_code)
+                .set(0x2B, load_init_code_hash)
+                .set(0x2C, load_storage_fee)
+                .set(0x2D, load_seqno)
                 .set(0x30, load_config_dict)
                 .set(0x32, load_config_ref_param)
                 .set(0x33, load_config_opt_param)
@@ -808,8 +863,12 @@ impl Handlers {
             .set(0x00, load_hashcu)
             .set(0x01, load_hashsu)
             .set(0x02, load_sha256u)
+            .set_range(0x04..0x08, load_hashext)
             .set(0x10, load_chksignu)
             .set(0x11, load_chksigns)
+            .set(0x12, load_ecrecover)
+            .set(0x14, load_p256_chksignu)
+            .set(0x15, load_p256_chksigns)
             .set(0x40, load_cdatasizeq)
             .set(0x41, load_cdatasize)
             .set(0x42, load_sdatasizeq)
@@ -844,6 +903,42 @@ impl Handlers {
         }
     }
 
+    /// Registers the handler for instructions starting with the `prefix` bytes,
+    /// intermediate subsets are created if needed.
+    pub fn register_opcode(&mut self, prefix: &[u8], handler: LoadHandler) -> std::result::Result<(), String> {
+        if prefix.is_empty() {
+            return Err("Opcode prefix is empty".to_owned())
+        }
+        if !self.try_register_opcode(prefix, handler) {
+            return Err(format!("Opcode {} is already registered", hex::encode_upper(prefix)))
+        }
+        Ok(())
+    }
+
+    fn try_register_opcode(&mut self, prefix: &[u8], handler: LoadHandler) -> bool {
+        let code = prefix[0] as usize;
+        let free = match self.directs[code] {
+            Handler::Direct(x) => x as usize == load_unknown as usize,
+            Handler::Subset(_) => false,
+        };
+        if prefix.len() == 1 {
+            if free {
+                self.directs[code] = Handler::Direct(handler);
+            }
+            return free
+        }
+        let index = match self.directs[code] {
+            Handler::Subset(i) => i,
+            Handler::Direct(_) if free => {
+                self.directs[code] = Handler::Subset(self.subsets.len());
+                self.subsets.push(Handlers::new());
+                self.subsets.len() - 1
+            }
+            Handler::Direct(_) => return false,
+        };
+        self.subsets[index].try_register_opcode(&prefix[1..], handler)
+    }
+
     fn add_subset(&mut self, code: u8, subset: &mut Handlers) -> &mut Handlers {
         match self.directs[code as usize] {
             Handler::Direct(x) => if x as usize == load_unknown as usize {
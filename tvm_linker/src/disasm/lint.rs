@@ -0,0 +1,319 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Gas of an instruction without arguments: 10 + 8 bits of opcode.
+const SIMPLE_GAS: i64 = 18;
+/// Gas of an instruction with a 8-bit immediate argument (ADDCONST, MULCONST).
+const CONST_GAS: i64 = 26;
+/// Difference between loading a new cell (100) and reloading an already loaded one (25).
+const CELL_RELOAD_GAS: i64 = 25;
+/// Gas of loading one new cell, paid for every dictionary node on the lookup path.
+const CELL_LOAD_GAS: i64 = 100;
+
+/// Possible optimization found in the disassembled code.
+pub struct Finding {
+    /// line in the output of `disasm text`, starting from 1
+    pub line: usize,
+    pub message: String,
+    /// estimated gas saved for one execution of the code, none for security findings
+    pub savings: Option<i64>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.savings {
+            Some(savings) => write!(f, "line {}: {} (estimated savings: {} gas)", self.line, self.message, savings),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+struct Insn<'a> {
+    line: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+/// Splits the text produced by the disassembler into instructions, comments are dropped.
+/// Returns instructions and indexes of instructions which start a new section.
+fn parse(text: &str) -> (Vec<Insn>, HashSet<usize>) {
+    let mut insns = vec![];
+    // a line starting with a comment is a section or a function header
+    let mut headers = HashSet::new();
+    for (i, line) in text.lines().enumerate() {
+        let code = line.split(";;").next().unwrap().trim_end();
+        if code.trim().is_empty() {
+            if line.starts_with(";;") {
+                headers.insert(insns.len());
+            }
+            continue
+        }
+        insns.push(Insn {
+            line: i + 1,
+            indent: code.len() - code.trim_start().len(),
+            text: code.trim_start(),
+        });
+    }
+    (insns, headers)
+}
+
+/// Finds continuations executed by loops: returns the opening and closing instructions
+/// of every such block and the loop instruction.
+fn loop_blocks(insns: &[Insn]) -> Vec<(usize, usize, usize)> {
+    // blocks of continuations: opening line -> closing line
+    let mut blocks = HashMap::new();
+    let mut stack = vec![];
+    for (n, insn) in insns.iter().enumerate() {
+        if insn.text.ends_with('{') {
+            stack.push(n);
+        } else if insn.text == "}" {
+            if let Some(open) = stack.pop() {
+                blocks.insert(open, n);
+            }
+        }
+    }
+    let mut opens: Vec<_> = blocks.keys().cloned().collect();
+    opens.sort();
+    let mut loops = vec![];
+    for open in opens {
+        // a loop takes continuations pushed right before it: body or condition and body
+        let mut after = blocks[&open] + 1;
+        while let Some(close) = blocks.get(&after) {
+            after = close + 1;
+        }
+        if insns.get(after).map(|insn| is_loop(insn.text)).unwrap_or(false) {
+            loops.push((open, blocks[&open], after));
+        }
+    }
+    loops
+}
+
+fn pushint_gas(value: i64) -> i64 {
+    if value >= -5 && value <= 10 {
+        SIMPLE_GAS
+    } else if value >= -128 && value <= 127 {
+        CONST_GAS
+    } else {
+        34
+    }
+}
+
+fn pushint_value(text: &str) -> Option<i64> {
+    if text.starts_with("PUSHINT ") {
+        text["PUSHINT ".len()..].trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_loop(text: &str) -> bool {
+    match text {
+        "REPEAT" | "REPEATBRK" | "UNTIL" | "UNTILBRK" | "WHILE" | "WHILEBRK" | "AGAIN" | "AGAINBRK" => true,
+        _ => false,
+    }
+}
+
+fn is_dict_op(text: &str) -> bool {
+    text.starts_with("DICT") || text.starts_with("PFXDICT") || text.starts_with("SUBDICT")
+}
+
+/// Instructions which push the same cell every time, so that a repeated CTOS
+/// of their result loads the cell again. PUSHROOT is disassembled as `PUSHCTR c4`.
+fn is_cell_source(text: &str) -> bool {
+    text == "PUSHCTR c4" || text.starts_with("GETGLOB ")
+}
+
+/// Returns the instruction following the given one in the same block.
+fn next<'a>(insns: &[Insn<'a>], i: usize, n: usize) -> Option<&'a str> {
+    insns.get(i + n).filter(|insn| insn.indent == insns[i].indent).map(|insn| insn.text)
+}
+
+fn fold(a: i64, b: i64, op: &str) -> Option<i64> {
+    match op {
+        "ADD" => a.checked_add(b),
+        "SUB" => a.checked_sub(b),
+        "MUL" => a.checked_mul(b),
+        _ => None,
+    }
+}
+
+/// Looks for known gas inefficiencies in the text produced by the disassembler:
+/// arithmetic on constants which can be folded or done with an immediate argument,
+/// repeated CTOS of the same cell and dictionary operations inside loops.
+/// Savings are rough estimates based on the basic instruction and cell load prices.
+pub(super) fn lint(text: &str) -> Vec<Finding> {
+    let (insns, headers) = parse(text);
+
+    let mut findings = vec![];
+    let mut sources: HashMap<&str, usize> = HashMap::new();
+    let mut i = 0;
+    while i < insns.len() {
+        if headers.contains(&i) {
+            sources.clear();
+        }
+        if let Some(a) = pushint_value(insns[i].text) {
+            if let (Some(b), Some(op)) = (next(&insns, i, 1).and_then(pushint_value), next(&insns, i, 2)) {
+                if let Some(c) = fold(a, b, op) {
+                    findings.push(Finding {
+                        line: insns[i].line,
+                        message: format!("PUSHINT {}; PUSHINT {}; {} can be folded to PUSHINT {}", a, b, op, c),
+                        savings: Some(pushint_gas(a) + pushint_gas(b) + SIMPLE_GAS - pushint_gas(c)),
+                    });
+                    i += 3;
+                    continue
+                }
+            }
+            let imm = match next(&insns, i, 1) {
+                Some("ADD") if a >= -128 && a <= 127 => Some(("ADDCONST", a)),
+                Some("SUB") if a > -128 && a <= 128 => Some(("ADDCONST", -a)),
+                Some("MUL") if a >= -128 && a <= 127 => Some(("MULCONST", a)),
+                _ => None,
+            };
+            if let Some((name, value)) = imm {
+                findings.push(Finding {
+                    line: insns[i].line,
+                    message: format!("PUSHINT {}; {} can be replaced with {} {}", a, next(&insns, i, 1).unwrap(), name, value),
+                    savings: Some(pushint_gas(a) + SIMPLE_GAS - CONST_GAS),
+                });
+                i += 2;
+                continue
+            }
+        }
+        if is_cell_source(insns[i].text) && next(&insns, i, 1) == Some("CTOS") {
+            match sources.get(insns[i].text).cloned() {
+                Some(first) => findings.push(Finding {
+                    line: insns[i].line,
+                    message: format!("{}; CTOS repeats line {}, keep the slice instead of loading the cell again",
+                        insns[i].text, first),
+                    savings: Some(CELL_RELOAD_GAS),
+                }),
+                None => { sources.insert(insns[i].text, insns[i].line); }
+            }
+        }
+        i += 1;
+    }
+
+    let mut reported = HashSet::new();
+    for (open, close, after) in loop_blocks(&insns) {
+        for n in open + 1..close {
+            if is_dict_op(insns[n].text) && reported.insert(n) {
+                findings.push(Finding {
+                    line: insns[n].line,
+                    message: format!("{} inside a loop at line {}, consider looking up values once before the loop",
+                        insns[n].text, insns[after].line),
+                    savings: Some(CELL_LOAD_GAS),
+                });
+            }
+        }
+    }
+    findings.sort_by_key(|finding| finding.line);
+    findings
+}
+
+fn is_signature_check(text: &str) -> bool {
+    text == "CHKSIGNU" || text == "CHKSIGNS"
+}
+
+fn is_check(text: &str) -> bool {
+    is_signature_check(text) || text.starts_with("THROWIF") || text.starts_with("THROWANYIF")
+}
+
+/// Whether the instruction throws exception 52, which Solidity throws for external
+/// messages with an expired or already used timestamp.
+pub(super) fn throws_replay_error(text: &str) -> bool {
+    let mut words = text.split(' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some("THROW"), Some("52"), None) | (Some("THROWIF"), Some("52"), None) | (Some("THROWIFNOT"), Some("52"), None) => true,
+        _ => false,
+    }
+}
+
+/// Looks for risky patterns in the text produced by the disassembler. These are heuristics
+/// working on the text, calls are not followed and the findings need a manual review:
+/// - ACCEPT in a contract whose code never checks a signature;
+/// - a Solidity contract with an external entry which never throws exception 52;
+/// - SETCODE without a signature check or a conditional exception before it in its block
+///   or in the blocks enclosing it, checks in other branches or in called functions are not seen;
+/// - SENDRAWMSG with mode 128 (carrying all balance) inside loops.
+pub(super) fn security_lint(text: &str) -> Vec<Finding> {
+    let (insns, headers) = parse(text);
+    let mut findings = vec![];
+
+    let external = text.lines().position(|line| line == ";; external entry");
+    if let Some(line) = external {
+        if text.starts_with(";; solidity") && !insns.iter().any(|insn| throws_replay_error(insn.text)) {
+            findings.push(Finding {
+                line: line + 1,
+                message: "external entry has no replay protection (exception 52 is never thrown)".to_string(),
+                savings: None,
+            });
+        }
+    }
+
+    let signed = insns.iter().any(|insn| is_signature_check(insn.text));
+    // whether a check is already done in every enclosing block, a block inherits the state of its parent
+    let mut checked = vec![false];
+    for (n, insn) in insns.iter().enumerate() {
+        if headers.contains(&n) {
+            checked = vec![false];
+        }
+        if insn.text == "}" {
+            if checked.len() > 1 {
+                checked.pop();
+            }
+            continue
+        }
+        if insn.text.ends_with('{') {
+            let parent = *checked.last().unwrap();
+            checked.push(parent);
+            continue
+        }
+        if is_check(insn.text) {
+            *checked.last_mut().unwrap() = true;
+        }
+        if insn.text == "ACCEPT" && !signed {
+            findings.push(Finding {
+                line: insn.line,
+                message: "ACCEPT in a contract which never checks a signature".to_string(),
+                savings: None,
+            });
+        }
+        if insn.text == "SETCODE" && !checked.last().unwrap() {
+            findings.push(Finding {
+                line: insn.line,
+                message: "SETCODE is not preceded by a signature check or a conditional exception".to_string(),
+                savings: None,
+            });
+        }
+    }
+
+    let mut reported = HashSet::new();
+    for (open, close, after) in loop_blocks(&insns) {
+        for n in open + 2..close {
+            let mode = pushint_value(insns[n - 1].text).unwrap_or(0);
+            if insns[n].text == "SENDRAWMSG" && mode & 128 != 0 && reported.insert(n) {
+                findings.push(Finding {
+                    line: insns[n].line,
+                    message: format!("SENDRAWMSG with mode {} carrying all balance inside a loop at line {}",
+                        mode, insns[after].line),
+                    savings: None,
+                });
+            }
+        }
+    }
+    findings.sort_by_key(|finding| finding.line);
+    findings
+}
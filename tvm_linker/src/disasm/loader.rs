@@ -11,7 +11,7 @@
  * limitations under the License.
  */
 
-use ton_types::{Result, SliceData, fail};
+use ton_types::{Cell, CellType, Result, SliceData, UInt256, fail};
 use std::ops::Not;
 use num_traits::Zero;
 
@@ -75,10 +75,9 @@ macro_rules! create_handler_2r {
             if opc != $opc {
                 fail!("invalid opcode");
             }
-            let mut subslice = SliceData::from(slice.reference(0)?);
-            let code = load(&mut subslice)?;
+            let subslice = SliceData::from(slice.reference(0)?);
             slice.shrink_references(1..);
-            Ok(Instruction::new($mnemonic).with_param(InstructionParameter::Code(code)))
+            Ok(Instruction::new($mnemonic).with_body(subslice))
         }
     };
 }
@@ -99,26 +98,111 @@ macro_rules! check_eq {
     };
 }
 
-pub(super) fn load(slice: &mut SliceData) -> Result<Code> {
-    let handlers = Handlers::new_code_page_0();
-    let mut code = Code::new();
-    loop {
-        if slice.is_empty() {
-            if slice.remaining_references() > 1 {
+/// Placeholder for a pruned branch, library reference or Merkle cell, which holds
+/// no code itself, with the hash of the cell it stands for.
+fn exotic_placeholder(cell: &Cell) -> Option<Instruction> {
+    // the hash follows the type byte, and the level mask for a pruned branch
+    let (kind, start) = match cell.cell_type() {
+        CellType::PrunedBranch => (";; pruned branch", 2),
+        CellType::LibraryReference => (";; library", 1),
+        CellType::MerkleProof => (";; merkle proof", 1),
+        CellType::MerkleUpdate => (";; merkle update", 1),
+        _ => return None,
+    };
+    let data = cell.data();
+    if data.len() < start + 32 {
+        return Some(Instruction::new(kind))
+    }
+    Some(Instruction::new(kind).with_param(InstructionParameter::Hash(UInt256::from(&data[start..start + 32]))))
+}
+
+/// Continuation being decoded with the instructions decoded so far.
+struct Frame {
+    slice: SliceData,
+    code: Code,
+    /// whether implicit jumps to the next cell are followed
+    follow: bool,
+    /// index of the parameter of the parent instruction taking the code
+    param: usize,
+    /// continuations of the last instruction which are not decoded yet
+    bodies: Vec<(usize, SliceData)>,
+}
+
+impl Frame {
+    fn new(slice: SliceData, follow: bool, param: usize) -> Frame {
+        Frame { slice, code: Code::new(), follow, param, bodies: vec![] }
+    }
+
+    /// Decodes the next instruction, returns false when the continuation ends.
+    fn step(&mut self, handlers: &Handlers) -> Result<bool> {
+        if self.follow && self.slice.is_empty() {
+            if self.slice.remaining_references() > 1 {
                 fail!("two or more remaining references");
-            } else if slice.remaining_references() == 1 {
-                *slice = SliceData::from(slice.reference(0).unwrap())
+            } else if self.slice.remaining_references() == 1 {
+                self.slice = SliceData::from(self.slice.reference(0).unwrap());
+                if let Some(insn) = exotic_placeholder(self.slice.cell()) {
+                    self.code.push(insn);
+                    return Ok(false)
+                }
             } else {
-                break;
+                return Ok(false)
             }
         }
-        while slice.remaining_bits() > 0 {
-            let handler = handlers.get_handler(&mut slice.clone())?;
-            let insn = handler(slice)?;
-            code.push(insn);
+        if self.slice.remaining_bits() == 0 {
+            // the next cell is checked by the next step
+            return Ok(self.follow)
         }
+        let slice = &mut self.slice;
+        let handler = handlers.get_handler(&mut slice.clone())?;
+        let (cell, offset) = (slice.cell().repr_hash(), slice.pos());
+        let (bits, refs) = (slice.remaining_bits(), slice.remaining_references());
+        let mut insn = handler(slice)?;
+        self.bodies = insn.take_bodies();
+        self.code.push(insn
+            .with_position(cell, offset)
+            .with_length(bits - slice.remaining_bits(), refs - slice.remaining_references()));
+        Ok(true)
     }
-    Ok(code)
+}
+
+/// Decodes the code with its continuations keeping them on an explicit stack,
+/// so deeply nested continuations do not exhaust the native one.
+fn decode(slice: &mut SliceData, follow: bool) -> Result<Code> {
+    if let Some(insn) = exotic_placeholder(slice.cell()) {
+        return Ok(vec![insn])
+    }
+    let handlers = Handlers::new_code_page_0();
+    let mut stack = vec![Frame::new(slice.clone(), follow, 0)];
+    loop {
+        let frame = stack.last_mut().unwrap();
+        if let Some((param, body)) = frame.bodies.pop() {
+            match exotic_placeholder(body.cell()) {
+                Some(insn) => frame.code.last_mut().unwrap().set_code(param, vec![insn]),
+                None => stack.push(Frame::new(body, true, param)),
+            }
+            continue
+        }
+        if frame.step(&handlers)? {
+            continue
+        }
+        let frame = stack.pop().unwrap();
+        match stack.last_mut() {
+            Some(parent) => parent.code.last_mut().unwrap().set_code(frame.param, frame.code),
+            None => {
+                *slice = frame.slice;
+                return Ok(frame.code)
+            }
+        }
+    }
+}
+
+pub(super) fn load(slice: &mut SliceData) -> Result<Code> {
+    decode(slice, true)
+}
+/// Loads instructions stored in the bits of the cell itself, references are consumed
+/// only by instructions which take them and implicit jumps to the next cell are not followed.
+pub(super) fn load_cell(slice: &mut SliceData) -> Result<Code> {
+    decode(slice, false)
 }
 pub(super) fn load_unknown(_slice: &mut SliceData) -> Result<Instruction> {
     fail!("unknown opcode")
@@ -517,10 +601,9 @@ pub(super) fn load_pushrefslice(slice: &mut SliceData) -> Result<Instruction> {
 pub(super) fn load_pushrefcont(slice: &mut SliceData) -> Result<Instruction> {
     let opc = slice.get_next_int(8)?;
     check_eq!(opc, 0x8a);
-    let mut subslice = SliceData::from(slice.reference(0)?);
-    let code = load(&mut subslice)?;
+    let subslice = SliceData::from(slice.reference(0)?);
     slice.shrink_references(1..);
-    Ok(Instruction::new("PUSHREFCONT").with_param(InstructionParameter::Code(code)))
+    Ok(Instruction::new("PUSHREFCONT").with_body(subslice))
 }
 pub(super) fn load_pushslice_short(slice: &mut SliceData) -> Result<Instruction> {
     let opc = slice.get_next_int(8)?;
@@ -560,20 +643,23 @@ pub(super) fn load_pushcont_long(slice: &mut SliceData) -> Result<Instruction> {
     let mut subslice = slice.clone();
     subslice.shrink_data(..bits);
     subslice.shrink_references(..r);
-    let code = load(&mut subslice)?;
 
     slice.shrink_data(bits..);
     slice.shrink_references(r..);
 
-    Ok(Instruction::new("PUSHCONT").with_param(InstructionParameter::Code(code)))
+    Ok(Instruction::new("PUSHCONT").with_body(subslice))
 }
 pub(super) fn load_pushcont_short(slice: &mut SliceData) -> Result<Instruction> {
     let opc = slice.get_next_int(4)?;
     check_eq!(opc, 0x9);
     let x = slice.get_next_int(4).unwrap() as usize;
-    let mut body = slice.get_next_slice(x * 8)?;
-    let code = load(&mut body)?;
-    Ok(Instruction::new("PUSHCONT").with_param(InstructionParameter::Code(code)))
+    check!(slice.remaining_bits() >= x * 8);
+    // the body stays a part of the cell to keep offsets of its instructions
+    let mut body = slice.clone();
+    body.shrink_data(..x * 8);
+    body.shrink_references(..0);
+    slice.shrink_data(x * 8..);
+    Ok(Instruction::new("PUSHCONT").with_body(body))
 }
 create_handler_1t!(load_add,    0xa0, "ADD");
 create_handler_1t!(load_sub,    0xa1, "SUB");
@@ -1134,19 +1220,17 @@ pub(super) fn load_ifbitjmpref(slice: &mut SliceData) -> Result<Instruction> {
     let opc = slice.get_next_int(15)?;
     check_eq!(opc << 1, 0xe3c);
     let n = slice.get_next_int(5)? as isize;
-    let mut subslice = SliceData::from(slice.reference(0)?);
-    let code = load(&mut subslice)?;
+    let subslice = SliceData::from(slice.reference(0)?);
     slice.shrink_references(1..);
-    Ok(Instruction::new("IFBITJMPREF").with_param(InstructionParameter::Integer(n)).with_param(InstructionParameter::Code(code)))
+    Ok(Instruction::new("IFBITJMPREF").with_param(InstructionParameter::Integer(n)).with_body(subslice))
 }
 pub(super) fn load_ifnbitjmpref(slice: &mut SliceData) -> Result<Instruction> {
     let opc = slice.get_next_int(15)?;
     check_eq!(opc << 1, 0xe3e);
     let n = slice.get_next_int(5)? as isize;
-    let mut subslice = SliceData::from(slice.reference(0)?);
-    let code = load(&mut subslice)?;
+    let subslice = SliceData::from(slice.reference(0)?);
     slice.shrink_references(1..);
-    Ok(Instruction::new("IFNBITJMPREF").with_param(InstructionParameter::Integer(n)).with_param(InstructionParameter::Code(code)))
+    Ok(Instruction::new("IFNBITJMPREF").with_param(InstructionParameter::Integer(n)).with_body(subslice))
 }
 create_handler_1!(load_repeat,    0xe4, "REPEAT");
 create_handler_1!(load_repeatend, 0xe5, "REPEATEND");
@@ -1380,6 +1464,7 @@ create_handler_2!(load_rawreservex,        0xfb03, "RAWRESERVEX");
 create_handler_2!(load_setcode,            0xfb04, "SETCODE");
 create_handler_2!(load_setlibcode,         0xfb06, "SETLIBCODE");
 create_handler_2!(load_changelib,          0xfb07, "CHANGELIB");
+create_handler_2!(load_copyleft,           0xfb0a, "COPYLEFT");
 create_handler_2!(load_stdict,             0xf400, "STDICT");
 create_handler_2!(load_skipdict,           0xf401, "SKIPDICT");
 create_handler_2!(load_lddicts,            0xf402, "LDDICTS");
@@ -1560,6 +1645,10 @@ create_handler_2!(load_randseed,         0xf826, "RANDSEED");
 create_handler_2!(load_balance,          0xf827, "BALANCE");
 create_handler_2!(load_my_addr,          0xf828, "MYADDR");
 create_handler_2!(load_config_root,      0xf829, "CONFIGROOT");
+create_handler_2!(load_my_code,          0xf82a, "MYCODE");
+create_handler_2!(load_init_code_hash,   0xf82b, "INITCODEHASH");
+create_handler_2!(load_storage_fee,      0xf82c, "STORAGEFEES");
+create_handler_2!(load_seqno,            0xf82d, "SEQNO");
 create_handler_2!(load_config_dict,      0xf830, "CONFIGDICT");
 create_handler_2!(load_config_ref_param, 0xf832, "CONFIGPARAM");
 create_handler_2!(load_config_opt_param, 0xf833, "CONFIGOPTPARAM");
@@ -1582,8 +1671,23 @@ pub(super) fn load_setglob(slice: &mut SliceData) -> Result<Instruction> {
 create_handler_2!(load_hashcu,     0xf900, "HASHCU");
 create_handler_2!(load_hashsu,     0xf901, "HASHSU");
 create_handler_2!(load_sha256u,    0xf902, "SHA256U");
+pub(super) fn load_hashext(slice: &mut SliceData) -> Result<Instruction> {
+    let opc = slice.get_next_int(16)?;
+    check!((0xf904..=0xf907).contains(&opc));
+    let hash_id = slice.get_next_int(8)? as usize;
+    let name = match opc {
+        0xf904 => "HASHEXT",
+        0xf905 => "HASHEXTR",
+        0xf906 => "HASHEXTA",
+        _ => "HASHEXTAR",
+    };
+    Ok(Instruction::new(name).with_param(InstructionParameter::Length(hash_id)))
+}
 create_handler_2!(load_chksignu,   0xf910, "CHKSIGNU");
 create_handler_2!(load_chksigns,   0xf911, "CHKSIGNS");
+create_handler_2!(load_ecrecover,  0xf912, "ECRECOVER");
+create_handler_2!(load_p256_chksignu, 0xf914, "P256_CHKSIGNU");
+create_handler_2!(load_p256_chksigns, 0xf915, "P256_CHKSIGNS");
 create_handler_2!(load_cdatasizeq, 0xf940, "CDATASIZEQ");
 create_handler_2!(load_cdatasize,  0xf941, "CDATASIZE");
 create_handler_2!(load_sdatasizeq, 0xf942, "SDATASIZEQ");
@@ -1621,97 +1725,162 @@ pub(super) fn load_print_var(slice: &mut SliceData) -> Result<Instruction> {
 pub(super) fn load_dump_string(slice: &mut SliceData) -> Result<Instruction> {
     let opc = slice.get_next_int(12)?;
     check_eq!(opc, 0xfef);
-    let n = slice.get_next_int(4)?;
-    let mode = slice.get_next_int(8)?;
-    match n {
-        0 => {
-            check_eq!(mode, 0x00);
-            Ok(Instruction::new("LOGFLUSH"))
-        }
-        _ => {
-            if mode == 0x00 {
-                let s = slice.get_next_slice(n as usize * 8)?;
-                Ok(Instruction::new("LOGSTR").with_param(InstructionParameter::Slice(s)))
-            } else if mode == 0x01 {
-                let s = slice.get_next_slice(n as usize * 8)?;
-                Ok(Instruction::new("PRINTSTR").with_param(InstructionParameter::Slice(s)))
-            } else {
-                fail!("unknown dump_string mode")
-            }
-        }
+    let n = slice.get_next_int(4)? as usize;
+    // n + 1 bytes follow: the mode and the string, or the format string as a whole
+    let string = slice.get_next_slice((n + 1) * 8)?;
+    let mut s = string.clone();
+    let mode = s.get_next_int(8)?;
+    match (n, mode) {
+        (0, 0x00) => Ok(Instruction::new("LOGFLUSH")),
+        (0, _) => fail!("unknown dump_string mode"),
+        (_, 0x00) => Ok(Instruction::new("LOGSTR").with_param(InstructionParameter::Slice(s))),
+        (_, 0x01) => Ok(Instruction::new("PRINTSTR").with_param(InstructionParameter::Slice(s))),
+        // the format string is stored as is, so it starts with a printable character
+        (_, 0x20..=0x7e) => Ok(Instruction::new("DUMPTOSFMT").with_param(InstructionParameter::Slice(string))),
+        _ => Ok(Instruction::new("DEBUGSTR").with_param(InstructionParameter::Slice(string))),
     }
 }
 
+/// Returns the inline string of LOGSTR, PRINTSTR and DUMPTOSFMT.
+pub(super) fn debug_string(insn: &Instruction) -> Option<String> {
+    match (insn.name(), insn.params().first()) {
+        ("LOGSTR", Some(InstructionParameter::Slice(s)))
+        | ("PRINTSTR", Some(InstructionParameter::Slice(s)))
+        | ("DUMPTOSFMT", Some(InstructionParameter::Slice(s))) =>
+            Some(String::from_utf8_lossy(&s.get_bytestring(0)).into_owned()),
+        _ => None,
+    }
+}
+
+/// Prints an instruction parameter as the assembler takes it, nested code is printed by `print_code`.
+pub(super) fn print_param(param: &InstructionParameter) -> String {
+    match param {
+        InstructionParameter::BigInteger(i) => format!("{}", i),
+        InstructionParameter::ControlRegister(c) => format!("c{}", c),
+        //InstructionParameter::DivisionMode(_) => {
+        //    todo!()
+        //}
+        InstructionParameter::Integer(i) => format!("{}", i),
+        InstructionParameter::Length(l) => format!("{}", l),
+        InstructionParameter::LengthAndIndex(l, i) => format!("{}, {}", l, i),
+        InstructionParameter::Nargs(n) => format!("{}", n),
+        InstructionParameter::Pargs(p) => format!("{}", p),
+        InstructionParameter::Rargs(r) => format!("{}", r),
+        InstructionParameter::Slice(s) => format!("x{}", s.to_hex_string()),
+        InstructionParameter::StackRegister(r) => format!("s{}", r),
+        InstructionParameter::StackRegisterPair(ra, rb) => format!("s{}, s{}", ra, rb),
+        InstructionParameter::StackRegisterTriple(ra, rb, rc) => format!("s{}, s{}, s{}", ra, rb, rc),
+        InstructionParameter::Code(_) => String::new(),
+        InstructionParameter::Hash(hash) => hash.to_hex_string(),
+    }
+}
+
+/// Width of the bit offset printed before instructions.
+const OFFSET_WIDTH: usize = 5;
+
+/// Optional comments added to the printed code.
+#[derive(Clone, Copy, Default)]
+pub(super) struct Annotations {
+    /// the basic gas price of every instruction and the total of every continuation,
+    /// nested continuations are counted separately
+    pub gas: bool,
+    /// the bit offset of every instruction in its cell and the hash of every cell
+    /// the code continues in
+    pub offsets: bool,
+}
+
 pub fn print_code(code: &Code, indent: &str) -> String {
+    print_code_annotated(code, indent, Annotations::default())
+}
+
+pub(super) fn print_code_annotated(code: &Code, indent: &str, annotations: Annotations) -> String {
+    print_code_ex(code, indent, annotations, &mut None)
+}
+
+/// Returns the instruction of the printed line without the offset and comments.
+pub(super) fn instruction_text(line: &str) -> &str {
+    let line = line.trim_start();
+    let line = match line.find(": ") {
+        Some(pos) if pos > 0 && line[..pos].bytes().all(|b| b.is_ascii_digit()) => &line[pos + 2..],
+        _ => line,
+    };
+    line.split(" ;;").next().unwrap()
+}
+
+fn print_code_ex(code: &Code, indent: &str, annotations: Annotations, cell: &mut Option<UInt256>) -> String {
+    let gas = annotations.gas;
+    let blank = if annotations.offsets { " ".repeat(OFFSET_WIDTH + 2) } else { String::new() };
     let mut disasm = String::new();
-    for insn in code {
-        disasm += indent;
+    // continuations being printed: the rest of the instructions, the indentation, the total gas
+    // and the end of the line of the instruction taking the continuation
+    let mut stack = vec![(code.iter(), indent.to_string(), 0, String::new())];
+    while let Some((insns, indent, total, _)) = stack.last_mut() {
+        let insn = match insns.next() {
+            Some(insn) => insn,
+            None => {
+                if gas {
+                    disasm += &format!("{}{};; total gas: {}\n", blank, indent, total);
+                }
+                let (_, _, _, end) = stack.pop().unwrap();
+                disasm += &end;
+                continue
+            }
+        };
+        let indent = indent.clone();
+        if annotations.offsets {
+            match insn.position() {
+                Some((hash, offset)) => {
+                    if cell.as_ref() != Some(hash) {
+                        disasm += &format!("{}{};; cell {}\n", blank, indent, hash.to_hex_string());
+                        *cell = Some(hash.clone());
+                    }
+                    disasm += &format!("{:>width$}: ", offset, width = OFFSET_WIDTH);
+                }
+                None => disasm += &blank,
+            }
+        }
+        disasm += &indent;
         disasm += insn.name();
         if insn.is_quiet() {
             disasm += "Q";
         }
-        let mut index = 0;
+        let mut nested = None;
         let len = insn.params().len();
         if len > 0 {
             disasm += " ";
         }
-        for param in insn.params() {
+        for (index, param) in insn.params().iter().enumerate() {
             let last = len == (index + 1);
             match param {
-                InstructionParameter::BigInteger(i) => {
-                    disasm += format!("{}", i).as_str();
-                }
-                InstructionParameter::ControlRegister(c) => {
-                    disasm += format!("c{}", c).as_str();
-                }
-                //InstructionParameter::DivisionMode(_) => {
-                //    todo!()
-                //}
-                InstructionParameter::Integer(i) => {
-                    disasm += format!("{}", i).as_str();
-                }
-                InstructionParameter::Length(l) => {
-                    disasm += format!("{}", l).as_str();
-                }
-                InstructionParameter::LengthAndIndex(l, i) => {
-                    disasm += format!("{}, {}", l, i).as_str();
-                }
-                InstructionParameter::Nargs(n) => {
-                    disasm += format!("{}", n).as_str();
-                }
-                InstructionParameter::Pargs(p) => {
-                    disasm += format!("{}", p).as_str();
-                }
-                InstructionParameter::Rargs(r) => {
-                    disasm += format!("{}", r).as_str();
-                }
-                InstructionParameter::Slice(s) => {
-                    disasm += format!("x{}", s.to_hex_string()).as_str();
-                }
-                InstructionParameter::StackRegister(r) => {
-                    disasm += format!("s{}", r).as_str();
-                }
-                InstructionParameter::StackRegisterPair(ra, rb) => {
-                    disasm += format!("s{}, s{}", ra, rb).as_str();
-                }
-                InstructionParameter::StackRegisterTriple(ra, rb, rc) => {
-                    disasm += format!("s{}, s{}, s{}", ra, rb, rc).as_str();
-                }
                 InstructionParameter::Code(code) => {
                     assert!(last, "code param isn't last");
                     disasm += "{\n";
-                    let inner_indent = String::from("  ") + indent;
-                    disasm += &print_code(code, inner_indent.as_str());
-                    disasm += indent;
-                    disasm += "}";
+                    nested = Some(code);
                 }
+                param => disasm += &print_param(param),
             }
             if !last {
                 disasm += ", ";
             }
-            index += 1;
         }
-        disasm += "\n";
+        let mut end = String::new();
+        if gas {
+            if let Some(insn_gas) = insn.gas() {
+                end += &format!(" ;; gas: {}", insn_gas);
+                *total += insn_gas;
+            }
+        }
+        if let Some(string) = debug_string(insn) {
+            end += &format!(" ;; debug: {:?}", string);
+        }
+        end += "\n";
+        match nested {
+            Some(code) => {
+                let end = format!("{}{}}}{}", blank, indent, end);
+                stack.push((code.iter(), String::from("  ") + &indent, 0, end));
+            }
+            None => disasm += &end,
+        }
     }
     disasm
 }
@@ -11,9 +11,18 @@
  * limitations under the License.
  */
 
-pub(crate) mod disasm;
+mod decompile;
+mod dedup;
+mod diff;
+pub mod disasm;
 mod handlers;
+mod lint;
 mod loader;
+mod stats;
 #[cfg(test)]
 mod tests;
 mod types;
+mod xref;
+
+pub use self::handlers::{Handlers, InstructionSet, LoadHandler, register_instruction_set};
+pub use self::types::{Instruction, InstructionParameter};
@@ -0,0 +1,99 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{HashMap, HashSet};
+use ton_types::{Cell, SliceData, UInt256};
+use super::types::{walk, Code};
+
+/// Number of continuations listed as the largest ones.
+const LARGEST_CONTINUATIONS: usize = 10;
+
+/// Code of a function with the cells and bits it takes.
+pub(super) struct FunctionStats<'a> {
+    pub label: &'a str,
+    pub code: &'a Code,
+    pub cells: usize,
+    pub bits: usize,
+}
+
+/// Counts bits of the slice and unique cells referenced by it with their bits.
+pub(super) fn slice_usage(slice: &SliceData) -> (usize, usize) {
+    let (mut cells, mut bits) = (0, slice.remaining_bits());
+    let mut visited: HashSet<UInt256> = HashSet::new();
+    let mut stack: Vec<Cell> = (0..slice.remaining_references()).map(|i| slice.reference(i).unwrap()).collect();
+    while let Some(cell) = stack.pop() {
+        if !visited.insert(cell.repr_hash()) {
+            continue
+        }
+        cells += 1;
+        bits += cell.bit_length();
+        stack.extend((0..cell.references_count()).map(|i| cell.reference(i).unwrap()));
+    }
+    (cells, bits)
+}
+
+/// Number of instructions of the code including nested continuations.
+pub(super) fn count_instructions(code: &Code) -> usize {
+    walk(code).count()
+}
+
+/// Adds instructions of the code and of its continuations to the histogram.
+pub(super) fn opcode_histogram(code: &Code, histogram: &mut HashMap<String, usize>) {
+    for (_, insn) in walk(code) {
+        let name = if insn.is_quiet() { format!("{}Q", insn.name()) } else { insn.name().to_string() };
+        *histogram.entry(name).or_insert(0) += 1;
+    }
+}
+
+/// Lists continuations nested in the code: the instruction holding the continuation
+/// and the number of instructions in it.
+pub(super) fn continuations(code: &Code, out: &mut Vec<(&'static str, usize)>) {
+    for (_, insn) in walk(code) {
+        if let Some(code) = insn.nested() {
+            out.push((insn.name(), count_instructions(code)));
+        }
+    }
+}
+
+/// Prints the total number of instructions, the histogram of opcodes, instructions,
+/// cells and bits of every function and the largest continuations.
+pub(super) fn stats_report(functions: &[FunctionStats]) -> String {
+    let mut histogram = HashMap::new();
+    let mut largest = vec![];
+    let mut total = 0;
+    let mut out = String::from("functions:\n");
+    for function in functions {
+        let count = count_instructions(function.code);
+        total += count;
+        opcode_histogram(function.code, &mut histogram);
+        let mut nested = vec![];
+        continuations(function.code, &mut nested);
+        largest.extend(nested.into_iter().map(|(insn, count)| (function.label, insn, count)));
+        out += &format!("  {}: {} instructions, {} cells, {} bits\n", function.label, count, function.cells, function.bits);
+    }
+
+    largest.sort_by(|a, b| b.2.cmp(&a.2));
+    out += "largest continuations:\n";
+    for (label, insn, count) in largest.iter().take(LARGEST_CONTINUATIONS) {
+        out += &format!("  {}: {} with {} instructions\n", label, insn, count);
+    }
+
+    let mut histogram: Vec<(String, usize)> = histogram.into_iter().collect();
+    histogram.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    out += "opcodes:\n";
+    for (name, count) in histogram {
+        out += &format!("  {:>6} {}\n", count, name);
+    }
+    out += &format!("instructions: {}\n", total);
+    out
+}
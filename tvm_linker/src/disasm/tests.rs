@@ -11,8 +11,19 @@
  * limitations under the License.
  */
 
-use ton_types::SliceData;
-use super::disasm::{disasm, print_tree_of_cells};
+use ton_types::{BuilderData, Cell, HashmapE, HashmapType, Result, SliceData};
+use super::disasm::{
+    annotate_function_ids, annotate_throws, code_json, code_tree, debug_strings, decode_boc, disasm, disasm_split,
+    disasm_text, print_tree_of_cells, read_boc,
+};
+use super::loader::{instruction_text, load, print_code_annotated, Annotations};
+use super::stats::{count_instructions, stats_report, FunctionStats};
+use super::xref::{annotate_calls, call_targets, xref_index};
+use super::decompile::decompile;
+use super::dedup::find_duplicates;
+use super::diff::diff_text;
+use super::lint::{lint, security_lint};
+use super::{register_instruction_set, Handlers, Instruction, InstructionParameter, InstructionSet};
 
 fn round_trip_test(raw0: &str, check_bin: bool) {
     let bin0 = base64::decode(raw0).unwrap();
@@ -53,3 +64,350 @@ fn round_trip() {
         round_trip_test(&raw, true);
     }
 }
+
+struct TestExtension;
+
+fn load_test_ext(slice: &mut SliceData) -> Result<Instruction> {
+    slice.get_next_int(16)?;
+    let n = slice.get_next_int(8)? as usize;
+    Ok(Instruction::new("TESTEXT").with_param(InstructionParameter::Length(n)))
+}
+
+impl InstructionSet for TestExtension {
+    fn register(&self, handlers: &mut Handlers) -> std::result::Result<(), String> {
+        handlers.register_opcode(&[0xF9, 0x80], load_test_ext)
+    }
+}
+
+#[test]
+fn instruction_set_plugin() {
+    let code = BuilderData::with_raw(vec![0xF9, 0x80, 0x05, 0x00], 32).unwrap().into_cell().unwrap();
+    assert!(register_instruction_set(Box::new(TestExtension)).is_ok());
+    assert_eq!(disasm(&mut SliceData::from(code)), "TESTEXT 5\nNOP\n");
+    assert!(register_instruction_set(Box::new(TestExtension)).is_err());
+}
+
+#[test]
+fn throw_annotation() {
+    let mut errors = std::collections::HashMap::new();
+    errors.insert(101, "NotOwner".to_owned());
+    let code = "PUSHINT 101\nTHROWIFNOT 101\n  THROW 102\n".to_owned();
+    assert_eq!(annotate_throws(code, &errors), "PUSHINT 101\nTHROWIFNOT 101 ;; NotOwner\n  THROW 102\n");
+}
+
+#[test]
+fn cell_tree() {
+    let child = BuilderData::with_raw(vec![0xA0], 8).unwrap().into_cell().unwrap();
+    let mut root = BuilderData::with_raw(vec![0x71, 0xA0], 16).unwrap();
+    root.checked_append_reference(child.clone()).unwrap();
+    root.checked_append_reference(child.clone()).unwrap();
+    let root = root.into_cell().unwrap();
+    let (root_hash, child_hash) = (root.repr_hash().to_hex_string(), child.repr_hash().to_hex_string());
+    assert_eq!(code_tree(&root), format!(
+        "└ {} bits: 16, refs: 2\n    PUSHINT 1\n    ADD\n  ├ {} bits: 8, refs: 0\n  │   ADD\n  └ {} (see above)\n",
+        &root_hash[..8], &child_hash[..8], &child_hash[..8]
+    ));
+}
+
+#[test]
+fn gas_lint() {
+    let code = ton_labs_assembler::compile_code_to_cell("PUSHINT 1\nPUSHINT 2\nADD\nPUSHINT 5\nMUL\n\
+        PUSHROOT\nCTOS\nPUSHROOT\nCTOS\nPUSHCONT {\n  DICTUGET\n}\nREPEAT\nPUSHCONT {\n  DICTUSET\n}\nIF").unwrap();
+    let code = format!(";; function id 0x1\n{}", disasm(&mut SliceData::from(code)));
+    let findings: Vec<(usize, i64)> = lint(&code).iter().map(|f| (f.line, f.savings.unwrap())).collect();
+    assert_eq!(findings, vec![(2, 36), (5, 10), (9, 25), (12, 100)]);
+}
+
+#[test]
+fn duplicate_cells() {
+    // root with two references to identical cells stored separately
+    let boc = [
+        0xb5, 0xee, 0x9c, 0x72, 0x01, 0x01, 0x03, 0x01, 0x00, 0x0b, 0x00,
+        0x02, 0x02, 0xaa, 0x01, 0x02,
+        0x00, 0x01, 0xc0,
+        0x00, 0x01, 0xc0,
+    ];
+    let root = ton_types::deserialize_tree_of_cells(&mut std::io::Cursor::new(&boc[..])).unwrap();
+    let duplicates = find_duplicates(&[root.clone()]);
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0].hash, root.reference(0).unwrap().repr_hash());
+    assert_eq!((duplicates[0].copies, duplicates[0].bits, duplicates[0].refs), (2, 1, 0));
+
+    let shared = ton_types::serialize_toc(&root).unwrap();
+    assert!(shared.len() < boc.len());
+    let root = ton_types::deserialize_tree_of_cells(&mut std::io::Cursor::new(shared)).unwrap();
+    assert!(find_duplicates(&[root]).is_empty());
+}
+
+#[test]
+fn security_lint_findings() {
+    let code = ";; solidity selector detected\n;; external entry\nACCEPT\nCHKSIGNU\n\
+        ;; function id 0x1\nPUSHCONT {\n  PUSHINT 128\n  SENDRAWMSG\n}\nREPEAT\nSETCODE\n\
+        ;; function id 0x2\nCHKSIGNU\nTHROWIFNOT 40\nSETCODE\n";
+    let lines: Vec<usize> = security_lint(code).iter().map(|f| f.line).collect();
+    assert_eq!(lines, vec![2, 8, 11]);
+
+    // no signature check at all, a check in another branch does not guard SETCODE
+    let code = ton_labs_assembler::compile_code_to_cell("ACCEPT\nPUSHCONT {\n  THROWIF 40\n}\nIF\nSETCODE\nTHROWIFNOT 52").unwrap();
+    let code = format!(";; solidity selector detected\n;; external entry\n{}", disasm(&mut SliceData::from(code)));
+    let lines: Vec<usize> = security_lint(&code).iter().map(|f| f.line).collect();
+    assert_eq!(lines, vec![3, 8]);
+}
+
+/// Code with fun-c selector and the functions in c3 dictionary, the internal entry has id 0.
+fn func_contract(functions: &[(usize, &str)]) -> Cell {
+    let mut dict = HashmapE::with_bit_len(19);
+    for (id, code) in functions {
+        let key = BuilderData::new().append_bits(*id, 19).unwrap().clone().into_cell().unwrap();
+        let code = ton_labs_assembler::compile_code_to_cell(code).unwrap();
+        dict.set(SliceData::from(key), &SliceData::from(code)).unwrap();
+    }
+    let mut root = BuilderData::with_raw(hex::decode("ff00f4a413f4bcf2c80b").unwrap(), 80).unwrap();
+    root.checked_append_reference(dict.data().unwrap().clone()).unwrap();
+    root.into_cell().unwrap()
+}
+
+#[test]
+fn split_output() {
+    // the internal entry and one function in c3 dictionary
+    let root = func_contract(&[(0, "PUSHINT 1"), (7, "PUSHINT 2")]);
+    let dir = std::env::temp_dir().join("tvm_linker_split_output");
+    let mut names = std::collections::HashMap::new();
+    names.insert(7, "transfer".to_owned());
+    disasm_split(&root, &names, &std::collections::HashMap::new(), Annotations::default(), &dir).unwrap();
+    assert_eq!(std::fs::read_to_string(dir.join("index.txt")).unwrap(),
+        ";; fun-c selector detected\ninternal_entry.code ;; internal entry\n0x7.code ;; function id 0x7\n");
+    assert_eq!(std::fs::read_to_string(dir.join("0x7.code")).unwrap(), ";; function id 0x7\nPUSHINT 2\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn debug_instructions() {
+    // LOGSTR "abc", DUMPTOSFMT "x=", a string with unknown mode 2 and LOGFLUSH
+    let code = BuilderData::with_raw(hex::decode("fef300616263fef1783dfef10241fef000").unwrap(), 136).unwrap().into_cell().unwrap();
+    let text = disasm(&mut SliceData::from(code.clone()));
+    assert_eq!(text, "LOGSTR x616263 ;; debug: \"abc\"\nDUMPTOSFMT x783d ;; debug: \"x=\"\nDEBUGSTR x0241\nLOGFLUSH\n");
+    assert_eq!(debug_strings("function id 0x1", &load(&mut SliceData::from(code)).unwrap()),
+        vec!["function id 0x1, offset 0: \"abc\"".to_string(), "function id 0x1, offset 48: \"x=\"".to_string()]);
+}
+
+#[test]
+fn json_output() {
+    // PUSHCONT { INC } DROP
+    let code = BuilderData::with_raw(vec![0x91, 0xa4, 0x30], 24).unwrap().into_cell().unwrap();
+    let mut insns = vec![];
+    code_json(&load(&mut SliceData::from(code.clone())).unwrap(), 0, &mut insns);
+    let fields: Vec<(&str, u64)> = insns.iter()
+        .map(|insn| (insn["name"].as_str().unwrap(), insn["level"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(fields, vec![("PUSHCONT", 0), ("INC", 1), ("DROP", 0)]);
+    assert_eq!(insns[0]["offset"], 0);
+    assert_eq!(insns[2]["offset"], 16);
+    assert_eq!(insns[2]["cell"], code.repr_hash().to_hex_string());
+}
+
+#[test]
+fn gas_annotation() {
+    // PUSHCONT { INC } DROP
+    let code = BuilderData::with_raw(vec![0x91, 0xa4, 0x30], 24).unwrap().into_cell().unwrap();
+    let annotations = Annotations { gas: true, offsets: false };
+    let text = print_code_annotated(&load(&mut SliceData::from(code)).unwrap(), "", annotations);
+    assert_eq!(text, "PUSHCONT {\n  INC ;; gas: 18\n  ;; total gas: 18\n} ;; gas: 26\nDROP ;; gas: 18\n;; total gas: 44\n");
+}
+
+#[test]
+fn function_id_names() {
+    let mut names = std::collections::HashMap::new();
+    names.insert(0x1234u32, "transfer".to_string());
+    let code = "PUSHINT 4660\nPUSHINT 2147488308\nPUSHINT 4661\nCALLDICT 4660\n".to_string();
+    assert_eq!(annotate_function_ids(code, &names),
+        "PUSHINT 4660 ;; transfer\nPUSHINT 2147488308 ;; transfer answer\nPUSHINT 4661\nCALLDICT 4660\n");
+}
+
+#[test]
+fn boc_encodings() {
+    let cell = BuilderData::with_raw(vec![0x71, 0xa0], 16).unwrap().into_cell().unwrap();
+    let boc = ton_types::serialize_toc(&cell).unwrap();
+    assert_eq!(decode_boc(boc.clone()).unwrap(), boc);
+    assert_eq!(decode_boc(hex::encode(&boc).into_bytes()).unwrap(), boc);
+    assert_eq!(decode_boc(format!("{}\n", base64::encode(&boc)).into_bytes()).unwrap(), boc);
+    assert!(decode_boc(b"not a boc".to_vec()).is_err());
+    assert_eq!(read_boc(&hex::encode(&boc)).unwrap()[0].repr_hash(), cell.repr_hash());
+    assert_eq!(read_boc("missing/contract").unwrap_err(), "file missing/contract not found");
+    assert_eq!(read_boc("contract.tvc").unwrap_err(), "file contract.tvc not found");
+}
+
+#[test]
+fn exotic_cells() {
+    let mut data = vec![2];
+    data.extend_from_slice(&[0xab; 32]);
+    let mut library = BuilderData::with_raw(data, 264).unwrap();
+    library.set_type(ton_types::CellType::LibraryReference);
+    let library = library.into_cell().unwrap();
+    // INC followed by the implicit jump to the library cell
+    let mut code = BuilderData::with_raw(vec![0xa4], 8).unwrap();
+    code.checked_append_reference(library).unwrap();
+    let text = disasm(&mut SliceData::from(code.into_cell().unwrap()));
+    assert_eq!(text, format!("INC\n;; library {}\n", "ab".repeat(32)));
+}
+
+#[test]
+fn code_stats() {
+    // PUSHCONT { INC INC } DROP DROP
+    let code = BuilderData::with_raw(vec![0x92, 0xa4, 0xa4, 0x30, 0x30], 40).unwrap().into_cell().unwrap();
+    let code = load(&mut SliceData::from(code)).unwrap();
+    let stats = [FunctionStats { label: "function id 0x1", code: &code, cells: 1, bits: 40 }];
+    assert_eq!(stats_report(&stats), "\
+functions:
+  function id 0x1: 5 instructions, 1 cells, 40 bits
+largest continuations:
+  function id 0x1: PUSHCONT with 2 instructions
+opcodes:
+       2 DROP
+       2 INC
+       1 PUSHCONT
+instructions: 5
+");
+}
+
+#[test]
+fn decompiler() {
+    let code = ton_labs_assembler::compile_code_to_cell("
+        PUSH s0
+        PUSHINT 10
+        GREATER
+        THROWIFNOT 100
+        ACCEPT
+        GETGLOB 10
+        ADD
+        SETGLOB 10
+        NOW
+        PUSHCONT {
+            PUSHINT 1
+            SETGLOB 11
+        }
+        IF
+        DEPTH
+    ").unwrap();
+    let code = load(&mut SliceData::from(code)).unwrap();
+    assert_eq!(decompile(&code), "    require((arg0 > 10), 100);
+    accept();
+    g10 = (arg0 + g10);
+    if (now()) {
+        g11 = 1;
+    }
+    asm(\"DEPTH\");
+");
+}
+
+#[test]
+fn cross_references() {
+    let main = ton_labs_assembler::compile_code_to_cell("CALL 1\nPUSHCONT { CALL 2 }\nIF\nCALL 1").unwrap();
+    let main = load(&mut SliceData::from(main)).unwrap();
+    assert_eq!(call_targets(&main), vec![1, 2]);
+
+    let mut ids = std::collections::HashMap::new();
+    ids.insert(1, "0x1".to_string());
+    ids.insert(3, "main".to_string());
+    assert_eq!(annotate_calls("CALL 1\nCALL 2\nPUSHINT 1\n".to_string(), &ids), "CALL 1 ;; 0x1\nCALL 2\nPUSHINT 1\n");
+    let calls = vec![("main", vec![1, 2]), ("0x1", vec![])];
+    assert_eq!(xref_index(&calls, &ids), "
+;; cross references
+;; main: calls 0x1, 2 (not found)
+;; 0x1: called by main
+");
+}
+
+#[test]
+fn cross_reference_index() {
+    // the internal entry calling a function of c3 dictionary
+    let root = func_contract(&[(0, "CALL 7"), (7, "PUSHINT 2")]);
+    let names = std::collections::HashMap::new();
+    let errors = std::collections::HashMap::new();
+    let text = disasm_text(&root, &names, &errors, Annotations::default(), false).unwrap();
+    assert!(text.contains("CALL 7 ;; 0x7\n"));
+    assert!(!text.contains(";; cross references"));
+    let text = disasm_text(&root, &names, &errors, Annotations::default(), true).unwrap();
+    assert!(text.ends_with(";; cross references\n;; internal_entry: calls 0x7\n;; 0x7: called by internal_entry\n"));
+}
+
+#[test]
+fn recent_instructions() {
+    let bytes = hex::decode("f82af82bf82cf82dfb0af90400f912f915").unwrap();
+    let code = BuilderData::with_raw(bytes, 136).unwrap().into_cell().unwrap();
+    assert_eq!(disasm(&mut SliceData::from(code)),
+        "MYCODE\nINITCODEHASH\nSTORAGEFEES\nSEQNO\nCOPYLEFT\nHASHEXT 0\nECRECOVER\nP256_CHKSIGNS\n");
+}
+
+#[test]
+fn long_reference_chain() {
+    // INC in every cell followed by the implicit jump to the next one
+    let mut cell = BuilderData::with_raw(vec![0xa4], 8).unwrap().into_cell().unwrap();
+    for _ in 1..10000 {
+        let mut builder = BuilderData::with_raw(vec![0xa4], 8).unwrap();
+        builder.checked_append_reference(cell).unwrap();
+        cell = builder.into_cell().unwrap();
+    }
+    assert_eq!(code_tree(&cell).lines().count(), 20000);
+    assert_eq!(disasm(&mut SliceData::from(cell)).lines().count(), 10000);
+}
+
+#[test]
+fn deeply_nested_continuations() {
+    // PUSHREFCONT CALLX in every cell, the next cell is the body of the continuation
+    let depth = 3000;
+    let mut cell = BuilderData::with_raw(vec![0xa4], 8).unwrap().into_cell().unwrap();
+    for _ in 1..depth {
+        let mut builder = BuilderData::with_raw(vec![0x8a, 0xd8], 16).unwrap();
+        builder.checked_append_reference(cell).unwrap();
+        cell = builder.into_cell().unwrap();
+    }
+    assert_eq!(disasm(&mut SliceData::from(cell.clone())).lines().count(), 3 * depth - 2);
+    let code = load(&mut SliceData::from(cell)).unwrap();
+    assert_eq!(count_instructions(&code), 2 * depth - 1);
+    let mut insns = vec![];
+    code_json(&code, 0, &mut insns);
+    assert_eq!(insns.iter().filter_map(|insn| insn["level"].as_u64()).max(), Some(depth as u64 - 1));
+    let text = decompile(&code);
+    assert_eq!(text.matches("call {").count(), depth - 1);
+    assert!(text.contains(&format!("\n{}call {{\n", "    ".repeat(depth - 1))));
+    assert!(text.ends_with("\n    // stack: (arg0 + 1)\n"));
+}
+
+#[test]
+fn offset_annotations() {
+    // PUSHCONT { INC } DROP and the implicit jump to INC in the next cell
+    let next = BuilderData::with_raw(vec![0xa4], 8).unwrap().into_cell().unwrap();
+    let mut code = BuilderData::with_raw(vec![0x91, 0xa4, 0x30], 24).unwrap();
+    code.checked_append_reference(next.clone()).unwrap();
+    let code = code.into_cell().unwrap();
+    let annotations = Annotations { gas: false, offsets: true };
+    let text = print_code_annotated(&load(&mut SliceData::from(code.clone())).unwrap(), "", annotations);
+    assert_eq!(text, format!("       ;; cell {}
+    0: PUSHCONT {{
+    8:   INC
+       }}
+   16: DROP
+       ;; cell {}
+    0: INC
+", code.repr_hash().to_hex_string(), next.repr_hash().to_hex_string()));
+    assert_eq!(instruction_text("   16: THROW 52 ;; NotOwner"), "THROW 52");
+}
+
+#[test]
+fn code_diff() {
+    let old = "PUSHINT 1\nADD\nPUSHCONT {\n  INC\n}\nIF\n";
+    let new = "PUSHINT 2\nADD\nPUSHCONT {\n  DEC\n}\nIF\nRET\n";
+    assert_eq!(diff_text(old, new), "\
+~ PUSHINT 2 ;; was PUSHINT 1
+  ADD
+  PUSHCONT {
+-   INC
++   DEC
+  }
+  IF
++ RET
+;; 2 added, 1 removed, 1 changed
+");
+    assert_eq!(diff_text(old, old).lines().last(), Some(";; 0 added, 0 removed, 0 changed"));
+}
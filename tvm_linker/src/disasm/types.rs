@@ -12,7 +12,7 @@
  */
 
 use std::collections::HashMap;
-use ton_types::{Cell, Result, /*Bitmask,*/ SliceData, fail};
+use ton_types::{Cell, Result, /*Bitmask,*/ SliceData, UInt256, fail};
 
 pub type Code = Vec<Instruction>;
 
@@ -21,17 +21,38 @@ pub struct Instruction {
     name: &'static str,
     params: Vec<InstructionParameter>,
     quiet: bool,
+    /// hash of the code cell and bit offset of the instruction in it
+    position: Option<(UInt256, usize)>,
+    /// bits and references taken by the instruction with its immediate arguments
+    length: Option<(usize, usize)>,
+    /// continuations taken by the instruction which are not decoded yet with
+    /// the indexes of their parameters
+    bodies: Vec<(usize, SliceData)>,
 }
 
 impl Instruction {
     pub fn new(name: &'static str) -> Self {
-        Self { name, params: vec!(), quiet: false }
+        Self { name, params: vec!(), quiet: false, position: None, length: None, bodies: vec!() }
     }
     pub fn with_param(self, param: InstructionParameter) -> Self {
         let mut clone = self;
         clone.params.push(param);
         clone
     }
+    /// Adds the continuation parameter, its code is decoded later by the loader
+    /// to keep the native stack flat for deeply nested continuations.
+    pub(super) fn with_body(self, body: SliceData) -> Self {
+        let mut clone = self;
+        clone.bodies.push((clone.params.len(), body));
+        clone.params.push(InstructionParameter::Code(Code::new()));
+        clone
+    }
+    pub(super) fn take_bodies(&mut self) -> Vec<(usize, SliceData)> {
+        std::mem::replace(&mut self.bodies, vec!())
+    }
+    pub(super) fn set_code(&mut self, index: usize, code: Code) {
+        self.params[index] = InstructionParameter::Code(code);
+    }
     pub fn set_quiet(self) -> Self {
         let mut clone = self;
         clone.quiet = true;
@@ -43,9 +64,85 @@ impl Instruction {
     pub fn params(&self) -> &Vec<InstructionParameter> {
         &self.params
     }
+    /// Code of the continuation taken by the instruction.
+    pub fn nested(&self) -> Option<&Code> {
+        self.params.iter().filter_map(|param| match param {
+            InstructionParameter::Code(code) => Some(code),
+            _ => None,
+        }).next()
+    }
     pub fn is_quiet(&self) -> bool {
         self.quiet
     }
+    pub fn with_position(self, cell: UInt256, offset: usize) -> Self {
+        let mut clone = self;
+        clone.position = Some((cell, offset));
+        clone
+    }
+    pub fn position(&self) -> Option<&(UInt256, usize)> {
+        self.position.as_ref()
+    }
+    pub fn with_length(self, bits: usize, refs: usize) -> Self {
+        let mut clone = self;
+        clone.length = Some((bits, refs));
+        clone
+    }
+    /// Basic gas price of the instruction: 10 + b + 5r, where b is the length of the
+    /// instruction in bits and r is the number of references it takes.
+    /// Cell loads, implicit jumps and costs depending on the stack are not included.
+    pub fn gas(&self) -> Option<usize> {
+        self.length.map(|(bits, refs)| 10 + bits + 5 * refs)
+    }
+}
+
+impl Drop for Instruction {
+    fn drop(&mut self) {
+        // nested continuations are dropped one by one instead of recursively
+        fn take_code(params: &mut Vec<InstructionParameter>, codes: &mut Vec<Code>) {
+            for param in params {
+                if let InstructionParameter::Code(code) = param {
+                    codes.push(std::mem::replace(code, Code::new()));
+                }
+            }
+        }
+        let mut codes = vec!();
+        take_code(&mut self.params, &mut codes);
+        while let Some(code) = codes.pop() {
+            for mut insn in code {
+                take_code(&mut insn.params, &mut codes);
+            }
+        }
+    }
+}
+
+/// Instructions of the code and of its continuations in the printed order with their
+/// nesting levels, continuations are walked with an explicit stack.
+pub(super) struct Walk<'a> {
+    stack: Vec<std::slice::Iter<'a, Instruction>>,
+}
+
+pub(super) fn walk(code: &Code) -> Walk {
+    Walk { stack: vec!(code.iter()) }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (usize, &'a Instruction);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let level = self.stack.len().checked_sub(1)?;
+            match self.stack[level].next() {
+                Some(insn) => {
+                    if let Some(code) = insn.nested() {
+                        self.stack.push(code.iter());
+                    }
+                    return Some((level, insn))
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -64,6 +161,7 @@ pub enum InstructionParameter {
     StackRegisterPair(isize, isize),
     StackRegisterTriple(isize, isize, isize),
     Code(Code),
+    Hash(UInt256),
 }
 
 // #[derive(Clone, Debug)]
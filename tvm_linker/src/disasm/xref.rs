@@ -0,0 +1,86 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use super::loader::instruction_text;
+use super::types::{walk, Code, InstructionParameter};
+
+/// Instructions calling or jumping to a function of the c3 dictionary by its id.
+const CALLS: [&str; 3] = ["CALL", "JMPDICT", "PREPARE"];
+
+/// Ids of functions called by the code and its continuations, each listed once.
+pub(super) fn call_targets(code: &Code) -> Vec<isize> {
+    let mut targets = vec![];
+    for (_, insn) in walk(code) {
+        if !CALLS.contains(&insn.name()) {
+            continue
+        }
+        for param in insn.params() {
+            if let InstructionParameter::Nargs(id) = param {
+                if !targets.contains(id) {
+                    targets.push(*id);
+                }
+            }
+        }
+    }
+    targets
+}
+
+/// Appends names of called functions to CALL, JMPDICT and PREPARE instructions.
+pub(super) fn annotate_calls(code: String, functions: &HashMap<isize, String>) -> String {
+    if functions.is_empty() {
+        return code
+    }
+    code.lines().map(|line| {
+        let mut words = instruction_text(line).split(' ');
+        let name = match (words.next(), words.next(), words.next()) {
+            (Some(insn), Some(arg), None) if CALLS.contains(&insn) =>
+                isize::from_str(arg).ok().and_then(|id| functions.get(&id)),
+            _ => None,
+        };
+        match name {
+            Some(name) => format!("{} ;; {}\n", line, name),
+            None => format!("{}\n", line),
+        }
+    }).collect()
+}
+
+/// Lists callees and callers of every function taking part in calls,
+/// ids missing from the dictionary are shown as is.
+pub(super) fn xref_index(calls: &[(&str, Vec<isize>)], functions: &HashMap<isize, String>) -> String {
+    let name = |id: &isize| functions.get(id).cloned().unwrap_or_else(|| format!("{} (not found)", id));
+    let mut callers: HashMap<String, Vec<&str>> = HashMap::new();
+    for (caller, targets) in calls {
+        for target in targets {
+            callers.entry(name(target)).or_insert_with(Vec::new).push(*caller);
+        }
+    }
+    let mut out = String::new();
+    for (function, targets) in calls {
+        let mut refs = vec![];
+        if !targets.is_empty() {
+            refs.push(format!("calls {}", targets.iter().map(|id| name(id)).collect::<Vec<_>>().join(", ")));
+        }
+        if let Some(callers) = callers.get(*function) {
+            refs.push(format!("called by {}", callers.join(", ")));
+        }
+        if !refs.is_empty() {
+            out += &format!(";; {}: {}\n", function, refs.join("; "));
+        }
+    }
+    if out.is_empty() {
+        return out
+    }
+    format!("\n;; cross references\n{}", out)
+}
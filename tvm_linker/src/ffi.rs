@@ -0,0 +1,159 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+//! C interface to the linker. Strings returned by the functions are allocated by
+//! the library and must be released with `tvm_linker_string_free`. On failure
+//! functions return null and the error can be read with `tvm_linker_last_error`.
+
+use abi_json::json_abi::encode_function_call;
+use disasm::disasm::disasm_ex;
+use ed25519_dalek::Keypair;
+use program::load_from_bytes;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io::Cursor;
+use std::os::raw::c_char;
+use std::ptr;
+use ton_types::cells_serialization::{deserialize_cells_tree, serialize_tree_of_cells};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn to_c_string(result: Result<String, String>) -> *mut c_char {
+    let result = result.and_then(|s| CString::new(s).map_err(|e| format!("Failed to convert result: {}", e)));
+    match result {
+        Ok(s) => s.into_raw(),
+        Err(e) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(e).ok());
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn from_c_str(s: *const c_char, name: &str) -> Result<String, String> {
+    if s.is_null() {
+        return Err(format!("{} is null", name))
+    }
+    CStr::from_ptr(s).to_str()
+        .map(|s| s.to_owned())
+        .map_err(|e| format!("{} is not valid utf-8: {}", name, e))
+}
+
+unsafe fn from_buffer(data: *const u8, len: usize) -> Result<Vec<u8>, String> {
+    if data.is_null() {
+        return Err("buffer is null".to_owned())
+    }
+    Ok(std::slice::from_raw_parts(data, len).to_vec())
+}
+
+/// Returns the error of the last failed call in the current thread or null.
+/// The string is owned by the library and is valid until the next failed call.
+#[no_mangle]
+pub extern "C" fn tvm_linker_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map(|e| e.as_ptr()).unwrap_or(ptr::null()))
+}
+
+/// Releases a string returned by the library.
+#[no_mangle]
+pub unsafe extern "C" fn tvm_linker_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Disassembles code in the root cell of a bag of cells.
+#[no_mangle]
+pub unsafe extern "C" fn tvm_linker_disassemble(boc: *const u8, len: usize) -> *mut c_char {
+    to_c_string(from_buffer(boc, len).and_then(|bytes| {
+        let mut roots = deserialize_cells_tree(&mut Cursor::new(bytes))
+            .map_err(|e| format!("Failed to deserialize cells: {}", e))?;
+        if roots.is_empty() {
+            return Err("bag of cells has no roots".to_owned())
+        }
+        disasm_ex(&mut roots.remove(0).into())
+    }))
+}
+
+/// Computes the address of a contract from its tvc file contents.
+/// Returns the address in raw form `wc:hash`.
+#[no_mangle]
+pub unsafe extern "C" fn tvm_linker_tvc_address(tvc: *const u8, len: usize, wc: i8) -> *mut c_char {
+    to_c_string(from_buffer(tvc, len).and_then(|bytes| {
+        let state = load_from_bytes(bytes)?;
+        let hash = state.hash().map_err(|e| format!("Failed to calculate hash: {}", e))?;
+        Ok(format!("{}:{:x}", wc, hash))
+    }))
+}
+
+/// Encodes a call of the ABI function into a message body and returns it as base64
+/// encoded bag of cells. `header` and `keypair` may be null. `keypair` is 64 bytes of
+/// the secret key followed by the public key.
+#[no_mangle]
+pub unsafe extern "C" fn tvm_linker_encode_call(
+    abi_json: *const c_char,
+    method: *const c_char,
+    params: *const c_char,
+    header: *const c_char,
+    keypair: *const u8,
+    internal: bool,
+) -> *mut c_char {
+    let result = || -> Result<String, String> {
+        let abi_json = from_c_str(abi_json, "abi_json")?;
+        let method = from_c_str(method, "method")?;
+        let params = from_c_str(params, "params")?;
+        let header = if header.is_null() { None } else { Some(from_c_str(header, "header")?) };
+        let keypair = if keypair.is_null() {
+            None
+        } else {
+            Some(Keypair::from_bytes(std::slice::from_raw_parts(keypair, 64))
+                .map_err(|e| format!("Failed to load keypair: {}", e))?)
+        };
+        let body = encode_function_call(abi_json, method, header, params, internal, keypair.as_ref())
+            .map_err(|e| format!("cannot encode abi body: {:?}", e))?
+            .into_cell()
+            .map_err(|e| format!("Failed to pack body in cell: {}", e))?;
+        let mut bytes = vec![];
+        serialize_tree_of_cells(&body, &mut bytes)
+            .map_err(|e| format!("Failed to serialize body: {}", e))?;
+        Ok(base64::encode(&bytes))
+    };
+    to_c_string(result())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ton_types::BuilderData;
+
+    fn disassemble(code: Vec<u8>, bits: usize) -> Result<String, String> {
+        let cell = BuilderData::with_raw(code, bits).unwrap().into_cell().unwrap();
+        let mut boc = vec![];
+        serialize_tree_of_cells(&cell, &mut boc).unwrap();
+        unsafe {
+            let text = tvm_linker_disassemble(boc.as_ptr(), boc.len());
+            if text.is_null() {
+                return Err(CStr::from_ptr(tvm_linker_last_error()).to_str().unwrap().to_owned())
+            }
+            let result = CStr::from_ptr(text).to_str().unwrap().to_owned();
+            tvm_linker_string_free(text);
+            Ok(result)
+        }
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble(vec![0xa4], 8), Ok("INC\n".to_owned()));
+        // SETCP with an unknown code page
+        assert!(disassemble(vec![0xff, 0x01], 16).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use abi::{build_abi_body, load_abi_contract, load_abi_json_string};
+use abi_json::{Param, ParamType};
+use clap::ArgMatches;
+use keyman::KeypairManager;
+use program::{load_from_file, get_now};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+use testcall::{call_contract_ex, ExecutionOptions, MsgInfo, TraceLevel};
+use ton_block::MsgAddressInt;
+use ton_types::{BuilderData, SliceData};
+use ton_types::cells_serialization::serialize_tree_of_cells;
+
+const DEFAULT_ITERATIONS: usize = 100;
+const MAX_ARRAY_LEN: usize = 4;
+const MAX_BYTES_LEN: usize = 64;
+
+/// Standard VM exceptions which are not expected to be thrown by a correct contract.
+fn is_unexpected_exception(exit_code: i32) -> bool {
+    (exit_code >= 2 && exit_code <= 14 && exit_code != 13) || exit_code == -1
+}
+
+fn is_out_of_gas(exit_code: i32) -> bool {
+    exit_code == 13 || exit_code == -14
+}
+
+fn random_uint(rng: &mut StdRng, bits: usize) -> num::BigUint {
+    let max = (num::BigUint::from(1u8) << bits) - num::BigUint::from(1u8);
+    match rng.gen_range(0, 4) {
+        0 => num::BigUint::from(0u8),
+        1 => num::BigUint::from(1u8),
+        2 => max,
+        _ => {
+            let mut bytes = vec![0u8; (bits + 7) / 8];
+            rng.fill(&mut bytes[..]);
+            num::BigUint::from_bytes_be(&bytes) % (max + num::BigUint::from(1u8))
+        }
+    }
+}
+
+fn random_int(rng: &mut StdRng, bits: usize) -> num::BigInt {
+    let half = num::BigInt::from(1u8) << (bits - 1);
+    match rng.gen_range(0, 5) {
+        0 => num::BigInt::from(0),
+        1 => num::BigInt::from(-1),
+        2 => -half,
+        3 => half - num::BigInt::from(1),
+        _ => {
+            let value = num::BigInt::from(random_uint(rng, bits - 1));
+            if rng.gen() { -value } else { value }
+        }
+    }
+}
+
+fn random_bytes(rng: &mut StdRng, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rng.fill(&mut bytes[..]);
+    bytes
+}
+
+fn random_cell(rng: &mut StdRng) -> Result<String, String> {
+    let len = rng.gen_range(0, 128);
+    let cell = BuilderData::with_raw(random_bytes(rng, len), len * 8)
+        .map_err(|e| format!("Failed to create builder: {}", e))?
+        .into_cell()
+        .map_err(|e| format!("Failed to convert builder to cell: {}", e))?;
+    let mut bytes = vec![];
+    serialize_tree_of_cells(&cell, &mut bytes)
+        .map_err(|e| format!("Failed to serialize cell: {}", e))?;
+    Ok(base64::encode(&bytes))
+}
+
+/// Generates a random value of the ABI type in json format accepted by the ABI encoder.
+/// Boundary values are preferred for numbers.
+fn random_value(rng: &mut StdRng, kind: &ParamType) -> Result<Value, String> {
+    Ok(match kind {
+        ParamType::Uint(bits) => Value::String(random_uint(rng, *bits).to_string()),
+        ParamType::Int(bits) => Value::String(random_int(rng, *bits).to_string()),
+        ParamType::Token => Value::String(rng.gen::<u64>().to_string()),
+        ParamType::Bool => Value::Bool(rng.gen()),
+        ParamType::Address => {
+            let wc = if rng.gen_range(0, 4) == 0 { -1 } else { 0 };
+            Value::String(format!("{}:{}", wc, hex::encode(random_bytes(rng, 32))))
+        }
+        ParamType::Bytes => {
+            let len = rng.gen_range(0, MAX_BYTES_LEN);
+            Value::String(hex::encode(random_bytes(rng, len)))
+        }
+        ParamType::FixedBytes(len) => Value::String(hex::encode(random_bytes(rng, *len))),
+        ParamType::Cell => Value::String(random_cell(rng)?),
+        ParamType::Map(_, _) => Value::Object(Map::new()),
+        ParamType::Array(item) => {
+            let len = rng.gen_range(0, MAX_ARRAY_LEN + 1);
+            Value::Array((0..len).map(|_| random_value(rng, item)).collect::<Result<_, _>>()?)
+        }
+        ParamType::FixedArray(item, len) => {
+            Value::Array((0..*len).map(|_| random_value(rng, item)).collect::<Result<_, _>>()?)
+        }
+        ParamType::Tuple(params) => Value::Object(random_params(rng, params)?),
+        kind => return Err(format!("fuzzing of {} parameters is not supported", kind.type_signature())),
+    })
+}
+
+fn random_params(rng: &mut StdRng, params: &Vec<Param>) -> Result<Map<String, Value>, String> {
+    let mut values = Map::new();
+    for param in params {
+        values.insert(param.name.clone(), random_value(rng, &param.kind)?);
+    }
+    Ok(values)
+}
+
+pub fn fuzz_command(matches: &ArgMatches) -> Result<(), String> {
+    let input = matches.value_of("INPUT").unwrap();
+    let abi_file = matches.value_of("ABI_JSON").unwrap();
+    let iterations = matches.value_of("ITERATIONS")
+        .map(|v| usize::from_str(v))
+        .transpose()
+        .map_err(|e| format!("cannot parse iterations: {}", e))?
+        .unwrap_or(DEFAULT_ITERATIONS);
+    let gas_budget = matches.value_of("GAS_BUDGET")
+        .map(|v| i64::from_str(v))
+        .transpose()
+        .map_err(|e| format!("cannot parse gas budget: {}", e))?;
+    let seed = matches.value_of("SEED")
+        .map(|v| u64::from_str(v))
+        .transpose()
+        .map_err(|e| format!("cannot parse seed: {}", e))?
+        .unwrap_or(get_now() as u64);
+    let internal = matches.value_of("INTERNAL");
+
+    let state = load_from_file(&format!("{}.tvc", input))?;
+    let address = MsgAddressInt::from_str(&format!("0:{}", input))
+        .map_err(|e| format!("failed to load address: {}", e))?;
+    let contract = load_abi_contract(&load_abi_json_string(abi_file)?)?;
+    let methods: Vec<String> = match matches.values_of("METHOD") {
+        Some(methods) => methods.map(|m| m.to_owned()).collect(),
+        None => {
+            let mut methods: Vec<String> = contract.functions().keys().cloned().collect();
+            methods.sort();
+            methods
+        }
+    };
+
+    println!("Fuzzing with seed {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut failed = 0;
+    for method in &methods {
+        let function = contract.function(method)
+            .map_err(|e| format!("cannot find method {}: {:?}", method, e))?;
+        let mut method_failures = 0;
+        for _ in 0..iterations {
+            let params = Value::Object(random_params(&mut rng, &function.inputs)?).to_string();
+            let keypair = match matches.value_of("SIGN") {
+                Some(path) => Some(KeypairManager::from_secret_file(path)
+                    .ok_or("Failed to read keypair.")?.drain()),
+                None => None,
+            };
+            let body: SliceData = build_abi_body(abi_file, method, &params, None, keypair, internal.is_some())?
+                .into_cell()
+                .map_err(|e| format!("failed to pack body in cell: {}", e))?
+                .into();
+            let msg_info = MsgInfo {
+                balance: internal,
+                src: None,
+                now: get_now(),
+                lt: None,
+                rand_seed: None,
+                bounced: false,
+                body: Some(body),
+            };
+            let (exit_code, _, _) = call_contract_ex(
+                address.clone(),
+                state.clone(),
+                None,
+                matches.value_of("BALANCE"),
+                msg_info,
+                None,
+                None,
+                None,
+                gas_budget,
+                None,
+                None::<fn(SliceData, bool)>,
+                TraceLevel::Silent,
+                ExecutionOptions::default(),
+            )?;
+            let reason = if is_out_of_gas(exit_code) {
+                "gas budget exceeded"
+            } else if is_unexpected_exception(exit_code) {
+                "unexpected exception"
+            } else {
+                continue
+            };
+            method_failures += 1;
+            println!("{}: {} (exit code {}), params: {}", method, reason, exit_code, params);
+        }
+        println!("{}: {} runs, {} failed", method, iterations, method_failures);
+        failed += method_failures;
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} runs failed", failed, iterations * methods.len()));
+    }
+    Ok(())
+}
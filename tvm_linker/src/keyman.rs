@@ -15,6 +15,11 @@ use rand::rngs::OsRng;
 use std::fs::File;
 use std::io::{Read, Write};
 
+/// Keys given as `keychain:<name>` are kept in the OS keychain instead of files.
+const KEYCHAIN_PREFIX: &str = "keychain:";
+#[cfg(feature = "keychain")]
+const KEYCHAIN_SERVICE: &str = "tvm_linker";
+
 pub struct KeypairManager {
     pair: Keypair,
 }
@@ -42,17 +47,47 @@ impl KeypairManager {
         self.store_key(file, false)
     }
 
+    /// Saves the keypair to the file and its public key to `<file>.pub`. Both keys are written
+    /// to temporary files which are renamed once written, so an existing key file is never left
+    /// half-written and nothing is saved if writing fails.
+    pub fn store_pair(&self, file: &str) -> Result<(), String> {
+        let public = format!("{}.pub", file);
+        if keychain_name(file).is_some() {
+            self.store_public(&public)?;
+            return self.store_secret(file)
+        }
+        let temp_public = format!("{}.tmp", public);
+        let temp_secret = format!("{}.tmp", file);
+        let result = self.store_public(&temp_public)
+            .and_then(|_| self.store_secret(&temp_secret))
+            .and_then(|_| rename(&temp_public, &public))
+            .and_then(|_| rename(&temp_secret, file));
+        if result.is_err() {
+            std::fs::remove_file(&temp_public).ok();
+            std::fs::remove_file(&temp_secret).ok();
+        }
+        result
+    }
+
     fn store_key(&self, file: &str, is_secret: bool) -> Result<(), String> {
         let bytes = match is_secret {
             true => self.pair.to_bytes().to_vec(),
             false => self.pair.public.to_bytes().to_vec()
         };
+        if let Some(name) = keychain_name(file) {
+            return write_keychain(name, &bytes)
+        }
         let mut file = File::create(file.to_string())
             .map_err(|e| format!("Failed to create key file {}: {}", file, e))?;
         file.write_all(&bytes).map_err(|e| format!("Failed to save key: {}", e))?;
         Ok(())
     }
 
+    /// Public key in hex.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.pair.public.to_bytes())
+    }
+
     pub fn drain(self) -> Keypair {
         self.pair
     }
@@ -64,11 +99,77 @@ fn generate_keypair() -> Keypair {
 }
 
 
+fn rename(from: &str, to: &str) -> Result<(), String> {
+    std::fs::rename(from, to).map_err(|e| format!("Failed to save key file {}: {}", to, e))
+}
+
+fn keychain_name(file_path: &str) -> Option<&str> {
+    if file_path.starts_with(KEYCHAIN_PREFIX) {
+        Some(&file_path[KEYCHAIN_PREFIX.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "keychain")]
+fn read_keychain(name: &str) -> Result<Vec<u8>, ()> {
+    let key = keyring::Keyring::new(KEYCHAIN_SERVICE, name).get_password()
+        .map_err(|e| println!("Failed to read the key {} from the keychain: {}", name, e))?;
+    hex::decode(key)
+        .map_err(|e| println!("Failed to decode the key {} from the keychain: {}", name, e))
+}
+
+#[cfg(feature = "keychain")]
+fn write_keychain(name: &str, bytes: &[u8]) -> Result<(), String> {
+    keyring::Keyring::new(KEYCHAIN_SERVICE, name).set_password(&hex::encode(bytes))
+        .map_err(|e| format!("Failed to save the key {} to the keychain: {}", name, e))
+}
+
+#[cfg(not(feature = "keychain"))]
+fn read_keychain(name: &str) -> Result<Vec<u8>, ()> {
+    println!("Failed to read the key {}: keychain support is not enabled in this build", name);
+    Err(())
+}
+
+#[cfg(not(feature = "keychain"))]
+fn write_keychain(name: &str, _bytes: &[u8]) -> Result<(), String> {
+    Err(format!("Failed to save the key {}: keychain support is not enabled in this build", name))
+}
+
 fn read_key(file_path: &str) -> Result<Vec<u8>, ()> {
+    if let Some(name) = keychain_name(file_path) {
+        return read_keychain(name)
+    }
     let mut file = File::open(file_path.to_string())
         .map_err(|e| println!("Failed to open the key file {}: {}", file_path, e))?;
     let mut keys_buf = vec![];
     file.read_to_end(&mut keys_buf)
         .map_err(|e| println!("Failed to open the key file {}: {}", file_path, e))?;
     Ok(keys_buf)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_pair() {
+        let dir = std::env::temp_dir().join("tvm_linker_store_pair");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("key").to_str().unwrap().to_string();
+        std::fs::write(&file, b"old").unwrap();
+
+        let pair = KeypairManager::new();
+        pair.store_pair(&file).unwrap();
+        let loaded = KeypairManager::from_secret_file(&file).unwrap();
+        assert_eq!(loaded.public_key(), pair.public_key());
+        assert_eq!(hex::encode(std::fs::read(format!("{}.pub", file)).unwrap()), pair.public_key());
+        assert!(!dir.join("key.tmp").exists());
+        assert!(!dir.join("key.pub.tmp").exists());
+
+        // nothing is left if the keys can not be written
+        let missing = dir.join("missing").join("key").to_str().unwrap().to_string();
+        assert!(pair.store_pair(&missing).is_err());
+        assert!(!dir.join("missing").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+//! Core functionality of TVM linker available to other Rust projects:
+//!
+//! - [`parser`] parses TVM assembler sources and resolves functions and data;
+//! - [`program`] links parsed sources into a contract and saves or loads tvc files;
+//! - [`abi`], [`real_ton`] and [`keyman`] build and decode message bodies and messages;
+//! - [`testcall`] emulates the compute phase of a transaction locally;
+//! - [`disasm`] disassembles code cells;
+//! - [`ffi`] exposes disassembly, address calculation and call encoding to C.
+//!
+//! The `tvm_linker` binary is a command line wrapper over this library.
+//!
+//! For `wasm32` targets only the parser and the disassembler are built, modules
+//! working with the file system, keys and the emulator are left out.
+
+extern crate ton_abi as abi_json;
+extern crate base64;
+extern crate clap;
+extern crate crc16;
+extern crate ed25519;
+extern crate ed25519_dalek;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate rand;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate sha2;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate simplelog;
+extern crate ton_block;
+extern crate ton_types;
+#[macro_use]
+extern crate ton_vm;
+#[macro_use]
+extern crate log;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate ton_sdk;
+extern crate ton_labs_assembler;
+extern crate num_traits;
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen;
+#[cfg(feature = "keychain")]
+extern crate keyring;
+#[cfg(feature = "python")]
+extern crate pyo3;
+
+pub mod abi;
+mod arity;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bench;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod coverage;
+#[cfg(not(target_arch = "wasm32"))]
+mod datadiff;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fuzz;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod initdata;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod keyman;
+pub mod parser;
+#[cfg(not(target_arch = "wasm32"))]
+mod profile;
+pub mod printer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod program;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod real_ton;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod resolver;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scenario;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod selfcheck;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod storagefee;
+#[cfg(not(target_arch = "wasm32"))]
+mod methdict;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testcall;
+pub mod disasm;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
@@ -10,61 +10,36 @@
  * See the License for the specific TON DEV software governing permissions and
  * limitations under the License.
  */
-extern crate ton_abi as abi_json;
-extern crate base64;
 #[macro_use]
 extern crate clap;
-extern crate crc16;
-extern crate ed25519;
-extern crate ed25519_dalek;
-#[macro_use]
-extern crate lazy_static;
-extern crate rand;
-extern crate regex;
-extern crate serde;
 extern crate serde_json;
-extern crate sha2;
-extern crate simplelog;
 extern crate ton_block;
-extern crate ton_types;
-#[macro_use]
-extern crate ton_vm;
-#[macro_use]
-extern crate log;
-extern crate ton_sdk;
 extern crate ton_labs_assembler;
-extern crate num_traits;
-
-mod abi;
-mod initdata;
-mod keyman;
-mod parser;
-mod printer;
-mod program;
-mod real_ton;
-mod resolver;
-mod methdict;
-mod testcall;
-mod disasm;
-
-use abi::{build_abi_body, decode_body, load_abi_json_string, load_abi_contract};
+extern crate ton_types;
+extern crate tvm_linker_lib;
+
 use clap::ArgMatches;
-use initdata::set_initial_data;
-use keyman::KeypairManager;
-use parser::{ParseEngine, ParseEngineResults};
-use program::{Program, get_now};
-use real_ton::{decode_boc, compile_message};
-use resolver::resolve_name;
-use ton_block::{Deserializable, Message, StateInit, Serializable, Account};
 use std::{path::Path};
-use testcall::{call_contract, MsgInfo, TraceLevel};
-use ton_types::{BuilderData, SliceData};
 use std::env;
-use disasm::disasm::disasm_command;
-use ton_labs_assembler::Line;
 use std::fs::File;
-
-use crate::real_ton::load_stateinit;
+use ton_block::{Deserializable, Message, StateInit, Serializable, Account};
+use ton_labs_assembler::Line;
+use ton_types::{BuilderData, SliceData};
+use tvm_linker_lib::abi::{abi_diff_command, build_abi_body, decode_body, load_abi_json_string, load_abi_contract};
+use tvm_linker_lib::bench::bench_command;
+use tvm_linker_lib::coverage::coverage_command;
+use tvm_linker_lib::disasm::disasm::disasm_command;
+use tvm_linker_lib::fuzz::fuzz_command;
+use tvm_linker_lib::initdata::set_initial_data;
+use tvm_linker_lib::keyman::KeypairManager;
+use tvm_linker_lib::parser::{ParseEngine, ParseEngineResults, parse_defines};
+use tvm_linker_lib::program::{Program, get_now, print_env};
+use tvm_linker_lib::real_ton::{change_key_command, decode_account_data, decode_boc, compile_message, load_stateinit};
+use tvm_linker_lib::resolver::resolve_name;
+use tvm_linker_lib::scenario::run_scenario;
+use tvm_linker_lib::selfcheck::selfcheck_command;
+use tvm_linker_lib::storagefee::storage_fee_command;
+use tvm_linker_lib::testcall::{call_contract, CallOptions, MsgInfo, TraceLevel};
 
 fn main() -> Result<(), i32> {
     linker_main().map_err(|err_str| {
@@ -86,11 +61,19 @@ fn linker_main() -> Result<(), String> {
         (author: "TON Labs")
         (about: "Tool for assembling, disassembling and executing TVM code")
         (@subcommand decode =>
+            (@setting SubcommandsNegateReqs)
             (about: "take apart a message boc or a tvc file")
             (version: build_info.as_str())
             (author: "TON Labs")
             (@arg INPUT: +required +takes_value "BOC file")
             (@arg TVC: --tvc "BOC file is tvc file")
+            (@subcommand account_data =>
+                (about: "decodes persistent data of a contract as public variables from the ABI")
+                (version: build_info.as_str())
+                (@arg INPUT: +required +takes_value "Account BOC file or tvc file")
+                (@arg ABI: -a --abi +required +takes_value "Contract ABI file")
+                (@arg TVC: --tvc "BOC file is tvc file")
+            )
         )
         (@subcommand replace_code =>
             (@setting AllowNegativeNumbers)
@@ -113,8 +96,8 @@ fn linker_main() -> Result<(), String> {
             (@arg INPUT: +required +takes_value "TVM assembler source file")
             (@arg ABI: -a --("abi-json") +takes_value "Supplies contract abi to calculate correct function ids. If not specified abi can be loaded from file path obtained from <INPUT> path if it exists.")
             (@arg CTOR_PARAMS: -p --("ctor-params") +takes_value "Supplies arguments for the constructor")
-            (@arg GENKEY: --genkey +takes_value conflicts_with[SETKEY] "Generates new keypair for the contract and saves it to the file")
-            (@arg SETKEY: --setkey +takes_value conflicts_with[GENKEY] "Loads existing keypair from the file")
+            (@arg GENKEY: --genkey +takes_value conflicts_with[SETKEY] "Generates new keypair for the contract and saves it to the file (or to the OS keychain if given as keychain:<name>)")
+            (@arg SETKEY: --setkey +takes_value conflicts_with[GENKEY] "Loads existing keypair from the file or from the OS keychain (keychain:<name>)")
             (@arg WC: -w +takes_value "Workchain id used to print contract address, -1 by default.")
             (@arg DEBUG: --debug "Prints debug info: xref table and parsed assembler sources")
             (@arg VERBOSE: --verbose "Prints verbose execution info")
@@ -123,6 +106,13 @@ fn linker_main() -> Result<(), String> {
             (@arg LIB: --lib +takes_value ... number_of_values(1) "Standard library source file. If not specified lib is loaded from environment variable TVM_LINKER_LIB_PATH if it exists.")
             (@arg OUT_FILE: -o +takes_value "Output file name")
             (@arg LANGUAGE: --language +takes_value "Enable language-specific features in linkage")
+            (@arg OUTPUT: --output +takes_value possible_values(&["text", "env"]) "Output format: human readable text (default) or KEY=value lines for shell scripts")
+            (@arg DEFINE: -D +takes_value ... number_of_values(1) "Defines symbol NAME[=value] (value is 1 by default) checked by .ifdef/.ifndef and substituted as $NAME$")
+            (@arg ABI_STUB: --("abi-stub") +takes_value "Saves a skeleton ABI with public functions and their ids to the file")
+            (@arg SPLIT_DEPTH: --("split-depth") +takes_value "Sets split_depth (0..30) of the contract StateInit")
+            (@arg TICK: --tick "Marks the contract as special, called in tick transactions")
+            (@arg TOCK: --tock "Marks the contract as special, called in tock transactions")
+            (@arg SHARE_BODIES: --("share-bodies") "Stores identical function bodies once, every call of them costs an extra cell load")
         )
         (@subcommand test =>
             (@setting AllowLeadingHyphen)
@@ -132,25 +122,99 @@ fn linker_main() -> Result<(), String> {
             (@arg SOURCE: -s --source +takes_value "Contract source file")
             (@arg BODY: --body +takes_value "Body for external inbound message (a bitstring like x09c_ or a hex string)")
             (@arg BODY_FROM_BOC: --("body-from-boc") +takes_value "Body from message boc file")
-            (@arg SIGN: --sign +takes_value "Signs body with private key from defined file")
+            (@arg SIGN: --sign +takes_value "Signs body with private key from defined file or from the OS keychain (keychain:<name>)")
             (@arg TRACE: --trace "Prints last command name, stack and registers after each executed TVM command")
             (@arg TRACE_MIN: --("trace-minimal") "Prints minimal trace")
+            (@arg TRACE_FORMAT: --("trace-format") +takes_value possible_values(&["full", "minimal", "vmlog"]) "Prints trace in the defined format, vmlog is compatible with the reference TVM vm.log")
+            (@arg TRACE_DECODE: --("trace-decode") "Prints full trace with slices decoded as addresses and strings where possible")
             (@arg DECODEC6: --("decode-c6") "Prints last command name, stack and registers after each executed TVM command")
             (@arg INTERNAL: --internal +takes_value "Emulates inbound internal message with value instead of external message")
             (@arg BOUNCED: --bounced requires[INTERNAL] "Emulates bounced message, can be used only with --internal option.")
             (@arg BALANCE: --balance +takes_value "Emulates supplied account balance")
             (@arg SRCADDR: --src +takes_value "Supplies message source address")
             (@arg NOW: --now +takes_value "Supplies transaction creation unixtime")
+            (@arg LT: --lt +takes_value "Supplies transaction logical time")
+            (@arg RAND_SEED: --("rand-seed") +takes_value "Supplies random seed (hex string)")
             (@arg TICKTOCK: --ticktock +takes_value conflicts_with[BODY] "Emulates ticktock transaction in masterchain, 0 for tick and -1 for tock")
             (@arg GASLIMIT: -l --("gas-limit") +takes_value "Defines gas limit for tvm execution")
-            (@arg CONFIG: --config +takes_value "Imports config parameters from a config contract boc")
-            (@arg INPUT: +required +takes_value "TVM assembler source file or contract name if used with test subcommand")
+            (@arg GASCREDIT: --("gas-credit") +takes_value "Defines gas credit for tvm execution (used by external messages before ACCEPT)")
+            (@arg CONFIG: --config +takes_value "Imports config parameters from a config contract boc, a config params dictionary boc or json")
+            (@arg COVERAGE: --coverage +takes_value "Accumulates executed instructions in the coverage file")
+            (@arg FLAMEGRAPH: --flamegraph +takes_value "Saves gas profile by functions in folded stacks format for flamegraph tools")
+            (@arg LOAD_STATE: --("load-state") +takes_value "Loads account state (code, data and balance) saved by --save-state")
+            (@arg SAVE_STATE: --("save-state") +takes_value "Saves account state after the call to the file instead of updating the contract file")
+            (@arg DATA_DIFF: --("data-diff") "Prints changes of contract data after a successful call, by data item names with --abi-json")
+            (@arg INPUT: +required +takes_value "TVM assembler source file or contract name if used with test subcommand. A json file is treated as a test scenario.")
             (@arg ADDRESS: --address +takes_value "Contract address, which can be obtained from the contract with `address(this)`. If not specified address can be obtained from the INPUT argument or set to zero.")
             (@arg ABI_JSON: -a --("abi-json") +takes_value conflicts_with[BODY] "Supplies json file with contract ABI")
             (@arg ABI_METHOD: -m --("abi-method") +takes_value conflicts_with[BODY] "Supplies the name of the calling contract method")
             (@arg ABI_PARAMS: -p --("abi-params") +takes_value conflicts_with[BODY] "Supplies ABI arguments for the contract method (can be passed via filename). Can be not specified for empty parameters.")
             (@arg ABI_HEADER: -h --("abi-header") +takes_value conflicts_with[BODY] conflicts_with[INTERNAL] "Supplies ABI header")
         )
+        (@subcommand coverage =>
+            (about: "print coverage report collected by test subcommand")
+            (version: build_info.as_str())
+            (author: "TON Labs")
+            (@arg TVC: +required +takes_value "Path to tvc file")
+            (@arg COVERAGE: +required +takes_value "Coverage file produced by test --coverage")
+            (@arg DEBUG_MAP: --("debug-map") +required +takes_value "Debug map file of the contract")
+            (@arg SOURCE_DIR: --("source-dir") +takes_value "Directory with contract sources, current directory by default")
+            (@arg ANNOTATE: --annotate "Prints sources annotated with hit counts")
+        )
+        (@subcommand fuzz =>
+            (about: "call contract methods with random ABI arguments in test environment")
+            (version: build_info.as_str())
+            (author: "TON Labs")
+            (@arg INPUT: +required +takes_value "Contract name (address of the contract tvc file)")
+            (@arg ABI_JSON: -a --("abi-json") +required +takes_value "Supplies json file with contract ABI")
+            (@arg METHOD: -m --method +takes_value ... number_of_values(1) "Method to fuzz, all methods of the ABI by default")
+            (@arg ITERATIONS: -n --iterations +takes_value "Number of calls of every method, 100 by default")
+            (@arg GAS_BUDGET: --("gas-budget") +takes_value "Reports calls which exceed the gas budget")
+            (@arg SEED: --seed +takes_value "Random seed to reproduce a fuzzing session")
+            (@arg SIGN: --sign +takes_value "Signs messages with private key from defined file or from the OS keychain (keychain:<name>)")
+            (@arg INTERNAL: --internal +takes_value "Sends internal messages with value instead of external messages")
+            (@arg BALANCE: --balance +takes_value "Emulates supplied account balance")
+        )
+        (@subcommand bench =>
+            (about: "measure performance of parsing, disassembling and message encoding")
+            (version: build_info.as_str())
+            (author: "TON Labs")
+            (@arg SOURCE: --source +takes_value "TVM assembler source file to parse and link")
+            (@arg LIB: --lib +takes_value ... number_of_values(1) "Standard library source file linked with the source")
+            (@arg TVC: --tvc +takes_value "Contract tvc file to disassemble")
+            (@arg ABI_JSON: -a --("abi-json") +takes_value "Supplies json file with contract ABI")
+            (@arg METHOD: -m --method +takes_value requires[ABI_JSON] "ABI method to encode messages for")
+            (@arg PARAMS: -p --params +takes_value requires[METHOD] "Parameters of the method in json, empty by default")
+            (@arg ITERATIONS: -n --iterations +takes_value "Number of parse and disassemble runs, 10 by default")
+            (@arg MESSAGES: --messages +takes_value "Number of encoded messages, 1000 by default")
+        )
+        (@subcommand selfcheck =>
+            (about: "check that every tvc and boc file in a directory can be loaded, disassembled and assembled back")
+            (version: build_info.as_str())
+            (author: "TON Labs")
+            (@arg DIR: +required +takes_value "Directory with tvc and boc files")
+        )
+        (@subcommand storage_fee =>
+            (@setting AllowNegativeNumbers)
+            (about: "estimates the storage fee of a contract from the number of its cells and bits")
+            (version: build_info.as_str())
+            (author: "TON Labs")
+            (@arg INPUT: +required +takes_value "Tvc file or account boc")
+            (@arg WORKCHAIN: -w --workchain +takes_value "Workchain id of the contract, masterchain prices are used for -1")
+            (@arg CONFIG: --config +takes_value "Takes storage prices from config param 18 of a config contract boc, a config params dictionary boc or json")
+            (@arg PERIOD: --period +takes_value "Period in seconds, one year by default")
+        )
+        (@subcommand abi =>
+            (about: "contract ABI tools")
+            (version: build_info.as_str())
+            (author: "TON Labs")
+            (@subcommand diff =>
+                (about: "reports changes between two versions of a contract ABI, marking breaking ones")
+                (version: build_info.as_str())
+                (@arg OLD: +required +takes_value "Old ABI file")
+                (@arg NEW: +required +takes_value "New ABI file")
+            )
+        )
         (@subcommand message =>
             (@setting AllowNegativeNumbers)
             (about: "generate external inbound message for the blockchain")
@@ -166,6 +230,17 @@ fn linker_main() -> Result<(), String> {
             (@arg SIGN: --setkey +takes_value "Loads existing keypair from the file")
             (@arg INPUT: +required +takes_value "TVM assembler source file or contract name")
         )
+        (@subcommand change_key =>
+            (about: "generate external inbound message changing the public key of the contract")
+            (version: build_info.as_str())
+            (@arg ABI_JSON: -a --("abi-json") +required +takes_value "Supplies json file with contract ABI")
+            (@arg ABI_METHOD: -m --("abi-method") +takes_value "Method changing the key, found in the ABI by default")
+            (@arg ABI_HEADER: -h --("abi-header") +takes_value "Supplies ABI header")
+            (@arg SIGN: --setkey +required +takes_value "Current keypair of the contract, signs the message")
+            (@arg NEW_KEY: --newkey +takes_value "New keypair file, a fresh keypair is generated if not specified")
+            (@arg WORKCHAIN: -w --workchain +takes_value "Supplies workchain id for the contract address")
+            (@arg INPUT: +required +takes_value "Contract address")
+        )
         (@subcommand init =>
             (about: "initialize smart contract public variables")
             (version: build_info.as_str())
@@ -180,18 +255,48 @@ fn linker_main() -> Result<(), String> {
             (@subcommand dump =>
                 (about: "dumps tree of cells for the given tvc")
                 (version: build_info.as_str())
-                (@arg TVC: +required +takes_value "Path to tvc file")
+                (@arg TVC: +required +takes_value "Path to tvc or boc file, or the bag of cells as a hex or base64 string")
             )
             (@subcommand graphviz =>
                 (about: "generates graphviz dot for the given tvc")
                 (version: build_info.as_str())
-                (@arg METHOD: --method +takes_value "Selects a particular method by ID or int|ext|ticktock")
-                (@arg TVC: +required +takes_value "Path to tvc file")
+                (@arg METHOD: --method +takes_value "Selects a particular method by ID, by name with --abi or int|ext|ticktock")
+                (@arg ABI: -a --abi +takes_value "Contract ABI file, used to find the method by its name")
+                (@arg TVC: +required +takes_value "Path to tvc or boc file, or the bag of cells as a hex or base64 string")
             )
             (@subcommand text =>
                 (about: "disassembles tvc's code into assembler text")
                 (version: build_info.as_str())
-                (@arg TVC: +required +takes_value "Path to tvc file")
+                (@arg TVC: +required +takes_value "Path to tvc or boc file, or the bag of cells as a hex or base64 string")
+                (@arg ERRORS: -e --errors +takes_value "ABI file with errors section or json object mapping error codes to names, used to annotate THROW instructions")
+                (@arg TREE: --tree "Prints the tree of code cells with instructions stored in every cell instead of assembler text")
+                (@arg ABI: -a --abi +takes_value "Contract ABI file, used to name public functions and their ids pushed by PUSHINT")
+                (@arg SPLIT_OUTPUT: --("split-output") +takes_value "Writes every function into its own file in the directory together with index.txt listing them")
+                (@arg DEBUG_STRINGS: --("debug-strings") "Lists strings of debug instructions (LOGSTR, PRINTSTR, DUMPTOSFMT) found in the code instead")
+                (@arg JSON: --json "Prints instructions of every function as json with operands, cell hashes, bit offsets and nesting levels instead")
+                (@arg GAS: --gas "Annotates every instruction with its basic gas price and every continuation with the total")
+                (@arg OFFSETS: --offsets "Prefixes every instruction with its bit offset in the cell and prints the hash of every cell the code continues in")
+                (@arg STATS: --stats "Prints the histogram of opcodes, instructions, cells and bits of every function and the largest continuations instead")
+                (@arg DECOMPILE: --decompile "Prints pseudo-code of every function recovered from common idioms instead")
+                (@arg XREFS: --xrefs "Appends the index of callers and callees of every function taking part in calls")
+            )
+            (@subcommand lint =>
+                (about: "looks for known gas inefficiencies in tvc's code and estimates savings")
+                (version: build_info.as_str())
+                (@arg TVC: +required +takes_value "Path to tvc or boc file, or the bag of cells as a hex or base64 string")
+                (@arg SECURITY: --security "Looks for risky patterns instead: ACCEPT without any signature check, missing replay protection, SENDRAWMSG with mode 128 in loops, unchecked SETCODE")
+            )
+            (@subcommand dedup =>
+                (about: "finds identical cells stored in the tvc several times")
+                (version: build_info.as_str())
+                (@arg TVC: +required +takes_value "Path to tvc or boc file, or the bag of cells as a hex or base64 string")
+                (@arg OUTPUT: -o --output +takes_value "Saves the tvc with identical cells shared to the file")
+            )
+            (@subcommand diff =>
+                (about: "prints the difference between disassembled code of two tvc files")
+                (version: build_info.as_str())
+                (@arg OLD: +required +takes_value "Path to the old tvc or boc file, or the bag of cells as a hex or base64 string")
+                (@arg NEW: +required +takes_value "Path to the new tvc or boc file, or the bag of cells as a hex or base64 string")
             )
         )
         (@setting SubcommandRequired)
@@ -209,6 +314,13 @@ fn linker_main() -> Result<(), String> {
 
     //SUBCOMMAND DECODE
     if let Some(decode_matches) = matches.subcommand_matches("decode") {
+        if let Some(data_matches) = decode_matches.subcommand_matches("account_data") {
+            return decode_account_data(
+                data_matches.value_of("INPUT").unwrap(),
+                data_matches.is_present("TVC"),
+                data_matches.value_of("ABI").unwrap(),
+            );
+        }
         return decode_boc(
             decode_matches.value_of("INPUT").unwrap(),
             decode_matches.is_present("TVC"),
@@ -252,6 +364,10 @@ fn linker_main() -> Result<(), String> {
         )
     }
 
+    if let Some(m) = matches.subcommand_matches("change_key") {
+        return change_key_command(m);
+    }
+
     //SUBCOMMAND COMPILE
     if let Some(compile_matches) = matches.subcommand_matches("compile") {
         let input = compile_matches.value_of("INPUT").unwrap();
@@ -292,9 +408,18 @@ fn linker_main() -> Result<(), String> {
         if verbose {
             println!("VERBOSE: List of source files: {:?}", sources);
         }
-        let mut prog = Program::new(
-            ParseEngine::new(sources, abi_json, verbose)?
-        );
+        let defines = parse_defines(compile_matches.values_of("DEFINE").unwrap_or_default())?;
+        let engine = ParseEngine::with_defines(sources, abi_json, defines, verbose)?;
+        for warning in engine.warnings() {
+            eprintln!("Warning: {}", warning);
+        }
+        if let Some(filename) = compile_matches.value_of("ABI_STUB") {
+            let file = File::create(filename)
+                .map_err(|e| format!("Failed to create file {}: {}", filename, e))?;
+            serde_json::to_writer_pretty(file, &engine.abi_stub())
+                .map_err(|e| format!("Failed to write data to file: {}", e))?;
+        }
+        let mut prog = Program::new(engine);
 
         match compile_matches.value_of("GENKEY") {
             Some(file) => {
@@ -315,6 +440,13 @@ fn linker_main() -> Result<(), String> {
 
         let debug = compile_matches.is_present("DEBUG");
         prog.set_language(compile_matches.value_of("LANGUAGE"));
+        let split_depth = compile_matches.value_of("SPLIT_DEPTH")
+            .map(|depth| u8::from_str_radix(depth, 10))
+            .transpose()
+            .map_err(|e| format!("invalid split depth: {}", e))?;
+        prog.set_split_depth(split_depth);
+        prog.set_ticktock(compile_matches.is_present("TICK"), compile_matches.is_present("TOCK"));
+        prog.set_share_bodies(compile_matches.is_present("SHARE_BODIES"));
 
         if debug {
            prog.debug_print();
@@ -332,7 +464,11 @@ fn linker_main() -> Result<(), String> {
 
         let data_filename = compile_matches.value_of("DATA");
 
-        prog.compile_to_file_ex(wc, abi_file, ctor_params, out_file, debug, data_filename)?;
+        if compile_matches.value_of("OUTPUT") == Some("env") {
+            print_env(&prog.compile_to_env(wc, abi_file, ctor_params, out_file, debug, data_filename)?);
+        } else {
+            prog.compile_to_file_ex(wc, abi_file, ctor_params, out_file, debug, data_filename)?;
+        }
 
         if compile_matches.is_present("DEBUG_MAP") {
             let filename = compile_matches.value_of("DEBUG_MAP").unwrap();
@@ -345,6 +481,25 @@ fn linker_main() -> Result<(), String> {
         return Ok(());
     }
 
+    if let Some(m) = matches.subcommand_matches("bench") {
+        return bench_command(m);
+    }
+    if let Some(m) = matches.subcommand_matches("selfcheck") {
+        return selfcheck_command(m);
+    }
+    if let Some(m) = matches.subcommand_matches("storage_fee") {
+        return storage_fee_command(m);
+    }
+    if let Some(m) = matches.subcommand_matches("abi").and_then(|m| m.subcommand_matches("diff")) {
+        return abi_diff_command(m);
+    }
+    if let Some(m) = matches.subcommand_matches("fuzz") {
+        return fuzz_command(m);
+    }
+    if let Some(m) = matches.subcommand_matches("coverage") {
+        return coverage_command(m);
+    }
+
     if let Some(m) = matches.subcommand_matches("disasm") {
         return disasm_command(m);
     }
@@ -480,6 +635,11 @@ fn decode_hex_string(hex_str: String) -> Result<(Vec<u8>, usize), String> {
 }
 
 fn run_test_subcmd(matches: &ArgMatches) -> Result<(), String> {
+    let input = matches.value_of("INPUT").unwrap();
+    if input.ends_with(".json") {
+        return run_scenario(input);
+    }
+
     let (body, sign) = match matches.value_of("BODY") {
         Some(hex_str) => {
             let mut hex_str = hex_str.to_string();
@@ -521,6 +681,10 @@ fn run_test_subcmd(matches: &ArgMatches) -> Result<(), String> {
 
     let ticktock = parse_ticktock(matches.value_of("TICKTOCK"))?;
     let now = parse_now(matches.value_of("NOW"))?;
+    let lt = matches.value_of("LT")
+        .map(|v| u64::from_str_radix(v, 10))
+        .transpose()
+        .map_err(|e| format!(r#"failed to parse "lt" option: {}"#, e))?;
 
     let action_decoder = |body, is_internal| {
         let abi_file = matches.value_of("ABI_JSON");
@@ -548,6 +712,8 @@ fn run_test_subcmd(matches: &ArgMatches) -> Result<(), String> {
         balance: matches.value_of("INTERNAL"),
         src: matches.value_of("SRCADDR"),
         now,
+        lt,
+        rand_seed: matches.value_of("RAND_SEED"),
         bounced: matches.is_present("BOUNCED"),
         body,
     };
@@ -566,9 +732,21 @@ fn run_test_subcmd(matches: &ArgMatches) -> Result<(), String> {
         .map(|v| i64::from_str_radix(v, 10))
         .transpose()
         .map_err(|e| format!("cannot parse gas limit value: {}", e))?;
+    let gas_credit = matches.value_of("GASCREDIT")
+        .map(|v| i64::from_str_radix(v, 10))
+        .transpose()
+        .map_err(|e| format!("cannot parse gas credit value: {}", e))?;
 
     let mut trace_level = TraceLevel::None;
-    if matches.is_present("TRACE") {
+    if let Some(format) = matches.value_of("TRACE_FORMAT") {
+        trace_level = match format {
+            "vmlog" => TraceLevel::VmLog,
+            "minimal" => TraceLevel::Minimal,
+            _ => TraceLevel::Full,
+        };
+    } else if matches.is_present("TRACE_DECODE") {
+        trace_level = TraceLevel::Decoded;
+    } else if matches.is_present("TRACE") {
         trace_level = TraceLevel::Full;
     } else if matches.is_present("TRACE_MIN") {
         trace_level = TraceLevel::Minimal;
@@ -597,9 +775,18 @@ fn run_test_subcmd(matches: &ArgMatches) -> Result<(), String> {
         sign,
         ticktock,
         gas_limit,
+        gas_credit,
         if matches.is_present("DECODEC6") { Some(action_decoder) } else { None },
         trace_level,
         debug_map_filename,
+        CallOptions {
+            coverage_file: matches.value_of("COVERAGE"),
+            load_state: matches.value_of("LOAD_STATE"),
+            save_state: matches.value_of("SAVE_STATE"),
+            data_diff: matches.is_present("DATA_DIFF"),
+            abi_file: abi_json,
+            flamegraph_file: matches.value_of("FLAMEGRAPH"),
+        },
     )?;
 
     println!("TEST COMPLETED");
@@ -18,6 +18,7 @@ use ton_types::{SliceData, dictionary::HashmapE};
 pub fn prepare_methods<T>(
     methods: &HashMap<T, Lines>,
     adjust_entry_points: bool,
+    share_identical: bool,
 ) -> Result<(HashmapE, DbgInfo), (T, String)>
 where
     T: Clone + Default + Eq + std::fmt::Display + Serializable + std::hash::Hash,
@@ -25,7 +26,7 @@ where
     let bit_len = SliceData::from(T::default().serialize().unwrap()).remaining_bits();
     let mut map = HashmapE::with_bit_len(bit_len);
     let mut dbg = DbgInfo::new();
-    insert_methods(&mut map, &mut dbg, methods, adjust_entry_points)?;
+    insert_methods(&mut map, &mut dbg, methods, adjust_entry_points, share_identical)?;
     Ok((map, dbg))
 }
 
@@ -34,34 +35,44 @@ pub fn insert_methods<T>(
     dbg: &mut DbgInfo,
     methods: &HashMap<T, Lines>,
     adjust_entry_points: bool,
+    share_identical: bool,
 ) -> Result<(), (T, String)>
 where
     T: Clone + Default + Eq + std::fmt::Display + Serializable + std::hash::Hash,
 {
+    let mut compiled = Vec::with_capacity(methods.len());
+    let mut copies = HashMap::new();
     for pair in methods.iter() {
-        let key: SliceData = pair.0.clone().serialize()
-            .map_err(|e| (pair.0.clone(), format!("Failed to serialize data: {}", e)))?.into();
-        let mut val = compile_code_debuggable(pair.1.clone()).map_err(|e| {
+        let val = compile_code_debuggable(pair.1.clone()).map_err(|e| {
             (pair.0.clone(), e.to_string())
         })?;
-        if val.0.remaining_bits() <= (1023 - (32 + 10)) { // key_length + hashmap overheads
+        *copies.entry(val.0.cell().repr_hash()).or_insert(0) += 1;
+        compiled.push((pair.0, val));
+    }
+    for (name, mut val) in compiled {
+        let key: SliceData = name.clone().serialize()
+            .map_err(|e| (name.clone(), format!("Failed to serialize data: {}", e)))?.into();
+        // on request identical bodies are stored once in a cell referenced by all their ids,
+        // the method is entered by an implicit jump to the reference which costs a cell load
+        let shared = share_identical && copies[&val.0.cell().repr_hash()] > 1;
+        if !shared && val.0.remaining_bits() <= (1023 - (32 + 10)) { // key_length + hashmap overheads
             map.set(key.clone(), &val.0).map_err(|e| {
-                (pair.0.clone(), format!("failed to set method _name_ to dictionary: {}", e))
+                (name.clone(), format!("failed to set method _name_ to dictionary: {}", e))
             })?;
         } else {
             map.setref(key.clone(), &val.0.clone().into_cell()).map_err(|e| {
-                (pair.0.clone(), format!("failed to set method _name_ to dictionary: {}", e))
+                (name.clone(), format!("failed to set method _name_ to dictionary: {}", e))
             })?;
         }
         let id = key.clone().get_next_i32()
-            .map_err(|e| (pair.0.clone(), format!("Failed to decode data: {}", e)))?;
+            .map_err(|e| (name.clone(), format!("Failed to decode data: {}", e)))?;
         if adjust_entry_points || id < -2 || id > 0 {
             let before = val.0;
             let after = map.get(key)
-                .map_err(|e| (pair.0.clone(), format!("Failed to find key: {}", e)))?
-                .ok_or((pair.0.clone(), "Data is empty".to_string()))?;
+                .map_err(|e| (name.clone(), format!("Failed to find key: {}", e)))?
+                .ok_or((name.clone(), "Data is empty".to_string()))?;
             adjust_debug_map(&mut val.1, before, after)
-                .map_err(|e| (pair.0.clone(), e))?;
+                .map_err(|e| (name.clone(), e))?;
         }
         dbg.append(&mut val.1)
     }
@@ -83,3 +94,33 @@ fn adjust_debug_map(map: &mut DbgInfo, before: SliceData, after: SliceData) -> R
     map.insert(hash_new, new);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ton_labs_assembler::Line;
+    use ton_types::HashmapType;
+
+    #[test]
+    fn test_identical_bodies_shared() {
+        let body = |lines: &[&str]| lines.iter().enumerate()
+            .map(|(i, text)| Line::new(text, "test.code", i + 1))
+            .collect::<Lines>();
+        let mut methods = HashMap::new();
+        methods.insert(1u32, body(&["PUSHINT 1\n", "PUSHINT 2\n", "ADD\n"]));
+        methods.insert(2u32, body(&["PUSHINT 1\n", "PUSHINT 2\n", "ADD\n"]));
+        methods.insert(3u32, body(&["PUSHINT 3\n"]));
+        let (map, _) = prepare_methods(&methods, false, true).unwrap();
+        let value = |id: u32| map.get(id.serialize().unwrap().into()).unwrap().unwrap();
+        let (first, second, other) = (value(1), value(2), value(3));
+        assert_eq!(first.remaining_bits(), 0);
+        assert_eq!(first.reference(0).unwrap().repr_hash(), second.reference(0).unwrap().repr_hash());
+        assert_eq!(other.remaining_references(), 0);
+
+        // bodies fitting the dictionary are kept inline by default
+        let (map, _) = prepare_methods(&methods, false, false).unwrap();
+        let value = |id: u32| map.get(id.serialize().unwrap().into()).unwrap().unwrap();
+        assert_eq!(value(1).remaining_references(), 0);
+        assert_eq!(value(2).remaining_references(), 0);
+    }
+}
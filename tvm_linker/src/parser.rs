@@ -12,6 +12,7 @@
  */
 use abi::{gen_abi_id, load_abi_contract};
 use abi_json::Contract;
+use arity::{check_calls, Signature};
 use regex::Regex;
 use resolver::resolve_name;
 use std::collections::{HashSet, HashMap};
@@ -71,6 +72,25 @@ impl ParseEngineResults {
     }
 }
 
+/// Parses `NAME` or `NAME=value` definitions given on the command line, `NAME` defines 1.
+pub fn parse_defines<'a>(defines: impl Iterator<Item = &'a str>) -> Result<HashMap<String, Ptr>, String> {
+    let mut symbols = HashMap::new();
+    for define in defines {
+        let mut parts = define.splitn(2, '=');
+        let name = parts.next().unwrap();
+        if name.is_empty() {
+            return Err(format!("invalid define \"{}\": name is empty", define));
+        }
+        let value = match parts.next() {
+            Some(value) => Ptr::from_str_radix(value, 10)
+                .map_err(|e| format!("invalid define \"{}\": {}", define, e))?,
+            None => 1,
+        };
+        symbols.insert(name.to_string(), value);
+    }
+    Ok(symbols)
+}
+
 pub fn ptr_to_builder(n: Ptr) -> Result<BuilderData, String> {
     let mut b = BuilderData::new();
     b.append_i64(n).map_err(|_| format!("failed to serialize an i64 to builder"))?;
@@ -207,6 +227,8 @@ struct Object {
     pub name: String,
     pub size: usize,
     pub public: bool,
+    /// alignment of the object address, 0 if not specified
+    pub align: usize,
     pub dtype: ObjectType,
 }
 
@@ -216,6 +238,7 @@ impl Object {
             name,
             size: 0,
             public: false,
+            align: 0,
             dtype: ObjectType::from(stype),
         }
     }
@@ -234,6 +257,16 @@ pub struct ParseEngine {
     internals: HashMap<i32, Func>,
     /// map of aliases for function names
     aliases: HashMap<String, i32>,
+    /// .internal functions referenced by name
+    used_internals: HashSet<String>,
+    /// symbols defined from the command line (name -> value)
+    defines: HashMap<String, Ptr>,
+    /// .globl objects defined in library sources
+    library_objects: HashSet<String>,
+    /// function signatures declared by .args (name -> signature)
+    signatures: HashMap<String, Signature>,
+    /// call sites with not enough arguments on the stack
+    arity_warnings: Vec<String>,
     /// .globl functions references (name -> id)
     xrefs: HashMap<String, u32>,
     /// map of .global objects: functions (private and public)
@@ -274,16 +307,25 @@ const PATTERN_PARAM:    &'static str = r#"^\s+\.(\w+),?\s*([a-zA-Z0-9-_\s"]+)"#;
 const PATTERN_TYPE:     &'static str = r"^\s*\.type\s+(:?[\w\.]+),\s*@([a-zA-Z]+)";
 const PATTERN_PUBLIC:   &'static str = r"^\s*\.public\s+([\w\.]+)";
 const PATTERN_SIZE:     &'static str = r"^\s*\.size\s+([\w\.]+),\s*([\.\w]+)";
+const PATTERN_ARGS:     &'static str = r"^\s*\.args\s+(:?[\w\.]+),\s*(\d+)(?:,\s*(\d+))?";
 const PATTERN_COMM:     &'static str = r"^\s*\.comm\s+([\w\.]+),\s*(\d+),\s*(\d+)";
 const PATTERN_ASCIZ:    &'static str = r#"^\s*\.asciz\s+"(.+)""#;
 const PATTERN_MACRO:    &'static str = r"^\s*\.macro\s+([\w\.:]+)";
-const PATTERN_IGNORED:  &'static str = r"^\s+\.(p2align|align|text|file|ident|section)";
+const PATTERN_ALIGN:    &'static str = r"^\s+\.(p2align|align)\s+(\d+)";
+const PATTERN_IGNORED:  &'static str = r"^\s+\.(text|file|ident|section)";
 const PATTERN_LOC:      &'static str = r"^\s*\.loc\s+(.+),\s+(\d+)\n$";
 const PATTERN_VERSION:  &'static str = r"^\s*\.version\s+(.+)";
 const PATTERN_PRAGMA:   &'static str = r"^\s*\.pragma\s+(.+)";
+const PATTERN_IFDEF:    &'static str = r"^\s*\.(ifdef|ifndef)\s+([\w\.]+)";
+const PATTERN_ELSE:     &'static str = r"^\s*\.else\s*$";
+const PATTERN_ENDIF:    &'static str = r"^\s*\.endif\s*$";
 
 lazy_static! {
     static ref COMPUTE_REGEX: Regex = Regex::new(r"^\s*\.compute\s+\$([\w\.:]+)\$").unwrap();
+    /// local numeric label `1:`
+    static ref LOCAL_LABEL_REGEX: Regex = Regex::new(r"^(\d+):\s*(;[^\n]*)?\s*$").unwrap();
+    /// reference to the previous (`$1b$`) or the next (`$1f$`) local label
+    static ref LOCAL_REF_REGEX: Regex = Regex::new(r"\$(\d+)([bf])([$+:])").unwrap();
 }
 
 const GLOBL:            &'static str = ".globl";
@@ -301,10 +343,49 @@ const SCI_NAME:         &'static str = "tvm_contract_info";
 impl ParseEngine {
 
     pub fn new(sources: Vec<&Path>, abi_json: Option<String>, verbose: bool) -> Result<Self, String> {
-        let mut engine = ParseEngine {
+        Self::with_defines(sources, abi_json, HashMap::new(), verbose)
+    }
+
+    /// Parses sources with predefined symbols. Defined symbols are checked by
+    /// `.ifdef`/`.ifndef` directives and are substituted as `$NAME$` constants.
+    pub fn with_defines(
+        sources: Vec<&Path>,
+        abi_json: Option<String>,
+        defines: HashMap<String, Ptr>,
+        verbose: bool,
+    ) -> Result<Self, String> {
+        let mut engine = Self::empty(verbose);
+        engine.defines = defines;
+        engine.parse(sources, abi_json)?;
+        Ok(engine)
+    }
+
+    /// Parses sources given as pairs of file name and text, without access to the file system.
+    pub fn from_sources(sources: Vec<(&str, &str)>, abi_json: Option<String>, verbose: bool) -> Result<Self, String> {
+        let mut engine = Self::empty(verbose);
+        engine.load_abi(abi_json)?;
+        let count = sources.len();
+        for (i, (filename, text)) in sources.into_iter().enumerate() {
+            engine.trace(&format!("Parse code file {}", filename));
+            engine.parse_source(filename.to_string(), text.as_bytes())?;
+            if i + 1 < count {
+                engine.mark_library_objects();
+            }
+        }
+        engine.finalize()?;
+        Ok(engine)
+    }
+
+    fn empty(verbose: bool) -> Self {
+        ParseEngine {
             xrefs:      HashMap::new(),
             intrefs:    HashMap::new(),
             aliases:    HashMap::new(),
+            used_internals: HashSet::new(),
+            defines:    HashMap::new(),
+            library_objects: HashSet::new(),
+            signatures: HashMap::new(),
+            arity_warnings: vec![],
             globals:    HashMap::new(),
             next_private_globl_funcid: 0,
             internals:  HashMap::new(),
@@ -319,9 +400,7 @@ impl ParseEngine {
             save_my_code:    false,
             computed:        HashMap::new(),
             verbose,
-        };
-        engine.parse(sources, abi_json)?;
-        Ok(engine)
+        }
     }
 
     fn trace(&self, line: &str) {
@@ -331,18 +410,36 @@ impl ParseEngine {
     }
 
     fn parse(&mut self, sources: Vec<&Path>, abi_json: Option<String>) -> Result<(), String> {
+        self.load_abi(abi_json)?;
+
+        for (i, source) in sources.iter().enumerate() {
+            self.trace(&format!("Parse code file {:?}", source));
+            self.parse_code(source)?;
+            if i + 1 < sources.len() {
+                self.mark_library_objects();
+            }
+        }
+
+        self.finalize()
+    }
+
+    /// All sources except the last one are libraries: objects parsed so far come from them.
+    fn mark_library_objects(&mut self) {
+        let names = self.globals.keys().cloned().collect::<Vec<_>>();
+        self.library_objects.extend(names);
+    }
+
+    fn load_abi(&mut self, abi_json: Option<String>) -> Result<(), String> {
         if let Some(s) = abi_json {
             self.abi = Some(load_abi_contract(&s)?);
             self.trace("ABI was successfully loaded.");
         }
 
-        self.preinit()?;
-
-        for source in &sources {
-            self.trace(&format!("Parse code file {:?}", source));
-            self.parse_code(source)?;
-        }
+        self.preinit()
+    }
 
+    fn finalize(&mut self) -> Result<(), String> {
+        self.arity_warnings = self.check_call_arity();
         self.replace_all_labels()?;
 
         self.drop_unused_objects();
@@ -474,6 +571,12 @@ impl ParseEngine {
     }
 
     fn parse_code(&mut self, path: &Path) -> Result<(), String> {
+        let filename = path.file_name().unwrap().to_str().unwrap().to_string();
+        let file = File::open(path).map_err(|e| format!("Can't open file {}: {}", filename, e))?;
+        self.parse_source(filename, BufReader::new(file))
+    }
+
+    fn parse_source<R: BufRead>(&mut self, filename: String, mut reader: R) -> Result<(), String> {
         let globl_regex = Regex::new(PATTERN_GLOBL).unwrap();
         let internal_regex = Regex::new(PATTERN_INTERNAL).unwrap();
         let selector_regex = Regex::new(PATTERN_SELECTOR).unwrap();
@@ -483,28 +586,35 @@ impl ParseEngine {
         let alias_regex = Regex::new(PATTERN_ALIAS).unwrap();
         let type_regex = Regex::new(PATTERN_TYPE).unwrap();
         let size_regex = Regex::new(PATTERN_SIZE).unwrap();
+        let args_regex = Regex::new(PATTERN_ARGS).unwrap();
         let base_glbl_regex = Regex::new(PATTERN_GLBLBASE).unwrap();
         let base_pers_regex = Regex::new(PATTERN_PERSBASE).unwrap();
         let ignored_regex = Regex::new(PATTERN_IGNORED).unwrap();
+        let align_regex = Regex::new(PATTERN_ALIGN).unwrap();
         let public_regex = Regex::new(PATTERN_PUBLIC).unwrap();
         let macro_regex = Regex::new(PATTERN_MACRO).unwrap();
         let loc_regex = Regex::new(PATTERN_LOC).unwrap();
         let version_regex = Regex::new(PATTERN_VERSION).unwrap();
         let pragma_regex = Regex::new(PATTERN_PRAGMA).unwrap();
+        let ifdef_regex = Regex::new(PATTERN_IFDEF).unwrap();
+        let else_regex = Regex::new(PATTERN_ELSE).unwrap();
+        let endif_regex = Regex::new(PATTERN_ENDIF).unwrap();
 
         let mut section_name: String = String::new();
         let mut obj_body: Lines = vec![];
         let mut obj_name: String = "".to_owned();
         let mut lnum = 0;
         let mut l = String::new();
+        // alignment given before the object it applies to
+        let mut next_align: usize = 0;
+        // conditional blocks: (condition of the block, lines are skipped by an outer block)
+        let mut conditions: Vec<(bool, bool)> = vec![];
 
         self.globl_ptr = self.globl_base + OFFSET_GLOBL_DATA;
         self.persistent_ptr = self.persistent_base + OFFSET_PERS_DATA;
 
-        let filename = path.file_name().unwrap().to_str().unwrap().to_string();
-        let file = File::open(path).map_err(|e| format!("Can't open file {}: {}", filename, e))?;
-        let mut reader = BufReader::new(file);
         let mut source_pos: Option<DbgPos> = None;
+        let mut errors = vec![];
 
         while reader.read_line(&mut l)
             .map_err(|_| format!("error while reading line (file: {})", filename))? != 0 {
@@ -519,131 +629,197 @@ impl ParseEngine {
                 None => DbgPos { filename: filename.clone(), line: lnum, line_code: lnum },
                 Some(pos) => pos
             };
-            if ignored_regex.is_match(&l) {
-                //ignore unused parameters
-                debug!("ignored: {}", l);
-            } else if version_regex.is_match(&l) {
-                let cap = version_regex.captures(&l).unwrap();
-                self.version = Some(cap.get(1).unwrap().as_str().to_owned());
-            } else if pragma_regex.is_match(&l) {
-                let cap = pragma_regex.captures(&l).unwrap();
-                match cap.get(1) {
-                    Some(m) => if m.as_str() == "selector-save-my-code" {
-                        self.save_my_code = true
-                    },
-                    None => {}
+            // errors are collected to report all of them at once, parsing goes on from the next line
+            let result = (|| -> Result<(), String> {
+                if let Some(cap) = ifdef_regex.captures(&l) {
+                    // .ifdef x, .ifndef x
+                    let skipped = conditions.last().map(|(cond, skipped)| *skipped || !*cond).unwrap_or(false);
+                    let defined = self.defines.contains_key(cap.get(2).unwrap().as_str());
+                    conditions.push((defined == (cap.get(1).unwrap().as_str() == "ifdef"), skipped));
+                    return Ok(())
+                } else if else_regex.is_match(&l) {
+                    let cond = conditions.last_mut().ok_or(format!("line {}: .else without .ifdef", lnum))?;
+                    cond.0 = !cond.0;
+                    return Ok(())
+                } else if endif_regex.is_match(&l) {
+                    conditions.pop().ok_or(format!("line {}: .endif without .ifdef", lnum))?;
+                    return Ok(())
+                } else if conditions.last().map(|(cond, skipped)| *skipped || !*cond).unwrap_or(false) {
+                    // line is excluded by a conditional block
+                    return Ok(())
                 }
-            } else if base_glbl_regex.is_match(&l) {
-                // .global-base
-                let cap = base_glbl_regex.captures(&l).unwrap();
-                let base = cap.get(1).map(|m| m.as_str())
-                    .ok_or(format!("line {}: invalid syntax for global base", lnum))?;
-                self.globl_base = Ptr::from_str_radix(base, 10)
-                    .map_err(|_| format!("line {}: invalid global base address", lnum))?;
-                self.globl_ptr = self.globl_base + OFFSET_GLOBL_DATA;
-                self.update_predefined();
-            } else if base_pers_regex.is_match(&l) {
-                // .persistent-base
-                let cap = base_pers_regex.captures(&l).unwrap();
-                let base = cap.get(1).map(|m| m.as_str())
-                    .ok_or(format!("line {}: invalid syntax for persistent base", lnum))?;
-                self.persistent_base = Ptr::from_str_radix(base, 10)
-                    .map_err(|_| format!("line {}: invalid persistent base address", lnum))?;
-                self.persistent_ptr = self.persistent_base + OFFSET_PERS_DATA;
-                self.update_predefined();
-            } else if type_regex.is_match(&l) {
-                // .type x, @...
-                //it's a mark for begining of a new object (func or data)
-                self.update(&section_name, &obj_name, &obj_body)
-                    .map_err(|e| format!("line {}: {}", lnum, e))?;
-                section_name = GLOBL.to_owned();
-                obj_body = vec![];
-                let cap = type_regex.captures(&l).unwrap();
-                obj_name = cap.get(1).unwrap().as_str().to_owned();
-                let type_name = cap.get(2).ok_or(format!("line {}: .type option is invalid", lnum))?.as_str();
-                let obj = self.globals.entry(obj_name.clone()).or_insert(Object::new(obj_name.clone(), &type_name));
-                obj.dtype = ObjectType::from(type_name);
-            } else if size_regex.is_match(&l) {
-                // .size x, val
-                let cap = size_regex.captures(&l).unwrap();
-                let name = cap.get(1).unwrap().as_str().to_owned();
-                let size_str = cap.get(2).ok_or(format!("line {}: .size option is invalid", lnum))?.as_str();
-                let item_ref = self.globals.entry(name.clone()).or_insert(Object::new(name, ""));
-                item_ref.size = usize::from_str_radix(size_str, 10).unwrap_or(0);
-            } else if public_regex.is_match(&l) {
-                // .public x
-                let cap = public_regex.captures(&l).unwrap();
-                let name = cap.get(1).unwrap().as_str();
-                self.globals.get_mut(name).and_then(|obj| {obj.public = true; Some(obj)});
-            } else if globl_regex.is_match(&l) {
-                // .globl x
-                let cap = globl_regex.captures(&l).unwrap();
-                let name = cap.get(1).unwrap().as_str().to_owned();
-                self.globals.entry(name.clone()).or_insert(Object::new(name.clone(), ""));
-            } else if macro_regex.is_match(&l) {
-                // .macro x
-                self.update(&section_name, &obj_name, &obj_body)
-                    .map_err(|e| format!("line {}: {}", lnum, e))?;
-                section_name = MACROS.to_owned();
-                obj_body = vec![];
-                obj_name = macro_regex.captures(&l).unwrap().get(1).unwrap().as_str().to_owned();
-            } else if data_regex.is_match(&l) {
-                // .data
-                //ignore, not used
-            } else if selector_regex.is_match(&l) {
-                // .selector
-                self.update(&section_name, &obj_name, &obj_body)?;
-                section_name = SELECTOR.to_owned();
-                obj_name = "".to_owned();
-                obj_body = vec![];
-            } else if internal_regex.is_match(&l) {
-                // .internal
-                self.update(&section_name, &obj_name, &obj_body)
-                    .map_err(|e| format!("line {}: {}", lnum, e))?;
-                section_name = INTERNAL.to_owned();
-                obj_body = vec![];
-                obj_name = internal_regex.captures(&l).unwrap().get(1).unwrap().as_str().to_owned();
-            } else if alias_regex.is_match(&l) {
-                // .internal-alias
-                let cap = alias_regex.captures(&l).unwrap();
-                self.aliases.insert(
-                    cap.get(1).unwrap().as_str().to_owned(),
-                    i32::from_str_radix(cap.get(2).unwrap().as_str(), 10)
-                        .map_err(|_| format!("line: '{}': failed to parse id", lnum))?,
-                );
-            } else if label_regex.is_match(&l) {
-                // ignore labels
-            } else if loc_regex.is_match(&l) {
-                let cap = loc_regex.captures(&l).unwrap();
-                let filename = String::from(cap.get(1).unwrap().as_str());
-                let line = cap.get(2).unwrap().as_str().parse::<usize>().unwrap();
-                if line == 0 { // special value for resetting current source pos
-                    source_pos = None;
+                if ignored_regex.is_match(&l) {
+                    //ignore unused parameters
+                    debug!("ignored: {}", l);
+                } else if align_regex.is_match(&l) {
+                    // .align x, .p2align x
+                    let in_data = section_name == GLOBL && self.globals.get(&obj_name)
+                        .map(|obj| obj.dtype.data().is_some())
+                        .unwrap_or(false);
+                    if in_data {
+                        obj_body.push(Line { text: l.clone(), pos });
+                    } else {
+                        next_align = Self::parse_align(&l).map_err(|e| format!("line {}: {}", lnum, e))?;
+                    }
+                } else if version_regex.is_match(&l) {
+                    let cap = version_regex.captures(&l).unwrap();
+                    self.version = Some(cap.get(1).unwrap().as_str().to_owned());
+                } else if pragma_regex.is_match(&l) {
+                    let cap = pragma_regex.captures(&l).unwrap();
+                    match cap.get(1) {
+                        Some(m) => if m.as_str() == "selector-save-my-code" {
+                            self.save_my_code = true
+                        },
+                        None => {}
+                    }
+                } else if base_glbl_regex.is_match(&l) {
+                    // .global-base
+                    let cap = base_glbl_regex.captures(&l).unwrap();
+                    let base = cap.get(1).map(|m| m.as_str())
+                        .ok_or(format!("line {}: invalid syntax for global base", lnum))?;
+                    self.globl_base = Ptr::from_str_radix(base, 10)
+                        .map_err(|_| format!("line {}: invalid global base address", lnum))?;
+                    self.globl_ptr = self.globl_base + OFFSET_GLOBL_DATA;
+                    self.update_predefined();
+                } else if base_pers_regex.is_match(&l) {
+                    // .persistent-base
+                    let cap = base_pers_regex.captures(&l).unwrap();
+                    let base = cap.get(1).map(|m| m.as_str())
+                        .ok_or(format!("line {}: invalid syntax for persistent base", lnum))?;
+                    self.persistent_base = Ptr::from_str_radix(base, 10)
+                        .map_err(|_| format!("line {}: invalid persistent base address", lnum))?;
+                    self.persistent_ptr = self.persistent_base + OFFSET_PERS_DATA;
+                    self.update_predefined();
+                } else if type_regex.is_match(&l) {
+                    // .type x, @...
+                    //it's a mark for begining of a new object (func or data)
+                    // the new section is started even if the previous one is invalid
+                    let updated = self.update(&section_name, &obj_name, &obj_body)
+                        .map_err(|e| format!("line {}: {}", lnum, e));
+                    section_name = GLOBL.to_owned();
+                    obj_body = vec![];
+                    let cap = type_regex.captures(&l).unwrap();
+                    obj_name = cap.get(1).unwrap().as_str().to_owned();
+                    let type_name = cap.get(2).ok_or(format!("line {}: .type option is invalid", lnum))?.as_str();
+                    let obj = self.globals.entry(obj_name.clone()).or_insert(Object::new(obj_name.clone(), &type_name));
+                    obj.dtype = ObjectType::from(type_name);
+                    obj.align = next_align;
+                    next_align = 0;
+                    updated?;
+                } else if size_regex.is_match(&l) {
+                    // .size x, val
+                    let cap = size_regex.captures(&l).unwrap();
+                    let name = cap.get(1).unwrap().as_str().to_owned();
+                    let size_str = cap.get(2).ok_or(format!("line {}: .size option is invalid", lnum))?.as_str();
+                    let item_ref = self.globals.entry(name.clone()).or_insert(Object::new(name, ""));
+                    item_ref.size = usize::from_str_radix(size_str, 10).unwrap_or(0);
+                } else if let Some(cap) = args_regex.captures(&l) {
+                    // .args x, args[, returns]
+                    let name = cap.get(1).unwrap().as_str().to_owned();
+                    let args = usize::from_str_radix(cap.get(2).unwrap().as_str(), 10)
+                        .map_err(|_| format!("line {}: invalid number of arguments", lnum))?;
+                    let returns = cap.get(3)
+                        .map(|m| usize::from_str_radix(m.as_str(), 10))
+                        .transpose()
+                        .map_err(|_| format!("line {}: invalid number of results", lnum))?;
+                    self.signatures.insert(name, Signature { args, returns });
+                } else if public_regex.is_match(&l) {
+                    // .public x
+                    let cap = public_regex.captures(&l).unwrap();
+                    let name = cap.get(1).unwrap().as_str();
+                    self.globals.get_mut(name).and_then(|obj| {obj.public = true; Some(obj)});
+                } else if globl_regex.is_match(&l) {
+                    // .globl x
+                    let cap = globl_regex.captures(&l).unwrap();
+                    let name = cap.get(1).unwrap().as_str().to_owned();
+                    self.globals.entry(name.clone()).or_insert(Object::new(name.clone(), ""));
+                } else if macro_regex.is_match(&l) {
+                    // .macro x
+                    let updated = self.update(&section_name, &obj_name, &obj_body)
+                        .map_err(|e| format!("line {}: {}", lnum, e));
+                    section_name = MACROS.to_owned();
+                    obj_body = vec![];
+                    obj_name = macro_regex.captures(&l).unwrap().get(1).unwrap().as_str().to_owned();
+                    updated?;
+                } else if data_regex.is_match(&l) {
+                    // .data
+                    //ignore, not used
+                } else if selector_regex.is_match(&l) {
+                    // .selector
+                    let updated = self.update(&section_name, &obj_name, &obj_body);
+                    section_name = SELECTOR.to_owned();
+                    obj_name = "".to_owned();
+                    obj_body = vec![];
+                    updated?;
+                } else if internal_regex.is_match(&l) {
+                    // .internal
+                    let updated = self.update(&section_name, &obj_name, &obj_body)
+                        .map_err(|e| format!("line {}: {}", lnum, e));
+                    section_name = INTERNAL.to_owned();
+                    obj_body = vec![];
+                    obj_name = internal_regex.captures(&l).unwrap().get(1).unwrap().as_str().to_owned();
+                    updated?;
+                } else if alias_regex.is_match(&l) {
+                    // .internal-alias
+                    let cap = alias_regex.captures(&l).unwrap();
+                    self.aliases.insert(
+                        cap.get(1).unwrap().as_str().to_owned(),
+                        i32::from_str_radix(cap.get(2).unwrap().as_str(), 10)
+                            .map_err(|_| format!("line: '{}': failed to parse id", lnum))?,
+                    );
+                } else if LOCAL_LABEL_REGEX.is_match(&l) && (section_name == INTERNAL || (section_name == GLOBL
+                        && self.globals.get(&obj_name).map(|obj| obj.dtype.is_func()).unwrap_or(false))) {
+                    // local labels are resolved when the function is complete
+                    obj_body.push(Line { text: l.clone(), pos });
+                } else if label_regex.is_match(&l) {
+                    // ignore labels
+                } else if loc_regex.is_match(&l) {
+                    let cap = loc_regex.captures(&l).unwrap();
+                    let filename = String::from(cap.get(1).unwrap().as_str());
+                    let line = cap.get(2).unwrap().as_str().parse::<usize>().unwrap();
+                    if line == 0 { // special value for resetting current source pos
+                        source_pos = None;
+                    } else {
+                        source_pos = Some(DbgPos { filename, line, line_code: lnum });
+                    }
+                } else if dotted_regex.is_match(&l) {
+                    // .param [value]
+                    let cap = dotted_regex.captures(&l).unwrap();
+                    let param = cap.get(1).unwrap().as_str();
+                    match param {
+                        "blob" | "cell" | "byte" | "long" | "short" | "quad" | "comm" | "bss" | "asciz" | "compute" => {
+                            obj_body.push(Line { text: l.clone(), pos })
+                        },
+                        "insn" => {
+                            let mut lines = Self::expand_insn(&l, &pos).map_err(|e| format!("line {}: {}", lnum, e))?;
+                            obj_body.append(&mut lines)
+                        },
+                        _ => Err(format!("line {}: invalid param \"{}\":{}", lnum, param, l))?,
+                    };
                 } else {
-                    source_pos = Some(DbgPos { filename, line, line_code: lnum });
+                    obj_body.push(Line { text: l.clone(), pos });
                 }
-            } else if dotted_regex.is_match(&l) {
-                // .param [value]
-                let cap = dotted_regex.captures(&l).unwrap();
-                let param = cap.get(1).unwrap().as_str();
-                match param {
-                    "blob" | "cell" | "byte" | "long" | "short" | "quad" | "comm" | "bss" | "asciz" | "compute" => {
-                        obj_body.push(Line { text: l.clone(), pos })
-                    },
-                    _ => Err(format!("line {}: invalid param \"{}\":{}", lnum, param, l))?,
-                };
-            } else {
-                obj_body.push(Line { text: l.clone(), pos });
+                Ok(())
+            })();
+            if let Err(e) = result {
+                errors.push(e);
             }
             l.clear();
         }
 
+        if !conditions.is_empty() {
+            errors.push(format!("line {}: .ifdef without .endif", lnum));
+        }
         if section_name.is_empty() {
             return Err("input file has no assembler definitions".to_string());
         }
 
-        self.update(&section_name, &obj_name, &obj_body)
-            .map_err(|e| format!("line {}: {}", lnum, e))?;
+        if let Err(e) = self.update(&section_name, &obj_name, &obj_body) {
+            errors.push(format!("line {}: {}", lnum, e));
+        }
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
         Ok(())
     }
 
@@ -672,18 +848,24 @@ impl ParseEngine {
 
     fn replace_labels_in_body(&mut self, lines: Vec<Line>, obj_name: FunctionId) -> Result<Vec<Line>, String> {
         let mut new_lines = vec![];
+        let mut errors = vec![];
         for line in lines {
             if COMPUTE_REGEX.is_match(&line.text) {
                 let name = COMPUTE_REGEX.captures(&line.text).unwrap().get(1).unwrap().as_str();
-                let mut resolved = self.compute_cell(name)?;
-                new_lines.append(&mut resolved);
+                match self.compute_cell(name) {
+                    Ok(mut resolved) => new_lines.append(&mut resolved),
+                    Err(e) => errors.push(e),
+                }
                 self.trace("Compute expression was replaced.");
                 continue
             }
-            let mut resolved =
-                self.replace_labels(&line, &obj_name)
-                    .map_err(|e| format!("line {}: cannot resolve label: {}", line.pos.line, e))?;
-            new_lines.append(&mut resolved);
+            match self.replace_labels(&line, &obj_name) {
+                Ok(mut resolved) => new_lines.append(&mut resolved),
+                Err(e) => errors.push(format!("line {}: cannot resolve label: {}", line.pos.line, e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
         }
         Ok(new_lines)
     }
@@ -691,13 +873,20 @@ impl ParseEngine {
     // return true, if at least one label was replaced
     fn try_replace_labels(&mut self) -> Result<bool, String> {
         let mut did_some = false;
+        let mut errors = vec![];
 
         let names = self.globals.keys().map(|k| k.clone()).collect::<Vec<_>>();
         for name in &names {
             if let ObjectType::Function(f) = &self.globals.get(name).unwrap().dtype {
                 let lines = f.body.clone();
                 let obj_name = self.globals.get(name).unwrap().name.clone();
-                let new_lines = self.replace_labels_in_body(lines, FunctionId::Name(obj_name))?;
+                let new_lines = match self.replace_labels_in_body(lines, FunctionId::Name(obj_name)) {
+                    Ok(new_lines) => new_lines,
+                    Err(e) => {
+                        errors.push(e);
+                        continue
+                    }
+                };
 
                 let body = &mut self.globals.get_mut(name).unwrap().dtype.func_mut().unwrap().body;
                 did_some = did_some || *body != new_lines;
@@ -711,7 +900,13 @@ impl ParseEngine {
         let ids = self.internals.keys().map(|x| *x).collect::<Vec<_>>();
         for id in &ids {
             let lines = self.internals.get(id).unwrap().body.clone();
-            let new_lines = self.replace_labels_in_body(lines, FunctionId::Id(*id))?;
+            let new_lines = match self.replace_labels_in_body(lines, FunctionId::Id(*id)) {
+                Ok(new_lines) => new_lines,
+                Err(e) => {
+                    errors.push(e);
+                    continue
+                }
+            };
             let body = &mut self.internals.get_mut(id).unwrap().body;
             did_some = did_some || *body != new_lines;
             *body = new_lines;
@@ -720,6 +915,9 @@ impl ParseEngine {
             }
         }
 
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
         Ok(did_some)
     }
 
@@ -761,6 +959,7 @@ impl ParseEngine {
 
                 if self.globals.get(name).unwrap().dtype.is_func() {
                     // globl object is function
+                    let body = &self.split_local_labels(name, body)?;
                     let func_id = self.create_function_id(name);
                     let item = self.globals.get_mut(name).unwrap();
                     let params = item.dtype.func_mut().unwrap();
@@ -778,19 +977,22 @@ impl ParseEngine {
                     // globl object is data
                     let item = self.globals.get_mut(name).unwrap();
                     let data = item.dtype.data_mut().unwrap();
-                    Self::update_data(body, name, &mut item.size, &mut data.values)?;
+                    Self::update_data(body, name, &mut item.size, &mut item.align, &mut data.values)?;
                     let offset = (data.values.len() as Ptr) * WORD_SIZE;
-                    if name.ends_with(PERSISTENT_DATA_SUFFIX) {
+                    let align = std::cmp::max(item.align as Ptr, 1);
+                    let ptr = if name.ends_with(PERSISTENT_DATA_SUFFIX) {
                         data.persistent = true;
-                        data.addr = self.persistent_ptr;
-                        self.persistent_ptr += offset;
+                        &mut self.persistent_ptr
                     } else {
-                        data.addr = self.globl_ptr;
-                        self.globl_ptr += offset;
-                    }
+                        &mut self.globl_ptr
+                    };
+                    *ptr = (*ptr + align - 1) / align * align;
+                    data.addr = *ptr;
+                    *ptr += offset;
                 }
             },
             INTERNAL => {
+                let body = &self.split_local_labels(name, body)?;
                 let func_id = self.aliases.get(name).ok_or(format!("id for '{}' not found", name))?;
                 self.intrefs.insert(name.to_string(), *func_id);
                 let prev = self.internals.insert(*func_id,Func::new());
@@ -810,19 +1012,150 @@ impl ParseEngine {
         Ok(())
     }
 
+    /// Splits the function at local numeric labels (`1:`) placed at its top level: the code
+    /// following a label becomes a private function named `<function>.<label>-<n>`, which can't
+    /// collide with other symbols, and the code before the label jumps to it with JMPDICT.
+    /// References `$1b$` and `$1f$` are replaced with the nearest definition of the label
+    /// before or after the line. Returns the code before the first label.
+    fn split_local_labels(&mut self, name: &str, body: &Lines) -> Result<Lines, String> {
+        let mut labels: Vec<(usize, String)> = vec![];
+        let mut depth = 0;
+        for (i, line) in body.iter().enumerate() {
+            if let Some(cap) = LOCAL_LABEL_REGEX.captures(&line.text) {
+                if depth != 0 {
+                    return Err(format!("local label {}: at line {} is inside a continuation", &cap[1], line.pos.line_code))
+                }
+                labels.push((i, cap[1].to_string()));
+                continue
+            }
+            let code = line.text.split(';').next().unwrap();
+            depth += code.matches('{').count() as isize - code.matches('}').count() as isize;
+        }
+        let segment_name = |n: usize| format!("{}.{}-{}", name, labels[n].1, n + 1);
+
+        let mut segments = vec![vec![]];
+        for (i, line) in body.iter().enumerate() {
+            if let Some(n) = labels.iter().position(|(index, _)| *index == i) {
+                // the code before the label falls through to it
+                segments.last_mut().unwrap().push(Line { text: format!("JMPDICT ${}$\n", segment_name(n)), pos: line.pos.clone() });
+                segments.push(vec![]);
+                continue
+            }
+            let mut text = String::new();
+            let mut end = 0;
+            for cap in LOCAL_REF_REGEX.captures_iter(&line.text) {
+                let target = if &cap[2] == "b" {
+                    labels.iter().rposition(|(index, label)| *index < i && label == &cap[1])
+                } else {
+                    labels.iter().position(|(index, label)| *index > i && label == &cap[1])
+                };
+                let n = target.ok_or(format!("local label {}{} used at line {} is not defined", &cap[1], &cap[2], line.pos.line_code))?;
+                let whole = cap.get(0).unwrap();
+                text += &line.text[end..whole.start()];
+                text += &format!("${}{}", segment_name(n), &cap[3]);
+                end = whole.end();
+            }
+            text += &line.text[end..];
+            segments.last_mut().unwrap().push(Line { text, pos: line.pos.clone() });
+        }
+
+        let head = segments.remove(0);
+        for (n, segment) in segments.into_iter().enumerate() {
+            let full_name = segment_name(n);
+            self.next_private_globl_funcid += 1;
+            let id = self.next_private_globl_funcid;
+            let mut obj = Object::new(full_name.clone(), "function");
+            let func = obj.dtype.func_mut().unwrap();
+            func.id = id;
+            func.body = segment;
+            self.globals.insert(full_name.clone(), obj);
+            self.xrefs.insert(full_name, id);
+        }
+        Ok(head)
+    }
+
+    /// Expands `.insn <hex-bits>[, <hex-bits>...]` into raw bits of the instruction
+    /// followed by cells it references, bits are given as in `.blob` without `x`.
+    fn expand_insn(line: &str, pos: &DbgPos) -> Result<Lines, String> {
+        let text = line.split(';').next().unwrap().trim();
+        let args: Vec<&str> = text[".insn".len()..].split(',')
+            .map(|arg| arg.trim().trim_start_matches('x'))
+            .collect();
+        for arg in &args {
+            let digits = arg.trim_end_matches('_');
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("invalid .insn bits \"{}\"", arg))
+            }
+        }
+        let line = |text: String| Line { text, pos: pos.clone() };
+        let mut lines = vec![line(format!(".blob x{}\n", args[0]))];
+        for bits in &args[1..] {
+            lines.push(line(".cell {\n".to_string()));
+            lines.push(line(format!(".blob x{}\n", bits)));
+            lines.push(line("}\n".to_string()));
+        }
+        Ok(lines)
+    }
+
+    /// Returns alignment in bytes: `.align` takes bytes, `.p2align` takes a power of two.
+    fn parse_align(line: &str) -> Result<usize, String> {
+        lazy_static! {
+            static ref ALIGN_RE: Regex = Regex::new(PATTERN_ALIGN).unwrap();
+        }
+        let cap = ALIGN_RE.captures(line).ok_or(format!("invalid alignment: \"{}\"", line.trim()))?;
+        let value = usize::from_str_radix(cap.get(2).unwrap().as_str(), 10)
+            .map_err(|_| format!("invalid alignment: \"{}\"", line.trim()))?;
+        let align = match cap.get(1).unwrap().as_str() {
+            "p2align" if value < 16 => 1 << value,
+            "align" if value.is_power_of_two() => value,
+            _ => Err(format!("invalid alignment: \"{}\"", line.trim()))?,
+        };
+        Ok(align)
+    }
+
     fn update_data(
         body: &Lines,
         name: &str,
         item_size: &mut usize,
+        item_align: &mut usize,
         values: &mut Vec<DataValue>,
     ) -> Result<(), String> {
         lazy_static! {
             static ref PARAM_RE: Regex = Regex::new(PATTERN_PARAM).unwrap();
             static ref COMM_RE:  Regex = Regex::new(PATTERN_COMM).unwrap();
             static ref ASCI_RE:  Regex = Regex::new(PATTERN_ASCIZ).unwrap();
+            static ref ALIGN_RE: Regex = Regex::new(PATTERN_ALIGN).unwrap();
         }
+        let mut offset: usize = 0;
+        let mut pending_align: usize = 0;
         for param in body {
             let mut value_len: usize = 0;
+            if ALIGN_RE.is_match(param.text.as_str()) {
+                // .align <bytes>, .p2align <power>
+                let align = Self::parse_align(&param.text)?;
+                if offset == 0 {
+                    *item_align = std::cmp::max(*item_align, align);
+                } else {
+                    pending_align = std::cmp::max(pending_align, align);
+                }
+                continue;
+            } else if param.text.trim() == ".bss" {
+                //ignore this directive
+                continue;
+            }
+            if pending_align > 1 {
+                // pad with zero bytes up to the boundary, padding is a part of the object size
+                let padding = (pending_align - offset % pending_align) % pending_align;
+                for _i in 0..padding {
+                    values.push(DataValue::Number((IntegerData::zero(), 1)));
+                }
+                if *item_size < padding {
+                    Err(format!("global object {} has invalid .size parameter: too small", name))?;
+                }
+                *item_size -= padding;
+                offset += padding;
+            }
+            pending_align = 0;
             if let Some(cap) = COMM_RE.captures(param.text.as_str()) {
                 // .comm <symbol>, <size>, <align>
                 let size_bytes = usize::from_str_radix(
@@ -845,8 +1178,6 @@ impl ParseEngine {
                     values.push(DataValue::Number((IntegerData::zero(), WORD_SIZE as usize)));
                 }
                 *item_size = value_len;
-            } else if param.text.trim() == ".bss" {
-                //ignore this directive
             } else if let Some(cap) = ASCI_RE.captures(param.text.as_str()) {
                 // .asciz "string"
                 let mut str_bytes = cap.get(1).unwrap().as_str().as_bytes().to_vec();
@@ -876,6 +1207,7 @@ impl ParseEngine {
                 Err(format!("global object {} has invalid .size parameter: too small", name))?;
             }
             *item_size -= value_len;
+            offset += value_len;
         }
         if *item_size > 0 {
             Err(format!("global object {} has invalid \".size\" value: real size = {}", name, *item_size))?;
@@ -987,10 +1319,12 @@ impl ParseEngine {
 
     fn replace_labels(&mut self, line: &Line, cur_obj_name: &FunctionId) -> Result<Lines, String> {
         resolve_name(line, |name| {
-            self.intrefs.get(name).and_then(|id| {
+            let id = self.intrefs.get(name).cloned();
+            if id.is_some() {
                 self.trace(&format!("Replaced internal function reference {}", name));
-                Some(id.clone())
-            })
+                self.used_internals.insert(name.to_string());
+            }
+            id
         })
         .or_else(|_| resolve_name(line, |name| {
             let mut res = self.xrefs.get(name).map(|id| id.clone());
@@ -1018,6 +1352,10 @@ impl ParseEngine {
                 _ => None,
             }
         }))
+        .or_else(|_| resolve_name(line, |name| {
+            self.trace(&format!("Replaced defined symbol {}", name));
+            self.defines.get(name).cloned()
+        }))
         .or_else(|e| {
             let mut name = String::new();
             resolve_name(line, |n| { name = n.to_string(); Some(0)}).unwrap();
@@ -1050,11 +1388,15 @@ impl ParseEngine {
         }
     }
 
+    /// Keeps only functions reachable from internal functions (called by the selector)
+    /// and public functions. Public functions of libraries are kept only if they are
+    /// listed in the contract ABI, otherwise they are linked only when called.
     fn drop_unused_objects(&mut self) {
         let mut ids = HashSet::new();
-        let publics_iter = self.globals.iter().filter_map(|obj| {
-            obj.1.dtype.func()
-                .and_then(|i| if obj.1.public { Some(i) } else { None })
+        let publics_iter = self.globals.iter().filter_map(|(name, obj)| {
+            let exported = !self.library_objects.contains(name) || self.is_public(name);
+            obj.dtype.func()
+                .and_then(|i| if obj.public && exported { Some(i) } else { None })
         });
 
         for func in publics_iter {
@@ -1064,16 +1406,85 @@ impl ParseEngine {
             self.enum_calling_funcs(&func.1, &mut ids);
         }
 
-        self.globals.retain(|_k, v| {
-            v.dtype.func()
+        let verbose = self.verbose;
+        self.globals.retain(|k, v| {
+            let used = v.dtype.func()
                 .map(|f| ids.contains(&f.id))
-                .unwrap_or(true)
+                .unwrap_or(true);
+            if !used && verbose {
+                println!("VERBOSE: Unused function {} is dropped", k);
+            }
+            used
         });
         self.xrefs.retain(|_k, v| {
             ids.contains(&v)
         });
     }
 
+    /// Skeleton ABI listing public functions with their ids. Parameter lists are left
+    /// empty and functions are marked unsigned, they should be corrected by hand.
+    pub fn abi_stub(&self) -> serde_json::Value {
+        use serde_json::{Map, Value};
+        let mut publics: Vec<(&String, u32)> = self.globals.values()
+            .filter(|obj| obj.public)
+            .filter_map(|obj| obj.dtype.func().map(|func| (&obj.name, func.id)))
+            .collect();
+        publics.sort();
+        let functions = publics.into_iter().map(|(name, id)| {
+            let mut function = Map::new();
+            function.insert("name".to_string(), Value::from(name.as_str()));
+            function.insert("id".to_string(), Value::from(format!("0x{:08x}", id)));
+            function.insert("signed".to_string(), Value::from(false));
+            function.insert("inputs".to_string(), Value::Array(vec![]));
+            function.insert("outputs".to_string(), Value::Array(vec![]));
+            Value::Object(function)
+        }).collect();
+        let mut abi = Map::new();
+        abi.insert("ABI version".to_string(), Value::from(1));
+        abi.insert("functions".to_string(), Value::Array(functions));
+        abi.insert("events".to_string(), Value::Array(vec![]));
+        abi.insert("data".to_string(), Value::Array(vec![]));
+        Value::Object(abi)
+    }
+
+    /// Reports internal aliases declared without a body and internal functions never
+    /// referenced by name. Entry points (ids 0 and below) are called by the selector.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut aliases = self.aliases.iter().collect::<Vec<_>>();
+        aliases.sort();
+        let mut warnings = vec![];
+        for (name, id) in aliases {
+            if !self.intrefs.contains_key(name) {
+                warnings.push(format!("internal alias {} ({}) is declared but has no body", name, id));
+            } else if *id > 0 && !self.used_internals.contains(name) {
+                warnings.push(format!("internal function {} ({}) is never referenced", name, id));
+            }
+        }
+        warnings.extend(self.arity_warnings.iter().cloned());
+        warnings
+    }
+
+    /// Checks call sites in functions with declared arguments, must be done
+    /// before labels are replaced with function ids.
+    fn check_call_arity(&self) -> Vec<String> {
+        if self.signatures.is_empty() {
+            return vec![]
+        }
+        let mut bodies: Vec<(&String, &Lines)> = self.globals.iter()
+            .filter_map(|(name, obj)| obj.dtype.func().map(|func| (name, &func.body)))
+            .collect();
+        bodies.extend(self.intrefs.iter()
+            .filter_map(|(name, id)| self.internals.get(id).map(|func| (name, &func.body))));
+        bodies.sort_by_key(|(name, _)| name.clone());
+        let mut warnings = vec![];
+        for (name, body) in bodies {
+            if let Some(signature) = self.signatures.get(name) {
+                warnings.append(&mut check_calls(body, signature.args, &self.signatures));
+            }
+        }
+        warnings
+    }
+
     fn enum_calling_funcs(&self, func: &Func, ids: &mut HashSet<u32>) {
         ids.insert(func.id); // TODO there are public/private globs and internals
 
@@ -1277,6 +1688,121 @@ mod tests {
         assert_eq!(parser.is_ok(), true);
     }
 
+    #[test]
+    fn test_multiple_errors() {
+        let source = "\t.internal :first\n\tPUSHINT 1\n\t.internal :second\n\tPUSHINT 2\n";
+        let err = ParseEngine::from_sources(vec![("test.tvm", source)], None, false).err().unwrap();
+        assert_eq!(err, "line 3: id for ':first' not found\nline 4: id for ':second' not found");
+    }
+
+    #[test]
+    fn test_unused_internals() {
+        let source = "\t.internal-alias :used, 1\n\t.internal-alias :unused, 2\n\t.internal-alias :missing, 3\n\
+            \t.internal :used\n\tPUSHINT 1\n\t.internal :unused\n\tCALL $:used$\n";
+        let parser = ParseEngine::from_sources(vec![("test.tvm", source)], None, false).unwrap();
+        assert_eq!(parser.warnings(), vec![
+            "internal alias :missing (3) is declared but has no body".to_string(),
+            "internal function :unused (2) is never referenced".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_data_align() {
+        let source = "\t.type a,@object\n\t.globl a\na:\n\t.byte 1\n\t.p2align 2\n\t.long 5\n\t.size a, 8\n\
+            \t.type b,@object\n\t.globl b\n\t.align 16\nb:\n\t.quad 7\n\t.size b, 8\n";
+        let parser = ParseEngine::from_sources(vec![("test.s", source)], None, false).unwrap();
+        let a = parser.globals.get("a").unwrap();
+        assert_eq!(a.dtype.data().unwrap().addr, 8);
+        assert_eq!(a.dtype.data().unwrap().values.len(), 5);
+        let b = parser.globals.get("b").unwrap();
+        assert_eq!(b.align, 16);
+        assert_eq!(b.dtype.data().unwrap().addr, 16);
+    }
+
+    #[test]
+    fn test_defines() {
+        let source = "\t.internal-alias :main, 1\n\t.internal :main\n\
+            .ifdef DEBUG\n\tPUSHINT $LEVEL$\n.else\n\tPUSHINT 0\n.endif\n\
+            .ifndef DEBUG\n\tDROP\n.endif\n";
+        let mut parser = ParseEngine::empty(false);
+        parser.defines.insert("DEBUG".to_string(), 1);
+        parser.defines.insert("LEVEL".to_string(), 3);
+        parser.parse_source("test.tvm".to_string(), source.as_bytes()).unwrap();
+        parser.finalize().unwrap();
+        let body = parser.internals().get(&1).unwrap().clone();
+        assert_eq!(lines_to_string(&body).trim(), "PUSHINT 3");
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let source = "\t.internal-alias :main, 1\n\t.internal :main\n\
+            \tPUSHINT 3\n1:\n\tDEC\n\tDUP\n\tPUSHCONT {\n\t\tJMPDICT $1b$\n\t}\n\tIF\n\tCALL $1f$\n\
+            1: ; the same label again\n\tCALL $foo$\n\
+            \t.globl\tfoo\n\t.type\tfoo,@function\nfoo:\n\tPUSHINT $1f$\n1:\n\tINC\n";
+        let parser = ParseEngine::from_sources(vec![("test.tvm", source)], None, false).unwrap();
+        let id = |name: &str| parser.global_by_name(name).unwrap().0;
+        let body = |name: &str| lines_to_string(&parser.global_by_name(name).unwrap().1);
+        let (first, second, foo, foo_first) = (id(":main.1-1"), id(":main.1-2"), id("foo"), id("foo.1-1"));
+        assert_eq!(lines_to_string(parser.internals().get(&1).unwrap()),
+            format!("\tPUSHINT 3\nJMPDICT {}\n", first));
+        assert_eq!(body(":main.1-1"),
+            format!("\tDEC\n\tDUP\n\tPUSHCONT {{\n\t\tJMPDICT {}\n\t}}\n\tIF\n\tCALL {}\nJMPDICT {}\n", first, second, second));
+        assert_eq!(body(":main.1-2"), format!("\tCALL {}\n", foo));
+        // labels of different functions don't collide
+        assert_eq!(body("foo"), format!("\tPUSHINT {}\nJMPDICT {}\n", foo_first, foo_first));
+        assert_eq!(body("foo.1-1"), "\tINC\n");
+
+        let source = "\t.internal-alias :main, 1\n\t.internal :main\n\tCALL $2f$\n2:\n\tCALL $2f$\n";
+        let err = ParseEngine::from_sources(vec![("test.tvm", source)], None, false).err().unwrap();
+        assert!(err.contains("local label 2f used at line 5 is not defined"), "{}", err);
+        let source = "\t.internal-alias :main, 1\n\t.internal :main\n\tPUSHCONT {\n1:\n\t}\n";
+        let err = ParseEngine::from_sources(vec![("test.tvm", source)], None, false).err().unwrap();
+        assert!(err.contains("local label 1: at line 4 is inside a continuation"), "{}", err);
+    }
+
+    #[test]
+    fn test_abi_stub() {
+        let source = "\t.globl\tfoo\n\t.public\tfoo\n\t.type\tfoo,@function\nfoo:\n\tRET\n\
+            \t.globl\tbar\n\t.type\tbar,@function\nbar:\n\tRET\n";
+        let parser = ParseEngine::from_sources(vec![("test.s", source)], None, false).unwrap();
+        let stub = parser.abi_stub();
+        let functions = stub["functions"].as_array().unwrap();
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0]["name"], "foo");
+        assert_eq!(functions[0]["id"], format!("0x{:08x}", gen_abi_id(None, "foo")));
+    }
+
+    #[test]
+    fn test_library_tree_shaking() {
+        let library = "\t.globl\tused\n\t.type\tused,@function\nused:\n\tRET\n\
+            \t.globl\tunused\n\t.type\tunused,@function\nunused:\n\tRET\n\
+            \t.globl\texported\n\t.public\texported\n\t.type\texported,@function\nexported:\n\tRET\n";
+        let source = "\t.globl\tmain\n\t.public\tmain\n\t.type\tmain,@function\nmain:\n\tCALL $used$\n";
+        let parser = ParseEngine::from_sources(vec![("lib.tvm", library), ("test.s", source)], None, false).unwrap();
+        let mut names = parser.xrefs.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["main".to_string(), "used".to_string()]);
+    }
+
+    #[test]
+    fn test_call_arity() {
+        let source = "\t.args\tcallee, 2, 1\n\t.args\tcaller, 1\n\
+            \t.globl\tcallee\n\t.type\tcallee,@function\ncallee:\n\tADD\n\
+            \t.globl\tcaller\n\t.type\tcaller,@function\ncaller:\n\tPUSHINT 1\n\tCALL $callee$\n\tCALL $callee$\n";
+        let parser = ParseEngine::from_sources(vec![("test.s", source)], None, false).unwrap();
+        assert_eq!(parser.warnings(), vec!["test.s:12: callee takes 2 arguments, the stack has 1".to_string()]);
+    }
+
+    #[test]
+    fn test_raw_insn() {
+        let source = "\t.globl\tfoo\n\t.public\tfoo\n\t.type\tfoo,@function\nfoo:\n\t.insn fe00 ; DUMPSTK\n\t.insn 88, 71a0\n";
+        let parser = ParseEngine::from_sources(vec![("test.s", source)], None, false).unwrap();
+        let body = parser.publics().remove(&gen_abi_id(None, "foo")).unwrap();
+        let texts: Vec<&str> = body.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec![".blob xfe00\n", ".blob x88\n", ".cell {\n", ".blob x71a0\n", "}\n"]);
+        assert!(ParseEngine::from_sources(vec![("test.s", "\t.globl\tfoo\n\t.type\tfoo,@function\nfoo:\n\t.insn xyz\n")], None, false).is_err());
+    }
+
     #[test]
     fn test_macros() {
         let sources = vec![Path::new("./tests/test_stdlib.tvm"),
@@ -0,0 +1,96 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use coverage::source_functions;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use ton_labs_assembler::DbgInfo;
+use ton_vm::executor::{EngineTraceInfo, EngineTraceInfoType};
+
+/// Gas profile of an execution in folded stacks format (`main;foo;bar 123`)
+/// consumed by inferno and flamegraph.pl.
+///
+/// Frames are resolved to function names using the debug map and the sources it
+/// refers to. A frame is pushed after CALL instructions and popped when execution
+/// returns into the function of the frame below.
+pub struct Profile {
+    debug_info: Option<DbgInfo>,
+    sources: HashMap<String, Vec<(String, usize)>>,
+    stack: Vec<String>,
+    pending_call: bool,
+    pending_ret: bool,
+    folded: BTreeMap<String, i64>,
+}
+
+impl Profile {
+    pub fn new(debug_info: Option<DbgInfo>) -> Self {
+        Profile {
+            debug_info,
+            sources: HashMap::new(),
+            stack: vec![],
+            pending_call: false,
+            pending_ret: false,
+            folded: BTreeMap::new(),
+        }
+    }
+
+    fn function_name(&mut self, info: &EngineTraceInfo) -> String {
+        let hash = info.cmd_code.cell().repr_hash();
+        let pos = self.debug_info.as_ref()
+            .and_then(|debug_info| debug_info.get(&hash))
+            .and_then(|offsets| offsets.get(&info.cmd_code.pos()))
+            .map(|pos| (pos.filename.clone(), pos.line));
+        let (filename, line) = match pos {
+            Some(pos) => pos,
+            None => return format!("cell_{}", &hash.to_hex_string()[..8]),
+        };
+        let functions = self.sources.entry(filename.clone()).or_insert_with(|| {
+            std::fs::read_to_string(&filename)
+                .map(|source| source_functions(&source))
+                .unwrap_or_default()
+        });
+        match functions.iter().rev().find(|(_, start)| *start <= line) {
+            Some((name, _)) => name.clone(),
+            None => format!("{}:{}", filename, line),
+        }
+    }
+
+    pub fn record(&mut self, info: &EngineTraceInfo) {
+        if info.info_type != EngineTraceInfoType::Normal && info.info_type != EngineTraceInfoType::Implicit {
+            return
+        }
+        let name = self.function_name(info);
+        if self.pending_ret && self.stack.len() > 1 && self.stack[self.stack.len() - 2] == name {
+            self.stack.pop();
+        }
+        if self.stack.is_empty() || self.pending_call {
+            self.stack.push(name);
+        } else if self.stack.last() != Some(&name) {
+            *self.stack.last_mut().unwrap() = name;
+        }
+        *self.folded.entry(self.stack.join(";")).or_insert(0) += info.gas_cmd;
+
+        self.pending_call = info.cmd_str.starts_with("CALL");
+        self.pending_ret = info.cmd_str.starts_with("RET") || info.cmd_str.contains("implicit RET");
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        let mut file = File::create(filename)
+            .map_err(|e| format!("Failed to create file {}: {}", filename, e))?;
+        for (stack, gas) in &self.folded {
+            writeln!(file, "{} {}", stack, gas)
+                .map_err(|e| format!("Failed to write data to file: {}", e))?;
+        }
+        Ok(())
+    }
+}
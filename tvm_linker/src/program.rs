@@ -13,8 +13,8 @@
 use base64::encode;
 use crc16::*;
 use ed25519_dalek::*;
-use std::io::Cursor;
-use std::io::Write;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Write};
 use std::collections::HashMap;
 use std::time::SystemTime;
 use methdict::*;
@@ -30,6 +30,9 @@ pub struct Program {
     language: Option<String>,
     engine: ParseEngineResults,
     keypair: Option<Keypair>,
+    split_depth: Option<u8>,
+    special: Option<TickTock>,
+    share_bodies: bool,
     pub dbgmap: DbgInfo,
 }
 
@@ -39,6 +42,9 @@ impl Program {
             language: None,
             engine: ParseEngineResults::new(parser),
             keypair: None,
+            split_depth: None,
+            special: None,
+            share_bodies: false,
             dbgmap: DbgInfo::new(),
         }
     }
@@ -51,6 +57,22 @@ impl Program {
         self.language = lang.map(|s| s.to_owned());
     }
 
+    /// Sets split depth of the contract state, it is taken into account in the contract address.
+    pub fn set_split_depth(&mut self, depth: Option<u8>) {
+        self.split_depth = depth;
+    }
+
+    /// Marks the contract as special to be called in tick and/or tock transactions.
+    pub fn set_ticktock(&mut self, tick: bool, tock: bool) {
+        self.special = if tick || tock { Some(TickTock { tick, tock }) } else { None };
+    }
+
+    /// Stores identical function bodies once in a cell referenced from every id instead of inline,
+    /// calls of such functions pay for loading the cell.
+    pub fn set_share_bodies(&mut self, share: bool) {
+        self.share_bodies = share;
+    }
+
     pub fn data(&self) -> std::result::Result<Cell, String> {
         let bytes =
             if let Some(ref pair) = self.keypair {
@@ -85,7 +107,7 @@ impl Program {
     }
 
     pub fn internal_method_dict(&mut self) -> std::result::Result<Option<Cell>, String> {
-        let mut dict = prepare_methods(&self.engine.privates(), true)
+        let mut dict = prepare_methods(&self.engine.privates(), true, self.share_bodies)
             .map_err(|e| e.1.replace("_name_", &self.engine.global_name(e.0).unwrap()))?;
         self.dbgmap.append(&mut dict.1);
         Ok(dict.0.data().map(|cell| cell.clone()))
@@ -99,10 +121,10 @@ impl Program {
     }
 
     pub fn public_method_dict(&mut self, remove_ctor: bool) -> std::result::Result<Option<Cell>, String> {
-        let mut dict = prepare_methods(&self.engine.internals(), true)
+        let mut dict = prepare_methods(&self.engine.internals(), true, self.share_bodies)
             .map_err(|e| e.1.replace("_name_", &self.engine.internal_name(e.0).unwrap()) )?;
 
-        insert_methods(&mut dict.0, &mut dict.1, &self.publics_filtered(remove_ctor), true)
+        insert_methods(&mut dict.0, &mut dict.1, &self.publics_filtered(remove_ctor), true, self.share_bodies)
             .map_err(|e| e.1.replace("_name_", &self.engine.global_name(e.0).unwrap()) )?;
 
         self.dbgmap.append(&mut dict.1);
@@ -124,15 +146,8 @@ impl Program {
         trace: bool,
         data_filename: Option<&str>,
     ) -> std::result::Result<String, String> {
-        let mut state_init = self.compile_to_state()?;
-        if let Some(ctor_params) = ctor_params {
-            state_init = self.apply_constructor(state_init, abi_file.unwrap(), ctor_params, trace)?;
-        }
-        if let Some(data_filename) = data_filename {
-            let mut data_cursor = Cursor::new(std::fs::read(data_filename).unwrap());
-            let data_cell = deserialize_cells_tree(&mut data_cursor).unwrap().remove(0);
-            state_init.set_data(data_cell);
-        }
+        let trace_level = if trace { TraceLevel::Full } else { TraceLevel::None };
+        let state_init = self.compile_to_state_ex(abi_file, ctor_params, trace_level, data_filename)?;
         let ret = save_to_file(state_init.clone(), out_file, wc);
         if out_file.is_some() && ret.is_ok() {
             println!("Contract successfully compiled. Saved to file {}.", out_file.unwrap());
@@ -141,14 +156,62 @@ impl Program {
         return ret;
     }
 
+    /// Compiles the contract like `compile_to_file_ex` without printing anything and returns
+    /// the saved file name, address and hashes as variables for `print_env`.
+    pub fn compile_to_env(
+        &mut self,
+        wc: i8,
+        abi_file: Option<&str>,
+        ctor_params: Option<&str>,
+        out_file: Option<&str>,
+        trace: bool,
+        data_filename: Option<&str>,
+    ) -> std::result::Result<Vec<(&'static str, String)>, String> {
+        let trace_level = if trace { TraceLevel::Full } else { TraceLevel::Silent };
+        let state_init = self.compile_to_state_ex(abi_file, ctor_params, trace_level, data_filename)?;
+        let address = state_init.hash().map_err(|e| format!("Failed to calculate hash: {}", e))?;
+        let file_name = save_to_file_ex(state_init.clone(), out_file, wc, false)?;
+        let hash = |cell: Option<Cell>| cell.map(|c| c.repr_hash().to_hex_string()).unwrap_or_default();
+        Ok(vec![
+            ("TVC", file_name),
+            ("ADDRESS", format!("{}:{:x}", wc, address)),
+            ("CODE_HASH", hash(state_init.code)),
+            ("DATA_HASH", hash(state_init.data)),
+        ])
+    }
+
+    /// Compiles the contract, applies the constructor and replaces data if requested.
+    pub fn compile_to_state_ex(
+        &mut self,
+        abi_file: Option<&str>,
+        ctor_params: Option<&str>,
+        trace_level: TraceLevel,
+        data_filename: Option<&str>,
+    ) -> std::result::Result<StateInit, String> {
+        let mut state_init = self.compile_to_state()?;
+        if let Some(ctor_params) = ctor_params {
+            state_init = self.apply_constructor(state_init, abi_file.unwrap(), ctor_params, trace_level)?;
+        }
+        if let Some(data_filename) = data_filename {
+            let file = File::open(data_filename)
+                .map_err(|e| format!("failed to read file {}: {}", data_filename, e))?;
+            let data_cell = deserialize_cells_tree(&mut BufReader::new(file))
+                .map_err(|e| format!("failed to deserialize data file {}: {}", data_filename, e))?
+                .into_iter().next()
+                .ok_or(format!("data file {} has no root cell", data_filename))?;
+            state_init.set_data(data_cell);
+        }
+        Ok(state_init)
+    }
+
     fn apply_constructor(
         &mut self,
         state_init: StateInit,
         abi_file: &str,
         ctor_params : &str,
-        trace: bool
+        trace_level: TraceLevel
     ) -> std::result::Result<StateInit, String> {
-        use testcall::{call_contract_ex, MsgInfo};
+        use testcall::{call_contract_ex, ExecutionOptions, MsgInfo};
         use abi;
 
         let action_decoder = |_b,_i| {};
@@ -171,6 +234,8 @@ impl Program {
                 balance: None,
                 src: None,
                 now: get_now(),
+                lt: None,
+                rand_seed: None,
                 bounced: false,
                 body: Some(body),
             },
@@ -178,8 +243,10 @@ impl Program {
             None, // key_file,
             None, // ticktock,
             None, // gas_limit,
+            None, // gas_credit,
             Some(action_decoder),
-            if trace { TraceLevel::Full } else { TraceLevel::None }
+            trace_level,
+            ExecutionOptions::default(),
         )?;
 
         if is_vm_success {
@@ -192,10 +259,15 @@ impl Program {
         }
     }
 
-    fn compile_to_state(&mut self) -> std::result::Result<StateInit, String> {
+    pub fn compile_to_state(&mut self) -> std::result::Result<StateInit, String> {
         let mut state = StateInit::default();
         state.set_code(self.compile_asm(false)?);
         state.set_data(self.data()?);
+        if let Some(depth) = self.split_depth {
+            state.split_depth = Some(Number5::new(depth as u32)
+                .map_err(|e| format!("invalid split depth {}: {}", depth, e))?);
+        }
+        state.special = self.special.clone();
         Ok(state)
     }
 
@@ -243,13 +315,13 @@ impl Program {
         let mut internal_selector = compile_code_debuggable(internal_selector_text)
             .map_err(|_| "unexpected TVM error while compiling internal selector".to_string())?;
 
-        let mut dict = prepare_methods(&self.engine.privates(), false)
+        let mut dict = prepare_methods(&self.engine.privates(), false, self.share_bodies)
             .map_err(|e| e.1.replace("_name_", &self.engine.global_name(e.0).unwrap()))?;
 
-        insert_methods(&mut dict.0, &mut dict.1, &self.engine.internals(), false)
+        insert_methods(&mut dict.0, &mut dict.1, &self.engine.internals(), false, self.share_bodies)
             .map_err(|e| e.1.replace("_name_", &self.engine.internal_name(e.0).unwrap()) )?;
 
-        insert_methods(&mut dict.0, &mut dict.1, &self.publics_filtered(remove_ctor), false)
+        insert_methods(&mut dict.0, &mut dict.1, &self.publics_filtered(remove_ctor), false, self.share_bodies)
             .map_err(|e| e.1.replace("_name_", &self.engine.global_name(e.0).unwrap()) )?;
 
         let mut entry_points = vec![];
@@ -343,7 +415,18 @@ impl Program {
     }
 }
 
-pub fn save_to_file(state: StateInit, name: Option<&str>, wc: i8) -> std::result::Result<String, String> {
+/// Prints values as `KEY=value` lines which can be evaluated by a shell.
+pub fn print_env(values: &[(&str, String)]) {
+    for (key, value) in values {
+        if value.chars().all(|c| c.is_ascii_alphanumeric() || "_:./-".contains(c)) {
+            println!("{}={}", key, value);
+        } else {
+            println!("{}='{}'", key, value.replace("'", "'\\''"));
+        }
+    }
+}
+
+pub fn save_to_bytes(state: &StateInit) -> std::result::Result<Vec<u8>, String> {
     let root_cell = state.write_to_new_cell()
         .map_err(|e| format!("Serialization failed: {}", e))?
         .into_cell()
@@ -351,17 +434,29 @@ pub fn save_to_file(state: StateInit, name: Option<&str>, wc: i8) -> std::result
     let mut buffer = vec![];
     BagOfCells::with_root(&root_cell).write_to(&mut buffer, false)
         .map_err(|e| format!("BOC failed: {}", e))?;
+    Ok(buffer)
+}
+
+pub fn save_to_file(state: StateInit, name: Option<&str>, wc: i8) -> std::result::Result<String, String> {
+    save_to_file_ex(state, name, wc, true)
+}
+
+/// Saves the state to `name` or to `<address>.tvc`, the addresses are printed
+/// in the latter case if `verbose` is set.
+pub fn save_to_file_ex(state: StateInit, name: Option<&str>, wc: i8, verbose: bool) -> std::result::Result<String, String> {
+    let buffer = save_to_bytes(&state)?;
 
     let mut print_filename = false;
-    let address = state.hash().unwrap();
+    let address = state.hash().map_err(|e| format!("Failed to calculate hash: {}", e))?;
     let file_name = if name.is_some() {
         format!("{}", name.unwrap())
     } else {
-        print_filename = true;
+        print_filename = verbose;
         format!("{:x}.tvc", address)
     };
 
-    let mut file = std::fs::File::create(&file_name).unwrap();
+    let mut file = std::fs::File::create(&file_name)
+        .map_err(|e| format!("Failed to create file {}: {}", file_name, e))?;
     file.write_all(&buffer).map_err(|e| format!("Write to file failed: {}", e))?;
 
     if print_filename {
@@ -387,13 +482,22 @@ fn calc_userfriendly_address(wc: i8, addr: &[u8], bounce: bool, testnet: bool) -
 }
 
 pub fn load_from_file(contract_file: &str) -> Result<StateInit, String> {
-    let mut csor = Cursor::new(
-        std::fs::read(contract_file)
-            .map_err(|e| format!("failed to read file {}: {}", contract_file, e))?
-    );
-    let mut cell = deserialize_cells_tree(&mut csor)
+    let file = File::open(contract_file)
+        .map_err(|e| format!("failed to read file {}: {}", contract_file, e))?;
+    load_from_reader(&mut BufReader::new(file))
+}
+
+pub fn load_from_bytes(bytes: Vec<u8>) -> Result<StateInit, String> {
+    load_from_reader(&mut Cursor::new(bytes))
+}
+
+/// Cells are deserialized while reading without a separate copy of the file bytes,
+/// the cell tree itself is still built as a whole.
+fn load_from_reader<R: Read>(reader: &mut R) -> Result<StateInit, String> {
+    let mut cell = deserialize_cells_tree(reader)
         .map_err(|e| format!("failed to deserialize cell tree: {}", e))?
-        .remove(0);
+        .into_iter().next()
+        .ok_or("bag of cells has no roots".to_string())?;
     // try appending a dummy library cell if there is no such cell in the tvc file
     if cell.references_count() == 2 {
         let mut adjusted_cell = BuilderData::from(cell);
@@ -428,7 +532,7 @@ mod tests {
     use crate::{printer::get_version_mycode_aware, real_ton::load_stateinit};
 
     use super::*;
-    use testcall::{perform_contract_call, call_contract, MsgInfo};
+    use testcall::{perform_contract_call, call_contract, CallOptions, MsgInfo};
 
     #[ignore] // due to offline constructor
     #[test]
@@ -507,6 +611,22 @@ mod tests {
         assert_eq!(perform_contract_call(name, None, None, TraceLevel::None, false, None, Some(-1), None, None, 0, |_b,_i| {}), 0);
     }
 
+    #[test]
+    fn test_special_state() {
+        let sources = vec![Path::new("./tests/test_stdlib_sol.tvm"),
+                                     Path::new("./tests/ticktock.code")];
+        let mut prog = Program::new(ParseEngine::new(sources, None, false).unwrap());
+        let plain = prog.compile_to_state().unwrap();
+        prog.set_split_depth(Some(4));
+        prog.set_ticktock(true, false);
+        let state = prog.compile_to_state().unwrap();
+        assert_eq!(state.split_depth.map(|d| d.0), Some(4));
+        assert_eq!(state.special, Some(TickTock { tick: true, tock: false }));
+        assert_ne!(state.hash().unwrap(), plain.hash().unwrap());
+        prog.set_split_depth(Some(32));
+        assert!(prog.compile_to_state().is_err());
+    }
+
     #[ignore] // due to offline constructor
     #[test]
     fn test_recursive_call() {
@@ -589,6 +709,8 @@ mod tests {
                 balance: Some("1000000000"), // msg balance = 1T
                 src: None,
                 now: 1,
+                lt: None,
+                rand_seed: None,
                 bounced: false,
                 body: Some(body.into_cell().unwrap().into())
             },
@@ -596,9 +718,11 @@ mod tests {
             None,
             None,
             Some(3000), // gas limit
+            None, // gas credit
             Some(|_, _| {}),
             TraceLevel::None,
             String::from(""),
+            CallOptions::default(),
         );
         // must equal to out of gas exception
         assert!(exit_code.is_ok());
@@ -632,6 +756,8 @@ mod tests {
                 balance: Some("1000000000"), // msg balance = 1T
                 src: None,
                 now: 1,
+                lt: None,
+                rand_seed: None,
                 bounced: false,
                 body: Some(body.into_cell().unwrap().into())
             },
@@ -639,9 +765,11 @@ mod tests {
             None,
             None,
             None,
+            None, // gas credit
             Some(|_, _| {}),
             TraceLevel::Full,
             debug_map_filename,
+            CallOptions::default(),
         );
         assert!(exit_code.is_ok());
         assert_eq!(exit_code.unwrap(), 0);
@@ -692,6 +820,8 @@ mod tests {
                 balance: Some("1000000000"), // msg balance = 1T
                 src: None,
                 now: 1,
+                lt: None,
+                rand_seed: None,
                 bounced: false,
                 body: Some(body.into_cell().unwrap().into())
             },
@@ -699,9 +829,11 @@ mod tests {
             None,
             None,
             None,
+            None, // gas credit
             Some(|_, _| {}),
             TraceLevel::None,
             String::new(),
+            CallOptions::default(),
         );
         assert!(exit_code.is_ok());
         assert_eq!(exit_code.unwrap(), 0);
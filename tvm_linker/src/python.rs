@@ -0,0 +1,144 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+//! Python module built with `--features python`. It gives test frameworks direct
+//! access to the parser, tvc files and ABI encoding without running the binary.
+
+use abi::{build_abi_body, decode_body};
+use keyman::KeypairManager;
+use parser::{ParseEngine, ParseEngineResults};
+use program::{load_from_file, save_to_bytes, Program};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use pyo3::wrap_pyfunction;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+use ton_types::cells_serialization::{deserialize_cells_tree, serialize_tree_of_cells};
+
+fn to_py_err(e: String) -> PyErr {
+    PyRuntimeError::new_err(e)
+}
+
+fn parse(sources: &Vec<String>, abi_file: Option<&str>) -> Result<ParseEngine, String> {
+    let abi_json = abi_file.map(|file| std::fs::read_to_string(file)
+        .map_err(|e| format!("unable to read ABI file {}: {}", file, e))
+    ).transpose()?;
+    ParseEngine::new(sources.iter().map(|s| Path::new(s)).collect(), abi_json, false)
+}
+
+/// Parsed assembler sources: `ParseEngine(sources, abi_file=None)`.
+#[pyclass(name = "ParseEngine", unsendable)]
+pub struct PyParseEngine {
+    results: ParseEngineResults,
+}
+
+#[pymethods]
+impl PyParseEngine {
+    #[new]
+    #[args(abi_file = "None")]
+    fn new(sources: Vec<String>, abi_file: Option<String>) -> PyResult<Self> {
+        let engine = parse(&sources, abi_file.as_deref()).map_err(to_py_err)?;
+        Ok(PyParseEngine { results: ParseEngineResults::new(engine) })
+    }
+
+    /// Returns ids of public functions by their names.
+    fn publics(&self) -> HashMap<String, u32> {
+        self.results.publics().keys()
+            .filter_map(|id| self.results.global_name(*id).map(|name| (name, *id)))
+            .collect()
+    }
+
+    /// Returns ids of internal functions by their names.
+    fn internals(&self) -> HashMap<String, i32> {
+        self.results.internals().keys()
+            .filter_map(|id| self.results.internal_name(*id).map(|name| (name, *id)))
+            .collect()
+    }
+
+    fn version(&self) -> Option<String> {
+        self.results.version()
+    }
+}
+
+/// Compiles sources into a contract and returns contents of its tvc file.
+#[pyfunction(abi_file = "None")]
+fn compile(py: Python, sources: Vec<String>, abi_file: Option<String>) -> PyResult<PyObject> {
+    let engine = parse(&sources, abi_file.as_deref()).map_err(to_py_err)?;
+    let state = Program::new(engine).compile_to_state().map_err(to_py_err)?;
+    let bytes = save_to_bytes(&state).map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &bytes).into())
+}
+
+/// Returns address, code hash and data hash of a contract stored in a tvc file.
+#[pyfunction]
+fn tvc_info(path: &str) -> PyResult<HashMap<String, String>> {
+    let state = load_from_file(path).map_err(to_py_err)?;
+    let mut info = HashMap::new();
+    let address = state.hash().map_err(|e| to_py_err(format!("Failed to calculate hash: {}", e)))?;
+    info.insert("address".to_owned(), format!("{:x}", address));
+    if let Some(code) = state.code {
+        info.insert("code_hash".to_owned(), code.repr_hash().to_hex_string());
+    }
+    if let Some(data) = state.data {
+        info.insert("data_hash".to_owned(), data.repr_hash().to_hex_string());
+    }
+    Ok(info)
+}
+
+/// Encodes a call of the ABI function and returns the message body as a bag of cells.
+#[pyfunction(header = "None", sign = "None", internal = "false")]
+fn encode_call(
+    py: Python,
+    abi_file: &str,
+    method: &str,
+    params: &str,
+    header: Option<&str>,
+    sign: Option<&str>,
+    internal: bool,
+) -> PyResult<PyObject> {
+    let keypair = match sign {
+        Some(path) => Some(KeypairManager::from_secret_file(path)
+            .ok_or(to_py_err(format!("Failed to read keypair from {}", path)))?.drain()),
+        None => None,
+    };
+    let body = build_abi_body(abi_file, method, params, header, keypair, internal)
+        .map_err(to_py_err)?
+        .into_cell()
+        .map_err(|e| to_py_err(format!("failed to pack body in cell: {}", e)))?;
+    let mut bytes = vec![];
+    serialize_tree_of_cells(&body, &mut bytes)
+        .map_err(|e| to_py_err(format!("Failed to serialize body: {}", e)))?;
+    Ok(PyBytes::new(py, &bytes).into())
+}
+
+/// Decodes a body of an answer message given as a bag of cells and returns output parameters in json.
+#[pyfunction(internal = "false")]
+fn decode_response(abi_file: &str, method: &str, body: &[u8], internal: bool) -> PyResult<String> {
+    let mut roots = deserialize_cells_tree(&mut Cursor::new(body))
+        .map_err(|e| to_py_err(format!("Failed to deserialize body: {}", e)))?;
+    if roots.is_empty() {
+        return Err(to_py_err("bag of cells has no roots".to_owned()))
+    }
+    decode_body(abi_file, method, roots.remove(0).into(), internal).map_err(to_py_err)
+}
+
+#[pymodule]
+fn tvm_linker_lib(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyParseEngine>()?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(tvc_info, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_call, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_response, m)?)?;
+    Ok(())
+}
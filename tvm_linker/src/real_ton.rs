@@ -11,6 +11,10 @@
  * limitations under the License.
  */
 use crate::printer::*;
+use abi::{build_abi_body, find_key_method, load_abi_contract, load_abi_json_string};
+use clap::ArgMatches;
+use keyman::KeypairManager;
+use abi_json::token::Detokenizer;
 use program::load_from_file;
 use std::str::FromStr;
 use std::io::Cursor;
@@ -21,7 +25,7 @@ extern crate hex;
 use ton_block::*;
 use ton_types::types::AccountId;
 use ton_types::cells_serialization::{BocSerialiseMode, BagOfCells, deserialize_cells_tree_ex};
-use ton_types::{SliceData, BuilderData};
+use ton_types::{SliceData, BuilderData, HashmapE, HashmapType};
 
 pub fn load_stateinit(file_name: &str) -> Result<(SliceData, Vec<u8>), String> {
     let mut orig_bytes = Vec::new();
@@ -30,7 +34,7 @@ pub fn load_stateinit(file_name: &str) -> Result<(SliceData, Vec<u8>), String> {
     f.read_to_end(&mut orig_bytes)
         .map_err(|e| format!("Failed to read file data: {}", e))?;
 
-    let mut cur = Cursor::new(orig_bytes.clone());
+    let mut cur = Cursor::new(&orig_bytes);
     let (root_cells, _mode, _x, _y) = deserialize_cells_tree_ex(&mut cur)
         .map_err(|e| format!("Failed to deserialize BOC: {}", e))?;
     let mut root = root_cells[0].clone();
@@ -57,6 +61,42 @@ pub fn decode_boc(filename: &str, is_tvc: bool) -> Result<(), String> {
     Ok(())
 }
 
+/// Decodes persistent data of a contract (a tvc or an account boc) as public variables
+/// listed in the `data` section of its ABI. The public key is read from key 0 of the data dictionary.
+pub fn decode_account_data(filename: &str, is_tvc: bool, abi_file: &str) -> Result<(), String> {
+    let (mut root_slice, _) = load_stateinit(filename)?;
+    let data = if is_tvc {
+        StateInit::construct_from(&mut root_slice)
+            .map_err(|e| format!("Failed to read state_init from the slice: {}", e))?
+            .data
+    } else {
+        Account::construct_from(&mut root_slice)
+            .map_err(|e| format!("Failed to read account from the slice: {}", e))?
+            .get_data()
+    }.ok_or("contract has no persistent data".to_string())?;
+
+    let contract = load_abi_contract(&load_abi_json_string(abi_file)?)?;
+    let slice = SliceData::from(data);
+    let dict = HashmapE::with_hashmap(64, slice.reference_opt(0));
+    let key = 0u64.write_to_new_cell().unwrap().into();
+    match dict.get(key).map_err(|e| format!("Failed to read data dictionary: {}", e))? {
+        Some(mut value) if value.remaining_bits() >= 256 => {
+            let pubkey = value.get_next_bytes(32).unwrap();
+            println!("public key: {}", hex::encode(pubkey));
+        }
+        _ => println!("public key: none"),
+    }
+    let tokens = contract.decode_data(slice)
+        .map_err(|e| format!("Failed to decode data by ABI: {}", e))?;
+    let values = Detokenizer::detokenize(&tokens)
+        .map_err(|e| format!("Failed to convert data to json: {}", e))?;
+    let values: serde_json::Value = serde_json::from_str(&values)
+        .map_err(|e| format!("Failed to parse decoded data: {}", e))?;
+    println!("variables:");
+    println!("{}", serde_json::to_string_pretty(&values).unwrap());
+    Ok(())
+}
+
 pub fn compile_message(
     address_str: &str, 
     wc: Option<&str>, 
@@ -99,3 +139,36 @@ pub fn compile_message(
     println!("boc file created: {}", output_file_name);
     Ok(())
 }
+
+/// Builds a message calling the key changing method with the new public key signed with the
+/// current key. A generated keypair is saved next to the current one as `<file>.new` only after
+/// the message is built, the current key is kept until the message is delivered.
+pub fn change_key_command(m: &ArgMatches) -> std::result::Result<(), String> {
+    let abi_file = m.value_of("ABI_JSON").unwrap();
+    let (method, param) = find_key_method(abi_file, m.value_of("ABI_METHOD"))?;
+    let key_file = m.value_of("SIGN").unwrap();
+    let current = KeypairManager::from_secret_file(key_file)
+        .ok_or("Failed to read keypair.")?;
+    let (new_pair, new_file) = match m.value_of("NEW_KEY") {
+        Some(file) => (KeypairManager::from_secret_file(file).ok_or("Failed to read new keypair.")?, None),
+        None => (KeypairManager::new(), Some(format!("{}.new", key_file))),
+    };
+    let params = format!("{{\"{}\": \"0x{}\"}}", param, new_pair.public_key());
+    let body: SliceData = build_abi_body(
+        abi_file,
+        &method,
+        &params,
+        m.value_of("ABI_HEADER"),
+        Some(current.drain()),
+        false,
+    )?.into_cell()
+    .map_err(|e| format!("failed to pack body in cell: {}", e))?
+    .into();
+    compile_message(m.value_of("INPUT").unwrap(), m.value_of("WORKCHAIN"), Some(body), false, "-msg-change-key.boc")?;
+
+    if let Some(file) = new_file {
+        new_pair.store_pair(&file)?;
+        println!("new keypair saved to {}, replace {} with it after the message is delivered", file, key_file);
+    }
+    Ok(())
+}
@@ -0,0 +1,516 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use abi::{build_abi_body, decode_body, load_abi_contract, load_abi_json_string};
+use keyman::KeypairManager;
+use program::{load_from_file, get_now};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+use testcall::{apply_transaction, call_contract_ex, ExecutionOptions, MsgInfo, TraceLevel, DEFAULT_ACCOUNT_BALANCE};
+use ton_block::{Message, MsgAddressInt, StateInit};
+use ton_types::{BuilderData, SliceData};
+
+/// Test scenario: a set of contracts deployed into the in-memory state
+/// and a sequence of messages sent to them.
+#[derive(Deserialize)]
+struct Scenario {
+    contracts: HashMap<String, ContractSpec>,
+    steps: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+struct ContractSpec {
+    /// path to the contract tvc file
+    tvc: String,
+    /// path to the contract ABI file
+    abi: Option<String>,
+    /// contract address, `<wc>:<hex>` or `<hex>`. If omitted, the hash of the tvc is used.
+    address: Option<String>,
+    /// account balance, a number or `{ "main": int, "extra": {...} }`
+    balance: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Step {
+    contract: String,
+    name: Option<String>,
+    method: Option<String>,
+    params: Option<Value>,
+    header: Option<Value>,
+    /// raw body (a bitstring like x09c_ or a hex string), used if method is not set
+    body: Option<String>,
+    /// value of the inbound internal message; external message is sent if omitted
+    internal: Option<Value>,
+    src: Option<String>,
+    sign: Option<String>,
+    now: Option<u32>,
+    lt: Option<u64>,
+    rand_seed: Option<String>,
+    /// execute outbound internal messages against the scenario contracts
+    #[serde(default)]
+    chain: bool,
+    #[serde(default)]
+    expect: Expect,
+}
+
+/// Assertions evaluated after a step.
+#[derive(Deserialize, Default)]
+struct Expect {
+    exit_code: Option<i32>,
+    events: Option<Vec<String>>,
+    data_hash: Option<String>,
+    /// getters called on the contract state after the step with their expected outputs
+    getters: Option<Vec<GetterExpect>>,
+    /// expected change of the account balance: value of inbound internal message
+    /// minus values of outbound internal messages, zero if the message bounced
+    balance_delta: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct GetterExpect {
+    method: String,
+    params: Option<Value>,
+    /// expected values of the getter outputs; outputs which are not listed are not checked
+    output: Map<String, Value>,
+}
+
+/// Limits the depth of the internal messages tree executed for a step.
+const MAX_CHAIN_DEPTH: usize = 16;
+
+#[derive(Clone)]
+struct Account {
+    address: MsgAddressInt,
+    state: StateInit,
+    abi: Option<String>,
+    balance: Option<String>,
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn load_account(name: &str, spec: &ContractSpec) -> Result<Account, String> {
+    let state = load_from_file(&spec.tvc)?;
+    let address = match &spec.address {
+        Some(addr) if addr.find(':').is_some() => addr.clone(),
+        Some(addr) => format!("0:{}", addr),
+        None => format!("0:{:x}", state.hash()
+            .map_err(|e| format!("failed to calculate address of {}: {}", name, e))?),
+    };
+    let address = MsgAddressInt::from_str(&address)
+        .map_err(|e| format!("invalid address of contract {}: {}", name, e))?;
+    Ok(Account {
+        address,
+        state,
+        abi: spec.abi.clone(),
+        balance: spec.balance.as_ref().map(value_to_string),
+    })
+}
+
+fn build_step_body(step: &Step, account: &Account) -> Result<Option<SliceData>, String> {
+    if let Some(method) = &step.method {
+        let abi_file = account.abi.as_ref()
+            .ok_or(format!("contract {} has no ABI to call method {}", step.contract, method))?;
+        let keypair = match &step.sign {
+            Some(path) => Some(KeypairManager::from_secret_file(path)
+                .ok_or("Failed to read keypair.")?.drain()),
+            None => None,
+        };
+        let params = step.params.as_ref().map(|p| p.to_string()).unwrap_or("{}".to_owned());
+        let header = step.header.as_ref().map(value_to_string);
+        let body = build_abi_body(
+            abi_file,
+            method,
+            &params,
+            header.as_ref().map(|h| h.as_str()),
+            keypair,
+            step.internal.is_some(),
+        )?.into_cell()
+        .map_err(|e| format!("failed to pack body in cell: {}", e))?;
+        return Ok(Some(body.into()))
+    }
+    match &step.body {
+        Some(body) => {
+            let (buf, bits) = if body.to_ascii_lowercase().starts_with('x') {
+                let slice = SliceData::from_string(&body[1..])
+                    .map_err(|_| format!("body {} is invalid literal slice", body))?;
+                (slice.get_bytestring(0), slice.remaining_bits())
+            } else {
+                let buf = hex::decode(body)
+                    .map_err(|_| format!("body {} is invalid hex string", body))?;
+                let bits = buf.len() * 8;
+                (buf, bits)
+            };
+            let body = BuilderData::with_raw(buf, bits)
+                .map_err(|e| format!("failed to pack body in cell: {}", e))?
+                .into_cell()
+                .map_err(|e| format!("failed to pack body in cell: {}", e))?;
+            Ok(Some(body.into()))
+        }
+        None => Ok(None)
+    }
+}
+
+fn decode_event_names(account: &Account, bodies: Vec<SliceData>) -> Result<Vec<String>, String> {
+    let abi = match &account.abi {
+        Some(abi_file) => load_abi_contract(&load_abi_json_string(abi_file)?)?,
+        None => return Ok(vec![]),
+    };
+    Ok(bodies.into_iter()
+        .filter_map(|body| abi.decode_output(body, false).ok())
+        .map(|decoded| decoded.function_name)
+        .collect())
+}
+
+fn balance_value(account: &Account) -> Option<i128> {
+    i128::from_str(account.balance.as_ref().map(|b| b.as_str()).unwrap_or(DEFAULT_ACCOUNT_BALANCE)).ok()
+}
+
+/// Runs the getter on a copy of the account and compares its outputs with expected ones.
+fn check_getter(account: &Account, getter: &GetterExpect, now: u32) -> Result<Option<String>, String> {
+    let abi_file = account.abi.as_ref()
+        .ok_or(format!("contract has no ABI to call getter {}", getter.method))?;
+    let params = getter.params.as_ref().map(|p| p.to_string()).unwrap_or("{}".to_owned());
+    let body = build_abi_body(abi_file, &getter.method, &params, None, None, false)?
+        .into_cell()
+        .map_err(|e| format!("failed to pack body in cell: {}", e))?;
+    let msg_info = MsgInfo {
+        balance: None,
+        src: None,
+        now,
+        lt: None,
+        rand_seed: None,
+        bounced: false,
+        body: Some(body.into()),
+    };
+    let (exit_code, out_bodies, _) = execute(&mut account.clone(), msg_info, None)?;
+    if exit_code != 0 {
+        return Ok(Some(format!("getter {} failed with exit code {}", getter.method, exit_code)))
+    }
+    let output = out_bodies.into_iter()
+        .filter_map(|body| decode_body(abi_file, &getter.method, body, false).ok())
+        .next()
+        .ok_or(format!("getter {} returned no output", getter.method))?;
+    let output: Map<String, Value> = serde_json::from_str(&output)
+        .map_err(|e| format!("failed to parse output of getter {}: {}", getter.method, e))?;
+    for (name, expected) in &getter.output {
+        let actual = output.get(name).map(value_to_string).unwrap_or_default();
+        if actual != value_to_string(expected) {
+            return Ok(Some(format!("getter {}: {} is {}, expected {}",
+                getter.method, name, actual, value_to_string(expected))))
+        }
+    }
+    Ok(None)
+}
+
+/// Evaluates assertions of the step, returns the number of assertions and failures.
+fn check_expectations(
+    expect: &Expect,
+    exit_code: i32,
+    events: &Vec<String>,
+    account: &Account,
+    balance_before: Option<i128>,
+    now: u32,
+) -> Result<(usize, Vec<String>), String> {
+    let state = &account.state;
+    let mut total = 0;
+    let mut failures = vec![];
+    total += expect.exit_code.iter().count()
+        + expect.events.as_ref().map(|e| e.len()).unwrap_or(0)
+        + expect.data_hash.iter().count()
+        + expect.getters.as_ref().map(|g| g.len()).unwrap_or(0)
+        + expect.balance_delta.iter().count();
+    if let Some(expected) = expect.exit_code {
+        if expected != exit_code {
+            failures.push(format!("exit code {}, expected {}", exit_code, expected));
+        }
+    }
+    if let Some(expected) = &expect.events {
+        for name in expected {
+            if !events.contains(name) {
+                failures.push(format!("event {} was not emitted", name));
+            }
+        }
+    }
+    if let Some(expected) = &expect.data_hash {
+        let hash = state.data.as_ref()
+            .map(|data| data.repr_hash().to_hex_string())
+            .unwrap_or_default();
+        if !hash.eq_ignore_ascii_case(expected) {
+            failures.push(format!("data hash {}, expected {}", hash, expected));
+        }
+    }
+    if let Some(expected) = expect.balance_delta {
+        match (balance_before, balance_value(account)) {
+            (Some(before), Some(after)) if after - before != expected as i128 => {
+                failures.push(format!("balance delta {}, expected {}", after - before, expected));
+            }
+            (Some(_), Some(_)) => {}
+            _ => failures.push("balance delta can be checked only for balances in nanograms".to_string()),
+        }
+    }
+    for getter in expect.getters.iter().flatten() {
+        if let Some(failure) = check_getter(account, getter, now)? {
+            failures.push(failure);
+        }
+    }
+    Ok((total, failures))
+}
+
+fn execute(
+    account: &mut Account,
+    msg_info: MsgInfo,
+    key_file: Option<Option<&str>>,
+) -> Result<(i32, Vec<SliceData>, Vec<Message>), String> {
+    let out_bodies = RefCell::new(vec![]);
+    let action_decoder = |body: SliceData, is_internal: bool| {
+        if !is_internal {
+            out_bodies.borrow_mut().push(body);
+        }
+    };
+
+    let inbound = msg_info.clone();
+    let mut out_messages = vec![];
+    let (exit_code, state, is_vm_success) = call_contract_ex(
+        account.address.clone(),
+        account.state.clone(),
+        None,
+        account.balance.as_ref().map(|b| b.as_str()),
+        msg_info,
+        None,
+        key_file,
+        None,
+        None,
+        None,
+        Some(action_decoder),
+        TraceLevel::None,
+        ExecutionOptions { out_messages: Some(&mut out_messages), ..ExecutionOptions::default() },
+    )?;
+    let balance = account.balance.as_ref().map(|b| b.as_str());
+    let sent = match apply_transaction(&account.address, balance, &inbound, is_vm_success, out_messages.clone())? {
+        Some((balance, sent)) => {
+            account.balance = Some(balance);
+            sent
+        }
+        // balances with extra currencies are not tracked, messages are sent as they are
+        None => out_messages.into_iter().map(|(_, msg)| msg).collect(),
+    };
+    if is_vm_success {
+        account.state = state;
+    }
+    Ok((exit_code, out_bodies.into_inner(), sent))
+}
+
+fn run_step(step: &Step, account: &mut Account) -> Result<(usize, Vec<String>, Vec<Message>), String> {
+    let body = build_step_body(step, account)?;
+    let key_file = if step.method.is_none() {
+        Some(step.sign.as_ref().map(|s| s.as_str()))
+    } else {
+        None
+    };
+    let internal = step.internal.as_ref().map(value_to_string);
+    let now = step.now.unwrap_or_else(get_now);
+    let balance_before = balance_value(account);
+    let msg_info = MsgInfo {
+        balance: internal.as_ref().map(|v| v.as_str()),
+        src: step.src.as_ref().map(|s| s.as_str()),
+        now,
+        lt: step.lt,
+        rand_seed: step.rand_seed.as_ref().map(|s| s.as_str()),
+        bounced: false,
+        body,
+    };
+
+    let (exit_code, out_bodies, out_messages) = execute(account, msg_info, key_file)?;
+    let events = decode_event_names(account, out_bodies)?;
+    let (total, failures) = check_expectations(&step.expect, exit_code, &events, account, balance_before, now)?;
+    Ok((total, failures, out_messages))
+}
+
+/// Recursively executes outbound internal messages against destination contracts
+/// of the scenario and prints the tree of messages with exit codes.
+fn run_chain(
+    accounts: &mut HashMap<String, Account>,
+    src: &MsgAddressInt,
+    messages: Vec<Message>,
+    now: u32,
+    depth: usize,
+) -> Result<(), String> {
+    let indent = "  ".repeat(depth);
+    for msg in messages.into_iter().filter(|msg| msg.is_internal()) {
+        let dst = msg.dst().ok_or("internal message without destination".to_string())?;
+        let value = msg.get_value().map(|v| v.grams.0.to_string()).unwrap_or("0".to_owned());
+        let name = accounts.iter()
+            .find(|(_, account)| account.address == dst)
+            .map(|(name, _)| name.clone());
+        let name = match name {
+            Some(name) => name,
+            None => {
+                println!("{}-> {} value {}: destination is not in the scenario, skipped", indent, dst, value);
+                continue
+            }
+        };
+        if depth > MAX_CHAIN_DEPTH {
+            return Err(format!("message chain is deeper than {} messages", MAX_CHAIN_DEPTH))
+        }
+        let src_str = src.to_string();
+        let bounced = msg.int_header().map(|header| header.bounced).unwrap_or(false);
+        let msg_info = MsgInfo {
+            balance: Some(value.as_str()),
+            src: Some(src_str.as_str()),
+            now,
+            lt: None,
+            rand_seed: None,
+            bounced,
+            body: msg.body(),
+        };
+        let account = accounts.get_mut(&name).unwrap();
+        let (exit_code, _, out_messages) = execute(account, msg_info, None)?;
+        println!("{}-> {} ({}) value {}{}: exit code {}",
+            indent, name, dst, value, if bounced { " bounced" } else { "" }, exit_code);
+        run_chain(accounts, &dst, out_messages, now, depth + 1)?;
+    }
+    Ok(())
+}
+
+pub fn run_scenario(filename: &str) -> Result<(), String> {
+    let text = std::fs::read_to_string(filename)
+        .map_err(|e| format!("failed to read scenario {}: {}", filename, e))?;
+    let scenario: Scenario = serde_json::from_str(&text)
+        .map_err(|e| format!("failed to parse scenario {}: {}", filename, e))?;
+
+    let mut accounts = HashMap::new();
+    for (name, spec) in &scenario.contracts {
+        accounts.insert(name.clone(), load_account(name, spec)?);
+    }
+
+    let mut failed = 0;
+    let mut assertions = 0;
+    let mut failed_assertions = 0;
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let title = step.name.clone().unwrap_or(format!("#{}", i + 1));
+        println!("STEP {} ({}) STARTED", title, step.contract);
+        let account = accounts.get_mut(&step.contract)
+            .ok_or(format!("step {}: unknown contract {}", title, step.contract))?;
+        let (total, failures, out_messages) = run_step(step, account)
+            .map_err(|e| format!("step {}: {}", title, e))?;
+        if step.chain {
+            let src = account.address.clone();
+            run_chain(&mut accounts, &src, out_messages, step.now.unwrap_or_else(get_now), 1)
+                .map_err(|e| format!("step {}: {}", title, e))?;
+        }
+        assertions += total;
+        failed_assertions += failures.len();
+        if failures.is_empty() {
+            println!("STEP {} PASSED", title);
+        } else {
+            failed += 1;
+            println!("STEP {} FAILED", title);
+            for failure in failures {
+                println!("  {}", failure);
+            }
+        }
+    }
+
+    println!("{} steps, {} passed, {} failed", scenario.steps.len(), scenario.steps.len() - failed, failed);
+    println!("{} assertions, {} failed", assertions, failed_assertions);
+    if failed > 0 {
+        return Err(format!("{} of {} steps failed", failed, scenario.steps.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::ParseEngine;
+    use program::Program;
+    use ton_block::Serializable;
+
+    /// Forwards the inbound value (mode 64) to the address at the start of the body
+    /// with the rest of the body, fails on an empty body and accepts bounced messages.
+    const RELAY: &str = "\t.internal-alias :main_internal, 0\n\t.internal :main_internal\n\
+        \tDROP\n\tDUP\n\tSEMPTY\n\tTHROWIF 100\n\
+        \tDUP\n\tPLDU 32\n\tPUSHINT 4294967295\n\tEQUAL\n\tIFRET\n\
+        \tLDMSGADDR\n\tSWAP\n\tNEWC\n\tPUSHINT 6\n\tSTUR 4\n\tPUSHINT 0\n\tSTUR 2\n\tSTSLICE\n\
+        \tPUSHINT 0\n\tSTUR 111\n\tSTSLICE\n\tENDC\n\tPUSHINT 64\n\tSENDRAWMSG\n";
+
+    fn relay(address: u8) -> Account {
+        let parser = ParseEngine::from_sources(vec![("relay.code", RELAY)], None, false).unwrap();
+        Account {
+            address: MsgAddressInt::with_standart(None, 0, [address; 32].into()).unwrap(),
+            state: Program::new(parser).compile_to_state().unwrap(),
+            abi: None,
+            balance: Some("1000".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_chain_with_bounce() {
+        let mut accounts = HashMap::new();
+        for (name, address) in &[("a", 0x11), ("b", 0x22), ("c", 0x33)] {
+            accounts.insert(name.to_string(), relay(*address));
+        }
+        // a forwards the value to b, b forwards it to c, c fails and bounces it back to b
+        let mut body = BuilderData::new();
+        accounts["b"].address.write_to(&mut body).unwrap();
+        accounts["c"].address.write_to(&mut body).unwrap();
+        let msg_info = MsgInfo {
+            balance: Some("100"),
+            src: None,
+            now: 1,
+            lt: None,
+            rand_seed: None,
+            bounced: false,
+            body: Some(body.into_cell().unwrap().into()),
+        };
+        let mut account = accounts["a"].clone();
+        let (exit_code, _, out_messages) = execute(&mut account, msg_info, None).unwrap();
+        assert_eq!(exit_code, 0);
+        assert_eq!(out_messages.len(), 1);
+        assert_eq!(out_messages[0].get_value().unwrap().grams.0.to_string(), "100");
+        let src = account.address.clone();
+        accounts.insert("a".to_string(), account);
+        run_chain(&mut accounts, &src, out_messages, 1, 1).unwrap();
+
+        let balance = |name: &str| accounts[name].balance.clone().unwrap();
+        assert_eq!(balance("a"), "1000");
+        assert_eq!(balance("b"), "1100");
+        assert_eq!(balance("c"), "1000");
+    }
+
+    #[test]
+    fn test_failed_step_bounces() {
+        let mut account = relay(0x11);
+        let src = "0:4444444444444444444444444444444444444444444444444444444444444444";
+        let msg_info = MsgInfo {
+            balance: Some("100"),
+            src: Some(src),
+            now: 1,
+            lt: None,
+            rand_seed: None,
+            bounced: false,
+            body: None,
+        };
+        let (exit_code, _, out_messages) = execute(&mut account, msg_info, None).unwrap();
+        assert_eq!(exit_code, 100);
+        assert_eq!(account.balance, Some("1000".to_string()));
+        assert_eq!(out_messages.len(), 1);
+        assert_eq!(out_messages[0].dst().unwrap().to_string(), src);
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use clap::ArgMatches;
+use disasm::disasm::disasm_ex;
+use program::load_from_file;
+use std::path::Path;
+use ton_block::{Account, Deserializable, Message, StateInit};
+use ton_labs_assembler::compile_code_to_cell;
+use ton_types::{Cell, SliceData};
+
+/// Extracts contract code from a tvc file or from a message or account boc.
+fn load_code(path: &Path) -> Result<Option<Cell>, String> {
+    let filename = path.to_str().ok_or("invalid file name")?;
+    if path.extension().map_or(false, |ext| ext == "tvc") {
+        return Ok(load_from_file(filename)?.code)
+    }
+    let root = ton_types::deserialize_tree_of_cells(&mut std::io::BufReader::new(
+        std::fs::File::open(path).map_err(|e| format!("failed to read file: {}", e))?
+    )).map_err(|e| format!("failed to deserialize cell tree: {}", e))?;
+    if let Ok(msg) = Message::construct_from_cell(root.clone()) {
+        return Ok(msg.state_init().and_then(|state| state.code.clone()))
+    }
+    if let Ok(account) = Account::construct_from_cell(root.clone()) {
+        return Ok(account.state_init().and_then(|state| state.code.clone()))
+    }
+    Ok(StateInit::construct_from_cell(root).ok().and_then(|state| state.code))
+}
+
+/// Disassembles the code, assembles the result back and checks that it disassembles
+/// to the same text. Returns whether the assembled code is bit-for-bit identical.
+fn check_code(code: Cell) -> Result<bool, String> {
+    let asm = disasm_ex(&mut SliceData::from(code.clone()))
+        .map_err(|e| format!("failed to disassemble: {}", e))?;
+    let reassembled = compile_code_to_cell(&asm)
+        .map_err(|e| format!("failed to assemble disassembled code: {}", e))?;
+    let asm2 = disasm_ex(&mut SliceData::from(reassembled.clone()))
+        .map_err(|e| format!("failed to disassemble assembled code: {}", e))?;
+    if asm != asm2 {
+        return Err("assembled code disassembles differently".to_owned())
+    }
+    Ok(code.repr_hash() == reassembled.repr_hash())
+}
+
+pub fn selfcheck_command(matches: &ArgMatches) -> Result<(), String> {
+    let dir = matches.value_of("DIR").unwrap();
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .map_err(|e| format!("failed to read directory {}: {}", dir, e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "tvc" || ext == "boc"))
+        .collect();
+    paths.sort();
+
+    let mut failed = 0;
+    for path in &paths {
+        let result = load_code(path).and_then(|code| match code {
+            Some(code) => check_code(code).map(|same| if same { "ok" } else { "ok (assembled to different cells)" }),
+            None => Ok("no code"),
+        });
+        match result {
+            Ok(status) => println!("{}: {}", path.display(), status),
+            Err(e) => {
+                failed += 1;
+                println!("{}: FAILED: {}", path.display(), e);
+            }
+        }
+    }
+    println!("{} files checked, {} failed", paths.len(), failed);
+    if failed > 0 {
+        return Err(format!("{} of {} files failed", failed, paths.len()));
+    }
+    Ok(())
+}
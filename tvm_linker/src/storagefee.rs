@@ -0,0 +1,125 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+
+use clap::ArgMatches;
+use std::collections::HashSet;
+use testcall::load_config_params;
+use ton_block::ConfigParams;
+use ton_types::{Cell, UInt256};
+
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// Storage prices in nanotokens per bit and per cell for 65536 seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prices {
+    pub bit_price: u64,
+    pub cell_price: u64,
+}
+
+/// Prices of config param 18 at the time of writing, used when no config is given.
+fn default_prices(masterchain: bool) -> Prices {
+    if masterchain {
+        Prices { bit_price: 1000, cell_price: 500000 }
+    } else {
+        Prices { bit_price: 1, cell_price: 500 }
+    }
+}
+
+/// Takes the latest storage prices from config param 18.
+fn prices_from_config(config: Cell, masterchain: bool) -> Result<Prices, String> {
+    let params = ConfigParams::with_address_and_params(UInt256::default(), Some(config));
+    let param18 = params.storage_prices()
+        .map_err(|e| format!("Failed to read config param 18: {}", e))?;
+    let count = param18.len()
+        .map_err(|e| format!("Failed to read config param 18: {}", e))?;
+    if count == 0 {
+        return Err("config param 18 has no storage prices".to_string())
+    }
+    let prices = param18.get(count as u32 - 1)
+        .map_err(|e| format!("Failed to read config param 18: {}", e))?;
+    Ok(if masterchain {
+        Prices { bit_price: prices.mc_bit_price_ps, cell_price: prices.mc_cell_price_ps }
+    } else {
+        Prices { bit_price: prices.bit_price_ps, cell_price: prices.cell_price_ps }
+    })
+}
+
+/// Counts unique cells of the tree and their bits, identical cells are stored once.
+pub fn storage_stats(root: &Cell) -> (u64, u64) {
+    fn walk(cell: &Cell, visited: &mut HashSet<UInt256>, cells: &mut u64, bits: &mut u64) {
+        if !visited.insert(cell.repr_hash()) {
+            return
+        }
+        *cells += 1;
+        *bits += cell.bit_length() as u64;
+        for i in 0..cell.references_count() {
+            walk(&cell.reference(i).unwrap(), visited, cells, bits);
+        }
+    }
+    let (mut cells, mut bits) = (0, 0);
+    walk(root, &mut HashSet::new(), &mut cells, &mut bits);
+    (cells, bits)
+}
+
+/// Storage fee in nanotokens for the period, rounded up as the storage phase does.
+pub fn storage_fee(cells: u64, bits: u64, prices: Prices, seconds: u64) -> u128 {
+    let per_period = (bits as u128 * prices.bit_price as u128 + cells as u128 * prices.cell_price as u128)
+        * seconds as u128;
+    (per_period + 0xffff) >> 16
+}
+
+pub fn storage_fee_command(m: &ArgMatches) -> Result<(), String> {
+    let filename = m.value_of("INPUT").unwrap();
+    let file = std::fs::File::open(filename)
+        .map_err(|e| format!("failed to read file {}: {}", filename, e))?;
+    let root = ton_types::deserialize_tree_of_cells(&mut std::io::BufReader::new(file))
+        .map_err(|e| format!("failed to deserialize cell tree: {}", e))?;
+    let masterchain = m.value_of("WORKCHAIN") == Some("-1");
+    let prices = match m.value_of("CONFIG") {
+        Some(config) => prices_from_config(load_config_params(config)?, masterchain)?,
+        None => default_prices(masterchain),
+    };
+    let seconds = match m.value_of("PERIOD") {
+        Some(period) => u64::from_str_radix(period, 10)
+            .map_err(|e| format!("invalid period: {}", e))?,
+        None => SECONDS_PER_YEAR,
+    };
+
+    let (cells, bits) = storage_stats(&root);
+    let fee = storage_fee(cells, bits, prices, seconds);
+    println!("cells: {}, bits: {}", cells, bits);
+    println!("prices per 65536 seconds: {} per bit, {} per cell", prices.bit_price, prices.cell_price);
+    println!("storage fee for {} seconds: {} nanotokens ({}.{:09} tokens)",
+        seconds, fee, fee / 1_000_000_000, fee % 1_000_000_000);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ton_types::BuilderData;
+
+    #[test]
+    fn test_storage_fee() {
+        let child = BuilderData::with_raw(vec![0xA0], 8).unwrap().into_cell().unwrap();
+        let mut root = BuilderData::with_raw(vec![0x71, 0xA0], 16).unwrap();
+        root.checked_append_reference(child.clone()).unwrap();
+        root.checked_append_reference(child).unwrap();
+        // the shared child is stored once
+        assert_eq!(storage_stats(&root.into_cell().unwrap()), (2, 24));
+
+        // (24 * 1 + 2 * 500) * 65536 seconds / 65536
+        assert_eq!(storage_fee(2, 24, default_prices(false), 65536), 1024);
+        assert_eq!(storage_fee(2, 24, default_prices(false), 1), 1);
+    }
+}
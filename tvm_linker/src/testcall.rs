@@ -11,29 +11,34 @@
  * limitations under the License.
  */
 
+use coverage::Coverage;
+use datadiff::print_data_diff;
+use profile::Profile;
 use ed25519::signature::Signer;
 use keyman::KeypairManager;
 use log::Level::Error;
 use crate::printer::msg_printer;
-use program::{load_from_file, save_to_file, get_now};
+use program::{load_from_file, save_to_file};
 use simplelog::{SimpleLogger, Config, LevelFilter};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fs::File;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use ton_vm::executor::{Engine, EngineTraceInfo, EngineTraceInfoType, gas::gas_state::Gas};
 use ton_vm::error::tvm_exception;
 use ton_vm::stack::{StackItem, Stack, savelist::SaveList, integer::IntegerData};
 use ton_vm::SmartContractInfo;
-use ton_types::{AccountId, BuilderData, Cell, SliceData};
+use ton_types::{AccountId, BuilderData, Cell, HashmapE, HashmapType, SliceData, UInt256};
+use ton_types::cells_serialization::serialize_tree_of_cells;
 use ton_block::{
-    CurrencyCollection, Deserializable, ExternalInboundMessageHeader, Grams,
-    InternalMessageHeader, Message, MsgAddressExt, MsgAddressInt, OutAction,
+    ConfigParamEnum, ConfigParams, CurrencyCollection, Deserializable, ExternalInboundMessageHeader,
+    GasLimitsPrices, Grams, InternalMessageHeader, Message, MsgAddressExt, MsgAddressInt, OutAction,
     OutActions, Serializable, StateInit, UnixTime32
 };
 use ton_labs_assembler::DbgInfo;
 
-const DEFAULT_ACCOUNT_BALANCE: &str = "100000000000";
+pub const DEFAULT_ACCOUNT_BALANCE: &str = "100000000000";
 
 #[allow(dead_code)]
 fn create_inbound_body(a: i32, b: i32, func_id: i32) -> Result<Cell, String> {
@@ -105,13 +110,28 @@ fn sign_body(body: &mut SliceData, key_file: Option<&str>) -> Result<(), String>
     Ok(())
 }
 
-fn initialize_registers(data: SliceData, code: Cell, myself: MsgAddressInt, now: u32, balance: (u64, CurrencyCollection), config: Option<Cell>) -> Result<SaveList, String> {
+fn initialize_registers(
+    data: SliceData,
+    code: Cell,
+    myself: MsgAddressInt,
+    msg_info: &MsgInfo,
+    balance: (u64, CurrencyCollection),
+    config: Option<Cell>,
+) -> Result<SaveList, String> {
     let mut ctrls = SaveList::new();
     let mut info = SmartContractInfo::with_myself(myself.serialize()
               .map_err(|e| format!("Failed to serialize address: {}", e))?.into());
     *info.balance_remaining_grams_mut() = balance.0 as u128;
     *info.balance_remaining_other_mut() = balance.1.other_as_hashmap().clone();
-    *info.unix_time_mut() = now;
+    *info.unix_time_mut() = msg_info.now;
+    if let Some(lt) = msg_info.lt {
+        *info.block_lt_mut() = lt;
+        *info.trans_lt_mut() = lt;
+    }
+    if let Some(seed) = msg_info.rand_seed {
+        *info.rand_seed_mut() = IntegerData::from_str_radix(seed.trim_start_matches("0x"), 16)
+            .map_err(|e| format!("Failed to parse random seed: {}", e))?;
+    }
     if let Some(cell) = config {
         info.set_config_params(cell);
     }
@@ -134,6 +154,16 @@ fn init_logger(debug: bool) -> Result<(), String>{
 }
 
 
+/// Sender of the inbound internal message, the zero address by default.
+fn internal_src(msg_info: &MsgInfo) -> Result<MsgAddressInt, String> {
+    match msg_info.src {
+        Some(s) => MsgAddressInt::from_str(s)
+            .map_err(|e| format!("Failed to convert address: {}", e)),
+        None => MsgAddressInt::with_standart(None, 0, [0u8; 32].into())
+            .map_err(|e| format!("Failed to convert address: {}", e)),
+    }
+}
+
 fn create_inbound_msg(
     selector: i32,
     msg_info: &MsgInfo,
@@ -143,19 +173,13 @@ fn create_inbound_msg(
         .map_err(|e| format!("Failed to decode balance: {}", e))?;
     Ok(match selector {
         0 => {
-            let src = match msg_info.src {
-                Some(s) => MsgAddressInt::from_str(s)
-                    .map_err(|e| format!("Failed to convert address: {}", e))?,
-                None => MsgAddressInt::with_standart(None, 0, [0u8; 32].into())
-                    .map_err(|e| format!("Failed to convert address: {}", e))?,
-            };
             Some(create_internal_msg(
-                src,
+                internal_src(msg_info)?,
                 MsgAddressInt::with_standart(None, 0, dst)
                     .map_err(|e| format!("Failed to convert address: {}", e))?,
                 value,
-                1,
-                get_now(),
+                msg_info.lt.unwrap_or(1),
+                msg_info.now,
                 msg_info.body.clone(),
                 msg_info.bounced,
             ))
@@ -183,6 +207,19 @@ fn create_inbound_msg(
     })
 }
 
+fn collect_out_messages(actions: StackItem, out_messages: &mut Vec<(u8, Message)>) -> Result<(), String> {
+    if let StackItem::Cell(cell) = actions {
+        let actions: OutActions = OutActions::construct_from(&mut cell.into())
+            .map_err(|e| format!("Failed to decode output actions: {}", e))?;
+        for act in actions {
+            if let OutAction::SendMsg{ mode, out_msg } = act {
+                out_messages.push((mode, out_msg));
+            }
+        }
+    }
+    Ok(())
+}
+
 fn decode_actions<F>(actions: StackItem, state: &mut StateInit, action_decoder: F) -> Result<(), String>
     where F: Fn(SliceData, bool) -> ()
 {
@@ -256,10 +293,15 @@ fn decode_balance(value: Option<&str>) -> Result<(u64, CurrencyCollection), Stri
     }
 }
 
+#[derive(Clone)]
 pub struct MsgInfo<'a> {
     pub balance: Option<&'a str>,
     pub src: Option<&'a str>,
     pub now: u32,
+    /// logical time of the transaction; the inbound message is created with the same lt
+    pub lt: Option<u64>,
+    /// random seed in hex, zero by default
+    pub rand_seed: Option<&'a str>,
     pub bounced: bool,
     pub body: Option<SliceData>,
 }
@@ -281,8 +323,182 @@ pub fn load_debug_info(
 #[derive(PartialEq)]
 pub enum TraceLevel {
     Full,
+    /// full trace with slices decoded as addresses and strings where possible
+    Decoded,
     Minimal,
-    None
+    /// trace in the format of the reference TVM `vm.log`
+    VmLog,
+    None,
+    /// no trace and no execution results printed
+    Silent,
+}
+
+/// Account state persisted between local calls: code and data cells in base64-encoded boc
+/// and account balance.
+#[derive(Serialize, Deserialize)]
+struct StateSnapshot {
+    code: Option<String>,
+    data: Option<String>,
+    balance: Option<String>,
+}
+
+fn cell_to_base64(cell: &Cell) -> Result<String, String> {
+    let mut bytes = vec![];
+    serialize_tree_of_cells(cell, &mut bytes)
+        .map_err(|e| format!("Failed to serialize cell: {}", e))?;
+    Ok(base64::encode(&bytes))
+}
+
+fn cell_from_base64(string: &str) -> Result<Cell, String> {
+    let bytes = base64::decode(string)
+        .map_err(|e| format!("Failed to decode base64: {}", e))?;
+    ton_types::deserialize_tree_of_cells(&mut std::io::Cursor::new(bytes))
+        .map_err(|e| format!("Failed to deserialize cell tree: {}", e))
+}
+
+impl StateSnapshot {
+    fn load(filename: &str) -> Result<Self, String> {
+        let file = File::open(filename)
+            .map_err(|e| format!("Failed to open state file {}: {}", filename, e))?;
+        serde_json::from_reader(file)
+            .map_err(|e| format!("Failed to read state file {}: {}", filename, e))
+    }
+
+    fn save(&self, filename: &str) -> Result<(), String> {
+        let file = File::create(filename)
+            .map_err(|e| format!("Failed to create file {}: {}", filename, e))?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|e| format!("Failed to write data to file: {}", e))
+    }
+
+    fn apply(&self, state: &mut StateInit) -> Result<(), String> {
+        if let Some(code) = &self.code {
+            state.code = Some(cell_from_base64(code)?);
+        }
+        if let Some(data) = &self.data {
+            state.data = Some(cell_from_base64(data)?);
+        }
+        Ok(())
+    }
+
+    fn with_state(state: &StateInit, balance: Option<String>) -> Result<Self, String> {
+        Ok(StateSnapshot {
+            code: state.code.as_ref().map(cell_to_base64).transpose()?,
+            data: state.data.as_ref().map(cell_to_base64).transpose()?,
+            balance,
+        })
+    }
+}
+
+/// Send mode flag: errors of the action are ignored.
+const SEND_IGNORE_ERRORS: u8 = 2;
+/// Send mode flag: the remaining value of the inbound message is added to the message value.
+const SEND_REMAINING_VALUE: u8 = 64;
+/// Send mode flag: the message carries all the remaining balance of the account.
+const SEND_ALL_BALANCE: u8 = 128;
+
+/// Body of a bounced message: 0xffffffff followed by the first 256 bits of the original body.
+fn bounce_body(body: Option<&SliceData>) -> Result<SliceData, String> {
+    let mut builder = BuilderData::new();
+    builder.append_u32(0xffffffff)
+        .map_err(|e| format!("Failed to write data: {}", e))?;
+    if let Some(body) = body {
+        let bits = std::cmp::min(body.remaining_bits(), 256);
+        builder.append_raw(&body.get_bytestring(0), bits)
+            .map_err(|e| format!("Failed to write data: {}", e))?;
+    }
+    Ok(builder.into_cell()
+        .map_err(|e| format!("Failed to convert builder to cell: {}", e))?.into())
+}
+
+/// Calculates the account balance after the transaction and the outbound messages
+/// with the values they actually carry. The value of the inbound internal message is
+/// credited first, then messages are sent in order: mode 128 carries all the remaining
+/// balance, mode 64 adds the value of the inbound message. If the balance is not enough
+/// for a message without mode 2, the action phase fails and no messages are sent.
+/// When the computing or action phase fails, a bounceable inbound message is returned
+/// to its sender with its value. Fees and reserves are not taken into account.
+/// Returns None if the balance or the inbound value has extra currencies.
+pub fn apply_transaction(
+    addr: &MsgAddressInt,
+    balance: Option<&str>,
+    msg_info: &MsgInfo,
+    is_vm_success: bool,
+    out_messages: Vec<(u8, Message)>,
+) -> Result<Option<(String, Vec<Message>)>, String> {
+    let balance = match u64::from_str(balance.unwrap_or(DEFAULT_ACCOUNT_BALANCE)) {
+        Ok(balance) => balance,
+        Err(_) => return Ok(None),
+    };
+    let msg_value = match msg_info.balance.map(u64::from_str) {
+        Some(Ok(value)) => value,
+        Some(Err(_)) => return Ok(None),
+        None => 0,
+    };
+    let credited = balance.checked_add(msg_value).ok_or("Account balance overflow".to_string())?;
+
+    let mut balance = credited as u128;
+    let mut sent = vec![];
+    let out_messages = if is_vm_success { out_messages } else { vec![] };
+    let mut success = is_vm_success;
+    for (mode, mut msg) in out_messages {
+        if !msg.is_internal() {
+            sent.push(msg);
+            continue
+        }
+        let value = if mode & SEND_ALL_BALANCE != 0 {
+            balance
+        } else {
+            let value = msg.get_value().map(|v| v.grams.0 as u128).unwrap_or(0);
+            if mode & SEND_REMAINING_VALUE != 0 { value + msg_value as u128 } else { value }
+        };
+        if value > balance {
+            if mode & SEND_IGNORE_ERRORS != 0 {
+                continue
+            }
+            success = false;
+            break
+        }
+        balance -= value;
+        if let Some(current) = msg.get_value_mut() {
+            current.grams = Grams(value.into());
+        }
+        sent.push(msg);
+    }
+    if success {
+        return Ok(Some((balance.to_string(), sent)))
+    }
+
+    if msg_info.balance.is_none() || msg_info.bounced {
+        return Ok(Some((credited.to_string(), vec![])))
+    }
+    let bounce = create_internal_msg(
+        addr.clone(),
+        internal_src(msg_info)?,
+        CurrencyCollection::with_grams(msg_value),
+        msg_info.lt.unwrap_or(1),
+        msg_info.now,
+        Some(bounce_body(msg_info.body.as_ref())?),
+        true,
+    );
+    Ok(Some(((credited - msg_value).to_string(), vec![bounce])))
+}
+
+/// Optional features of a local call with files they use.
+#[derive(Default)]
+pub struct CallOptions<'a> {
+    /// file accumulating execution coverage
+    pub coverage_file: Option<&'a str>,
+    /// file with the account state to use instead of the one of the tvc
+    pub load_state: Option<&'a str>,
+    /// file to save the account state to instead of updating the tvc
+    pub save_state: Option<&'a str>,
+    /// print changes of the contract data after a successful call
+    pub data_diff: bool,
+    /// ABI used to decode fields of the contract data
+    pub abi_file: Option<&'a str>,
+    /// file to save the gas profile to as folded stacks
+    pub flamegraph_file: Option<&'a str>,
 }
 
 pub fn call_contract<F>(
@@ -294,12 +510,15 @@ pub fn call_contract<F>(
     key_file: Option<Option<&str>>,
     ticktock: Option<i8>,
     gas_limit: Option<i64>,
+    gas_credit: Option<i64>,
     action_decoder: Option<F>,
     trace_level: TraceLevel,
     debug_map_filename: String,
+    options: CallOptions,
 ) -> Result<i32, String>
     where F: Fn(SliceData, bool)
 {
+    let CallOptions { coverage_file, load_state, save_state, data_diff, abi_file, flamegraph_file } = options;
     let wc = match msg_info.balance {
         Some(_) => 0,
         None => if ticktock.is_some() { -1 } else { 0 },
@@ -313,19 +532,51 @@ pub fn call_contract<F>(
     let addr = ton_block::MsgAddressInt::from_str(&addr)
         .map_err(|e| format!("failed to load address: {}", e))?;
 
-    let state_init = load_from_file(smc_file)?;
+    let mut state_init = load_from_file(smc_file)?;
+    let snapshot = load_state.map(|filename| StateSnapshot::load(filename)).transpose()?;
+    if let Some(snapshot) = &snapshot {
+        snapshot.apply(&mut state_init)?;
+    }
+    let smc_balance = match snapshot.as_ref().and_then(|s| s.balance.as_ref()) {
+        Some(balance) => Some(balance.as_str()),
+        None => smc_balance,
+    };
+    let inbound = msg_info.clone();
+    let data_before = state_init.data.clone().unwrap_or_default();
+    let profile = flamegraph_file
+        .map(|_| Arc::new(Mutex::new(Profile::new(load_debug_info(debug_map_filename.clone())))));
     let debug_info = load_debug_info(debug_map_filename);
-    let config_cell = config_file.map(|filename| {
-        let state = load_from_file(filename).unwrap_or_default();
-        let (_code, data) = load_code_and_data(&state);
-        // config dictionary is located in the first reference of the storage root cell
-        data.into_cell().reference(0)
-            .map_err(|e| format!("Failed to obtain dicitonary: {}", e))
-    }).transpose()?;
+    let config_cell = config_file.map(|filename| load_config_params(filename)).transpose()?;
+    let coverage = coverage_file.map(|filename| Coverage::load(filename))
+        .transpose()?
+        .map(|coverage| Arc::new(Mutex::new(coverage)));
+    let mut out_messages = vec![];
     let (exit_code, state_init, is_vm_success) = call_contract_ex(
-        addr, state_init, debug_info, smc_balance,
-        msg_info, config_cell, key_file, ticktock, gas_limit, action_decoder, trace_level)?;
-    if is_vm_success {
+        addr.clone(), state_init, debug_info, smc_balance,
+        msg_info, config_cell, key_file, ticktock, gas_limit, gas_credit, action_decoder, trace_level,
+        ExecutionOptions {
+            coverage: coverage.clone(),
+            out_messages: Some(&mut out_messages),
+            profile: profile.clone(),
+        })?;
+    if let (Some(filename), Some(profile)) = (flamegraph_file, profile) {
+        profile.lock().unwrap().save(filename)?;
+        println!("Folded stacks saved to {}", filename);
+    }
+    if is_vm_success && data_diff {
+        print_data_diff(&data_before, &state_init.data.clone().unwrap_or_default(), abi_file)?;
+    }
+    if let (Some(filename), Some(coverage)) = (coverage_file, coverage) {
+        coverage.lock().unwrap().save(filename)?;
+        println!("Coverage data saved to {}", filename);
+    }
+    if let Some(filename) = save_state {
+        let new_balance = apply_transaction(&addr, smc_balance, &inbound, is_vm_success, out_messages)?
+            .map(|(balance, _)| balance)
+            .unwrap_or(smc_balance.unwrap_or(DEFAULT_ACCOUNT_BALANCE).to_owned());
+        StateSnapshot::with_state(&state_init, Some(new_balance))?.save(filename)?;
+        println!("Account state saved to {}", filename);
+    } else if is_vm_success {
         save_to_file(state_init, Some(&smc_file), 0)
             .map_err(|e| format!("Failed to save file: {}", e))?;
         println!("Contract persistent data updated");
@@ -333,6 +584,70 @@ pub fn call_contract<F>(
     Ok(exit_code)
 }
 
+/// Loads blockchain configuration parameters dictionary from a file. The file can be
+/// a config contract boc, a boc with the parameters dictionary itself or a json object
+/// mapping parameter indexes to base64-encoded parameter cells: `{"20": "te6cck...", ...}`.
+pub fn load_config_params(filename: &str) -> Result<Cell, String> {
+    if filename.ends_with(".json") {
+        let text = std::fs::read_to_string(filename)
+            .map_err(|e| format!("failed to read file {}: {}", filename, e))?;
+        let params: serde_json::Map<String, Value> = serde_json::from_str(&text)
+            .map_err(|e| format!("failed to parse config params json: {}", e))?;
+        let mut dict = HashmapE::with_bit_len(32);
+        for (index, value) in params {
+            let index = u32::from_str(&index)
+                .map_err(|e| format!("invalid config param index {}: {}", index, e))?;
+            let bytes = value.as_str().and_then(|s| base64::decode(s).ok())
+                .ok_or(format!("config param {} must be a base64 encoded boc", index))?;
+            let cell = ton_types::deserialize_tree_of_cells(&mut std::io::Cursor::new(bytes))
+                .map_err(|e| format!("failed to deserialize config param {}: {}", index, e))?;
+            let key = index.serialize()
+                .map_err(|e| format!("Failed to serialize data: {}", e))?;
+            dict.setref(key.into(), &cell)
+                .map_err(|e| format!("failed to set config param {}: {}", index, e))?;
+        }
+        return dict.data().cloned().ok_or("config params json is empty".to_string())
+    }
+    let file = File::open(filename)
+        .map_err(|e| format!("failed to read file {}: {}", filename, e))?;
+    let root = ton_types::deserialize_tree_of_cells(&mut std::io::BufReader::new(file))
+        .map_err(|e| format!("failed to deserialize cell tree: {}", e))?;
+    match load_from_file(filename) {
+        // config dictionary is located in the first reference of the storage root cell
+        Ok(StateInit { code: Some(_), data: Some(data), .. }) => data.reference(0)
+            .map_err(|e| format!("Failed to obtain dicitonary: {}", e)),
+        _ => Ok(root),
+    }
+}
+
+/// Computes gas limits the same way as the compute phase does using gas prices
+/// from config params 20 (masterchain) or 21 (basechain).
+fn gas_from_config(
+    config: &Cell,
+    addr: &MsgAddressInt,
+    func_selector: i32,
+    balance: u64,
+    msg_value: u64,
+) -> Result<Option<Gas>, String> {
+    let params = ConfigParams::with_address_and_params(UInt256::default(), Some(config.clone()));
+    let index = if addr.workchain_id() == -1 { 20 } else { 21 };
+    let prices: GasLimitsPrices = match params.config(index)
+        .map_err(|e| format!("Failed to read config param {}: {}", index, e))? {
+        Some(ConfigParamEnum::ConfigParam20(prices)) => prices,
+        Some(ConfigParamEnum::ConfigParam21(prices)) => prices,
+        _ => return Ok(None),
+    };
+    // gas price in config is defined in nanograms per 65536 gas units
+    let gas_price = std::cmp::max(prices.gas_price >> 16, 1);
+    let gas_max = std::cmp::min(prices.gas_limit, balance / gas_price);
+    let (gas_limit, gas_credit) = match func_selector {
+        0 => (std::cmp::min(gas_max, msg_value / gas_price), 0),
+        -1 => (0, std::cmp::min(prices.gas_credit, gas_max)),
+        _ => (prices.special_gas_limit, 0),
+    };
+    Ok(Some(Gas::new(gas_limit as i64, gas_credit as i64, gas_max as i64, gas_price as i64)))
+}
+
 fn get_position(info: &EngineTraceInfo, debug_info: &Option<DbgInfo>) -> Option<String> {
     if let Some(debug_info) = debug_info {
         let cell_hash = info.cmd_code.cell().repr_hash();
@@ -358,7 +673,86 @@ fn trace_callback_minimal(_engine: &Engine, info: &EngineTraceInfo, debug_info:
     println!();
 }
 
-fn trace_callback(_engine: &Engine, info: &EngineTraceInfo, extended: bool, debug_info: &Option<DbgInfo>) {
+fn format_vmlog_item(item: &StackItem) -> String {
+    match item {
+        StackItem::None => "(null)".to_string(),
+        StackItem::Integer(value) => value.to_string(),
+        StackItem::Cell(cell) => format!("C{{{}}}", cell.repr_hash().to_hex_string().to_uppercase()),
+        StackItem::Slice(slice) => format!("CS{{Cell{{{}}} bits: {}..{}; refs: 0..{}}}",
+            hex::encode(slice.cell().data()), slice.pos(), slice.pos() + slice.remaining_bits(),
+            slice.remaining_references()),
+        StackItem::Builder(builder) => format!("BC{{{}}}", hex::encode(builder.data())),
+        StackItem::Tuple(items) => format!("[ {} ]",
+            items.iter().map(|item| format_vmlog_item(item)).collect::<Vec<_>>().join(" ")),
+        StackItem::Continuation(_) => "Cont{vmc_std}".to_string(),
+    }
+}
+
+/// Prints trace lines the same way as the reference TVM does in `vm.log`: stack before
+/// each instruction, code position, instruction and gas remaining after it.
+fn trace_callback_vmlog(engine: &Engine, info: &EngineTraceInfo) {
+    match info.info_type {
+        EngineTraceInfoType::Dump => return,
+        EngineTraceInfoType::Start => {}
+        EngineTraceInfoType::Exception => println!("handling exception code {}", info.cmd_str),
+        EngineTraceInfoType::Implicit => println!("{}", info.cmd_str),
+        _ => {
+            println!("code cell hash: {} offset: {}",
+                info.cmd_code.cell().repr_hash().to_hex_string().to_uppercase(), info.cmd_code.pos());
+            println!("execute {}", info.cmd_str);
+            println!("gas remaining: {}", engine.get_gas().get_gas_remaining());
+        }
+    }
+    println!("stack: [ {} ] ", info.stack.iter().map(|item| format_vmlog_item(item)).collect::<Vec<_>>().join(" "));
+}
+
+const SLICE_PREVIEW_LEN: usize = 64;
+
+fn decode_slice(slice: &SliceData) -> Option<String> {
+    if slice.remaining_bits() == 267 && slice.remaining_references() == 0 {
+        if let Ok(addr) = MsgAddressInt::construct_from(&mut slice.clone()) {
+            return Some(format!("address {}", addr))
+        }
+    }
+    if slice.remaining_bits() >= 16 && slice.remaining_bits() % 8 == 0 {
+        if let Ok(text) = String::from_utf8(slice.get_bytestring(0)) {
+            if text.chars().all(|c| !c.is_control()) {
+                return Some(format!("string {:?}", text))
+            }
+        }
+    }
+    None
+}
+
+/// Formats stack item with its type: integers, cells with hashes, slice previews,
+/// tuple structure and continuations.
+fn format_stack_item(item: &StackItem, decode: bool) -> String {
+    match item {
+        StackItem::None => "null".to_string(),
+        StackItem::Integer(value) => format!("int {}", value),
+        StackItem::Cell(cell) => format!("cell {} (bits: {}, refs: {})",
+            cell.repr_hash().to_hex_string(), cell.bit_length(), cell.references_count()),
+        StackItem::Slice(slice) => {
+            let mut preview = slice.to_hex_string();
+            if preview.len() > SLICE_PREVIEW_LEN {
+                preview.truncate(SLICE_PREVIEW_LEN);
+                preview.push_str("...");
+            }
+            let decoded = if decode { decode_slice(slice) } else { None };
+            format!("slice x{} (bits: {}, refs: {}){}", preview,
+                slice.remaining_bits(), slice.remaining_references(),
+                decoded.map(|d| format!(" {}", d)).unwrap_or_default())
+        }
+        StackItem::Builder(builder) => format!("builder (bits: {}, refs: {})",
+            builder.bits_used(), builder.references_used()),
+        StackItem::Tuple(items) => format!("tuple({}) [{}]", items.len(),
+            items.iter().map(|item| format_stack_item(item, decode)).collect::<Vec<_>>().join(", ")),
+        StackItem::Continuation(cont) => format!("continuation (code bits: {}, refs: {})",
+            cont.code().remaining_bits(), cont.code().remaining_references()),
+    }
+}
+
+fn trace_callback(_engine: &Engine, info: &EngineTraceInfo, extended: bool, decode: bool, debug_info: &Option<DbgInfo>) {
 
     if info.info_type == EngineTraceInfoType::Dump {
         println!("{}", info.cmd_str);
@@ -387,11 +781,22 @@ fn trace_callback(_engine: &Engine, info: &EngineTraceInfo, extended: bool, debu
 
     println!("\n--- Stack trace ------------------------");
     for item in info.stack.iter() {
-        println!("{}", item);
+        println!("{}", format_stack_item(item, decode));
     }
     println!("----------------------------------------\n");
 }
 
+/// Collectors filled during the execution.
+#[derive(Default)]
+pub struct ExecutionOptions<'a> {
+    /// coverage recording every executed instruction
+    pub coverage: Option<Arc<Mutex<Coverage>>>,
+    /// list receiving outbound messages of a successful execution with their send modes
+    pub out_messages: Option<&'a mut Vec<(u8, Message)>>,
+    /// gas profile recording every executed instruction
+    pub profile: Option<Arc<Mutex<Profile>>>,
+}
+
 pub fn call_contract_ex<F>(
     addr: MsgAddressInt,
     state_init: StateInit,
@@ -402,11 +807,14 @@ pub fn call_contract_ex<F>(
     key_file: Option<Option<&str>>,
     ticktock: Option<i8>,
     gas_limit: Option<i64>,
+    gas_credit: Option<i64>,
     action_decoder: Option<F>,
     trace_level: TraceLevel,
+    options: ExecutionOptions,
 ) -> Result<(i32, StateInit, bool), String>
     where F: Fn(SliceData, bool)
 {
+    let ExecutionOptions { coverage, out_messages, profile } = options;
     let func_selector = match msg_info.balance {
         Some(_) => 0,
         None => if ticktock.is_some() { -2 } else { -1 },
@@ -415,7 +823,7 @@ pub fn call_contract_ex<F>(
     let msg = create_inbound_msg(func_selector, &msg_info, addr.address().clone())?;
 
     if !log_enabled!(Error) {
-        init_logger(trace_level == TraceLevel::Full)?;
+        init_logger(trace_level == TraceLevel::Full || trace_level == TraceLevel::Decoded)?;
     }
 
     let mut state_init = state_init;
@@ -426,11 +834,17 @@ pub fn call_contract_ex<F>(
         data,
         code.clone().into_cell(),
         addr.clone(),
-        msg_info.now,
+        &msg_info,
         (smc_value.clone(), smc_balance),
-        config,
+        config.clone(),
     )?;
 
+    let msg_value = if func_selector == 0 {
+        decode_balance(msg_info.balance)?.0 // for internal message
+    } else {
+        0 // for external message
+    };
+
     let mut stack = Stack::new();
     if func_selector > -2 {
         let msg_cell = StackItem::Cell(msg.ok_or("Failed to create message".to_string())?
@@ -448,12 +862,6 @@ pub fn call_contract_ex<F>(
             }
         }
 
-        let msg_value = if func_selector == 0 {
-            decode_balance(msg_info.balance)?.0 // for internal message
-        } else {
-            0 // for external message
-        };
-
         stack
             .push(int!(smc_value))        // contract balance
             .push(int!(msg_value))        // msg value
@@ -471,25 +879,50 @@ pub fn call_contract_ex<F>(
             .push(int!(func_selector));
     }
 
-    let gas = if let Some(gas_limit) = gas_limit {
-        let mut tmp_gas = Gas::test();
-        tmp_gas.new_gas_limit(gas_limit);
-        tmp_gas
+    let config_gas = match &config {
+        Some(config) => gas_from_config(config, &addr, func_selector, smc_value, msg_value)?,
+        None => None,
+    };
+    let gas = config_gas.unwrap_or(Gas::test());
+    let gas = if gas_limit.is_some() || gas_credit.is_some() {
+        let limit = gas_limit.unwrap_or(gas.get_gas_limit());
+        Gas::new(
+            limit,
+            gas_credit.unwrap_or(0),
+            std::cmp::max(limit, gas.get_gas_limit_max()),
+            gas.get_gas_price(),
+        )
     } else {
-        Gas::test()
+        gas
     };
+    let (initial_limit, initial_credit) = (gas.get_gas_limit(), gas.get_gas_credit());
 
     let mut engine = Engine::new().setup_with_libraries(code, Some(registers), Some(stack), Some(gas), vec![]);
     engine.set_trace(0);
-    match trace_level {
-        TraceLevel::Full => engine.set_trace_callback(move |engine, info| { trace_callback(engine, info, true, &debug_info); }),
-        TraceLevel::Minimal => engine.set_trace_callback(move |engine, info| { trace_callback_minimal(engine, info, &debug_info); }),
-        TraceLevel::None => {}
+    let silent = trace_level == TraceLevel::Silent;
+    if (trace_level != TraceLevel::None && !silent) || coverage.is_some() || profile.is_some() {
+        engine.set_trace_callback(move |engine, info| {
+            if let Some(coverage) = &coverage {
+                coverage.lock().unwrap().record(info);
+            }
+            if let Some(profile) = &profile {
+                profile.lock().unwrap().record(info);
+            }
+            match trace_level {
+                TraceLevel::Full => trace_callback(engine, info, true, false, &debug_info),
+                TraceLevel::Decoded => trace_callback(engine, info, true, true, &debug_info),
+                TraceLevel::Minimal => trace_callback_minimal(engine, info, &debug_info),
+                TraceLevel::VmLog => trace_callback_vmlog(engine, info),
+                TraceLevel::None | TraceLevel::Silent => {}
+            }
+        });
     }
     let exit_code = match engine.execute() {
         Err(exc) => match tvm_exception(exc) {
             Ok(exc) => {
-                println!("Unhandled exception: {}", exc);
+                if !silent {
+                    println!("Unhandled exception: {}", exc);
+                }
                 exc.exception_or_custom_code()
             }
             _ => -1
@@ -498,17 +931,28 @@ pub fn call_contract_ex<F>(
     };
 
     let is_vm_success = engine.get_committed_state().is_committed();
-    println!("TVM terminated with exit code {}", exit_code);
-    println!("Computing phase is success: {}", is_vm_success);
-    println!("Gas used: {}", engine.get_gas().get_gas_used());
-    println!();
-    println!("{}", engine.dump_stack("Post-execution stack state", false));
-    println!("{}", engine.dump_ctrls(false));
+    if !silent {
+        println!("TVM terminated with exit code {}", exit_code);
+        println!("Computing phase is success: {}", is_vm_success);
+        println!("Gas used: {}", engine.get_gas().get_gas_used());
+        println!("Gas limit: {} (initial {}), credit: {} (initial {})",
+            engine.get_gas().get_gas_limit(), initial_limit,
+            engine.get_gas().get_gas_credit(), initial_credit);
+        if engine.get_gas().get_gas_credit() != 0 {
+            println!("Gas credit was not spent: the message is not accepted by the contract");
+        }
+        println!();
+        println!("{}", engine.dump_stack("Post-execution stack state", false));
+        println!("{}", engine.dump_ctrls(false));
+    }
 
     if is_vm_success {
         if let Some(decoder) = action_decoder {
             decode_actions(engine.get_actions(), &mut state_init, decoder)?;
         }
+        if let Some(out_messages) = out_messages {
+            collect_out_messages(engine.get_actions(), out_messages)?;
+        }
 
         state_init.data = match engine.get_committed_state().get_root() {
             StackItem::Cell(root_cell) => Some(root_cell),
@@ -544,6 +988,8 @@ pub fn perform_contract_call<F>(
             balance: msg_balance,
             src,
             now,
+            lt: None,
+            rand_seed: None,
             bounced: false,
             body
         },
@@ -551,9 +997,11 @@ pub fn perform_contract_call<F>(
         key_file,
         ticktock,
         None,
+        None,
         if decode_c5 { Some(action_decoder) } else { None },
         trace_level,
         String::from(""),
+        CallOptions::default(),
     ).unwrap_or(-1)
 }
 
@@ -585,6 +1033,67 @@ mod tests {
         println!("SendMsg action:\n{}", msg_printer(&msg).unwrap_or("Undefined".to_string()));
     }
 
+    fn send(mode: u8, value: u64) -> (u8, Message) {
+        (mode, create_internal_msg(
+            MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap(),
+            MsgAddressInt::with_standart(None, 0, [0x22; 32].into()).unwrap(),
+            CurrencyCollection::with_grams(value),
+            1,
+            2,
+            None,
+            false,
+        ))
+    }
+
+    fn inbound(value: Option<&str>) -> MsgInfo {
+        MsgInfo { balance: value, src: None, now: 0, lt: None, rand_seed: None, bounced: false, body: None }
+    }
+
+    fn values(messages: &Vec<Message>) -> Vec<String> {
+        messages.iter().map(|msg| msg.get_value().unwrap().grams.0.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_transaction() {
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+
+        // mode 64 adds the inbound value, mode 128 carries the rest of the balance
+        let (balance, sent) = apply_transaction(&addr, Some("1000"), &inbound(Some("100")), true,
+            vec![send(64, 10), send(0, 300), send(128, 0)]).unwrap().unwrap();
+        assert_eq!(balance, "0");
+        assert_eq!(values(&sent), vec!["110", "300", "690"]);
+
+        // a message which can not be paid is skipped with mode 2
+        let (balance, sent) = apply_transaction(&addr, Some("1000"), &inbound(Some("100")), true,
+            vec![send(2, 5000), send(0, 200)]).unwrap().unwrap();
+        assert_eq!(balance, "900");
+        assert_eq!(values(&sent), vec!["200"]);
+
+        // otherwise the action phase fails and the inbound value is bounced
+        let (balance, sent) = apply_transaction(&addr, Some("1000"), &inbound(Some("100")), true,
+            vec![send(0, 200), send(0, 5000)]).unwrap().unwrap();
+        assert_eq!(balance, "1000");
+        assert_eq!(values(&sent), vec!["100"]);
+        let header = sent[0].int_header().unwrap();
+        assert!(header.bounced && !header.bounce);
+        assert_eq!(sent[0].body().unwrap().get_next_u32().unwrap(), 0xffffffff);
+
+        // bounced and external messages are not bounced
+        let mut bounced = inbound(Some("100"));
+        bounced.bounced = true;
+        let (balance, sent) = apply_transaction(&addr, Some("1000"), &bounced, false, vec![]).unwrap().unwrap();
+        assert_eq!(balance, "1100");
+        assert!(sent.is_empty());
+        let (balance, sent) = apply_transaction(&addr, None, &inbound(None), false, vec![send(0, 1)]).unwrap().unwrap();
+        assert_eq!(balance, DEFAULT_ACCOUNT_BALANCE);
+        assert!(sent.is_empty());
+
+        // balances with extra currencies are not tracked
+        let extra = r#"{ "main": 100, "extra": {"0": 33} }"#;
+        assert!(apply_transaction(&addr, Some(extra), &inbound(Some("1")), true, vec![]).unwrap().is_none());
+        assert!(apply_transaction(&addr, Some(&u64::MAX.to_string()), &inbound(Some("1")), true, vec![]).is_err());
+    }
+
     #[test]
     fn test_decode_balance() {
         let (main, balance) = decode_balance(Some(r#"{ "main": 100, "extra": {"0": 33, "50": 99} }"#)).unwrap();
@@ -0,0 +1,45 @@
+/*
+ * Copyright 2018-2021 TON DEV SOLUTIONS LTD.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific TON DEV software governing permissions and
+ * limitations under the License.
+ */
+use disasm::disasm::disasm_ex;
+use parser::{ParseEngine, ParseEngineResults};
+use serde_json::{Map, Value};
+use ton_types::cells_serialization::deserialize_cells_tree;
+use wasm_bindgen::prelude::*;
+
+/// Disassembles a bag of cells given in base64 and returns code of its root cell.
+#[wasm_bindgen]
+pub fn disassemble(boc_base64: &str) -> Result<String, JsValue> {
+    let bytes = base64::decode(boc_base64)
+        .map_err(|e| JsValue::from_str(&format!("Failed to decode base64: {}", e)))?;
+    let mut roots = deserialize_cells_tree(&mut std::io::Cursor::new(bytes))
+        .map_err(|e| JsValue::from_str(&format!("Failed to deserialize cells: {}", e)))?;
+    if roots.len() != 1 {
+        return Err(JsValue::from_str(&format!("Expected one root cell, found {}", roots.len())))
+    }
+    disasm_ex(&mut roots.remove(0).into())
+        .map_err(|e| JsValue::from_str(&format!("Failed to disassemble code: {}", e)))
+}
+
+/// Parses assembler source and returns a json object with names and ids of public functions.
+#[wasm_bindgen]
+pub fn parse_functions(source: &str, abi_json: Option<String>) -> Result<String, JsValue> {
+    let parser = ParseEngine::from_sources(vec![("source.code", source)], abi_json, false)
+        .map_err(|e| JsValue::from_str(&e))?;
+    let results = ParseEngineResults::new(parser);
+    let mut functions = Map::new();
+    for id in results.publics().keys() {
+        let name = results.global_name(*id).unwrap_or(format!("0x{:08x}", id));
+        functions.insert(name, Value::from(*id));
+    }
+    Ok(Value::Object(functions).to_string())
+}
@@ -42,4 +42,58 @@ fn test_compile_lib() -> Result<(), Box<dyn std::error::Error>> {
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Temporary directory removed when the test ends, even if it fails.
+struct TempDir(std::path::PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> std::io::Result<TempDir> {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().subsec_nanos();
+        let dir = env::temp_dir().join(format!("tvm_linker_{}_{}_{}", name, std::process::id(), nanos));
+        std::fs::create_dir_all(&dir)?;
+        Ok(TempDir(dir))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn test_change_key() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new("change_key")?;
+    let dir = &dir.0;
+    let key = dir.join("key");
+    std::fs::copy("key1", &key)?;
+    let key = key.to_str().unwrap();
+    let abi = env::current_dir()?.join("tests/change_key.abi.json");
+    let address = "c4a9e5fe00000000000000000000000000000000000000000000000000000000";
+
+    // the message is saved into the current directory
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.current_dir(dir)
+        .arg("change_key")
+        .arg(address)
+        .arg("--abi-json")
+        .arg(&abi)
+        .arg("--setkey")
+        .arg(key)
+        .arg("-w")
+        .arg("0")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("new keypair saved to {}.new", key)));
+
+    // the current key is kept, the new keypair is complete
+    assert_eq!(std::fs::read(key)?, std::fs::read("key1")?);
+    let secret = std::fs::read(format!("{}.new", key))?;
+    let public = std::fs::read(format!("{}.new.pub", key))?;
+    assert_eq!(secret.len(), 64);
+    assert_eq!(&secret[32..], &public[..]);
+    assert!(!dir.join("key.new.tmp").exists());
+    assert!(dir.join("c4a9e5fe-msg-change-key.boc").exists());
+    Ok(())
+}